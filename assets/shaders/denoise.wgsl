@@ -0,0 +1,82 @@
+// Edge-avoiding à-trous wavelet filter, see "Edge-Avoiding À-Trous Wavelet Transform for fast Global
+// Illumination Filtering" (Dammertz et al. 2010). Illumination is demodulated by the albedo on the first
+// pass and modulated back on the last one.
+
+struct Params {
+  step: i32,
+  first: u32,
+  last: u32,
+  sigma_color: f32,
+  sigma_normal: f32,
+  sigma_depth: f32,
+}
+
+@group(0) @binding(0)
+var input: texture_2d<f32>;
+@group(0) @binding(1)
+var albedo: texture_2d<f32>;
+@group(0) @binding(2)
+var normal: texture_2d<f32>;
+@group(0) @binding(3)
+var depth: texture_2d<f32>;
+#ifdef FINAL_PASS
+@group(0) @binding(4)
+var output: texture_storage_2d<rgba8unorm, write>;
+#else
+@group(0) @binding(4)
+var output: texture_storage_2d<rgba16float, write>;
+#endif
+@group(0) @binding(5)
+var<uniform> params: Params;
+
+fn load_color(location: vec2<i32>) -> vec3<f32> {
+  let color = textureLoad(input, location, 0);
+  if (params.first != 0u) {
+    // The accumulation buffer holds weighted sums, resolve and demodulate them
+    return color.rgb / max(color.a, 1e-6) / max(textureLoad(albedo, location, 0).rgb, vec3(1e-3));
+  }
+  return color.rgb;
+}
+
+@compute @workgroup_size(8, 8, 1)
+fn main(@builtin(global_invocation_id) invocation_id: vec3<u32>) {
+  let size = vec2<i32>(textureDimensions(input));
+  let location = vec2<i32>(invocation_id.xy);
+  if (any(location >= size)) {
+    return;
+  }
+  var kernel = array<f32, 3>(0.375, 0.25, 0.0625);
+
+  let color = load_color(location);
+  let n = textureLoad(normal, location, 0).xyz;
+  let d = textureLoad(depth, location, 0).w;
+
+  var sum = vec3(0.0);
+  var weight_sum = 0.0;
+  for (var y = -2; y <= 2; y++) {
+    for (var x = -2; x <= 2; x++) {
+      let sample_location = clamp(location + vec2(x, y) * params.step, vec2(0), size - 1);
+      let sample_color = load_color(sample_location);
+      let sample_normal = textureLoad(normal, sample_location, 0).xyz;
+      let sample_depth = textureLoad(depth, sample_location, 0).w;
+
+      let color_delta = sample_color - color;
+      let color_weight = exp(-dot(color_delta, color_delta) / (params.sigma_color * params.sigma_color + 1e-6));
+      let normal_weight = pow(max(dot(n, sample_normal), 0.0), params.sigma_normal);
+      let depth_weight = exp(-abs(d - sample_depth) / (params.sigma_depth * f32(params.step) + 1e-6));
+      let weight = kernel[abs(x)] * kernel[abs(y)] * color_weight * normal_weight * depth_weight;
+
+      sum += sample_color * weight;
+      weight_sum += weight;
+    }
+  }
+
+  var result = sum / max(weight_sum, 1e-6);
+  if (weight_sum <= 0.0) {
+    result = color;
+  }
+  if (params.last != 0u) {
+    result *= max(textureLoad(albedo, location, 0).rgb, vec3(1e-3));
+  }
+  textureStore(output, location, vec4(result, 1.0));
+}
@@ -5,7 +5,19 @@
 @group(1) @binding(0)
 var texture: texture_storage_2d<rgba8unorm, read_write>;
 @group(1) @binding(1)
-var<uniform> iter: u32;
+var accumulation: texture_storage_2d<rgba32float, read_write>;
+@group(1) @binding(2)
+var albedo_aov: texture_storage_2d<rgba32float, read_write>;
+@group(1) @binding(3)
+var normal_aov: texture_storage_2d<rgba32float, read_write>;
+@group(1) @binding(4)
+var depth_aov: texture_storage_2d<rgba32float, read_write>;
+@group(1) @binding(5)
+var history_color: texture_2d<f32>;
+@group(1) @binding(6)
+var history_normal: texture_2d<f32>;
+@group(1) @binding(7)
+var history_depth: texture_2d<f32>;
 
 struct Vertex {
   coord: vec3<f32>,
@@ -14,11 +26,19 @@ struct Vertex {
 
 struct Mesh {
   transform: mat4x4<f32>,
+  previous_transform: mat4x4<f32>,
   start_index: u32,
   len_index: u32,
-  material: u32
+  material: u32,
+  flags: u32,
+  render_layers: u32,
 }
 
+// Mesh flags, each kind of ray only hits the meshes that have its flag
+const MESH_CAMERA_RAYS: u32 = 1u;
+const MESH_REFLECTIONS: u32 = 2u;
+const MESH_CAST_SHADOWS: u32 = 4u;
+
 struct Material {
   color: vec4<f32>,
   emissive: vec4<f32>,
@@ -31,7 +51,9 @@ struct HitInfo {
   hit_point: vec3<f32>,
   distance: f32,
   normal: vec3<f32>,
-  material:  u32
+  material:  u32,
+  geometric_normal: vec3<f32>,
+  mesh: u32,
 }
 
 @group(2) @binding(0)
@@ -40,31 +62,97 @@ var<storage> verticies: array<Vertex>;
 var<storage> indicies: array<u32>;
 @group(2) @binding(2)
 var<storage> meshes: array<Mesh>;
-@group(2) @binding(3)
-var<uniform> num_meshes: u32;
 
 @group(3) @binding(0)
 var<storage> materials: array<Material>;
 
+struct Light {
+  position: vec3<f32>,
+  kind: u32,
+  direction: vec3<f32>,
+  inverse_range_squared: f32,
+  color: vec3<f32>,
+  spot_scale: f32,
+  spot_offset: f32,
+}
+
+const LIGHT_POINT: u32 = 0u;
+const LIGHT_SPOT: u32 = 1u;
+const LIGHT_DIRECTIONAL: u32 = 2u;
+const MAX_LIGHTS: u32 = 16u;
+
+const PI: f32 = 3.14159265358979;
+
+struct FrameUniform {
+  light_dir: vec3<f32>,
+  mesh_count: u32,
+  // Radiance of rays leaving the scene, w is 0 for the debug environment
+  environment: vec4<f32>,
+  light_count: u32,
+  lights: array<Light, MAX_LIGHTS>,
+}
+
 @group(4) @binding(0)
-var<uniform> light_dir: vec3<f32>;
+var<uniform> frame_uniform: FrameUniform;
+
+struct Settings {
+  seed: u32,
+  frame: u32,
+  sampling: u32,
+  pixel_filter: u32,
+  filter_radius: f32,
+  lens_radius: f32,
+  focus_distance: f32,
+  aperture_blades: u32,
+  aperture_rotation: f32,
+  shutter_open: f32,
+  shutter_close: f32,
+  view_mode: u32,
+  previous_inverse_view: mat4x4<f32>,
+  previous_view_proj: mat4x4<f32>,
+  temporal: u32,
+  temporal_alpha: f32,
+  position_threshold: f32,
+  normal_threshold: f32,
+  resolution: vec2<u32>,
+  // Ratio between the aspect of the image and the one of the camera projection
+  aspect_scale: f32,
+  projection: u32,
+  fisheye_fov: f32,
+  cube_face: u32,
+  render_layers: u32,
+  // Whether the camera or the scene moved this frame
+  moved: u32,
+}
 
 @group(5) @binding(0)
-var<uniform> seed: vec2<f32>;
+var<uniform> settings: Settings;
+
+// Triangle tests done by hit(), reset before the primary ray for the traversal heatmap
+var<private> traversal_steps: u32;
 
 struct Ray {
   org: vec3<f32>,
-  dir: vec3<f32>
+  dir: vec3<f32>,
+  time: f32,
+}
+
+fn mix_mat4(a: mat4x4<f32>, b: mat4x4<f32>, t: f32) -> mat4x4<f32> {
+  return mat4x4(mix(a[0], b[0], t), mix(a[1], b[1], t), mix(a[2], b[2], t), mix(a[3], b[3], t));
 }
 
-fn hit(hit_info: ptr<function, HitInfo>, ray: Ray) -> bool {
+fn hit(hit_info: ptr<function, HitInfo>, ray: Ray, ray_flag: u32) -> bool {
   var hit_flag = false;
-  for (var mid: u32 = u32(0); mid < num_meshes; mid++) {
-    let transform = meshes[mid].transform;
+  for (var mid: u32 = u32(0); mid < frame_uniform.mesh_count; mid++) {
+    if ((meshes[mid].flags & ray_flag) == 0u || (meshes[mid].render_layers & settings.render_layers) == 0u) {
+      continue;
+    }
+    let transform = mix_mat4(meshes[mid].previous_transform, meshes[mid].transform, ray.time);
     let start_index = meshes[mid].start_index;
     let len_index = meshes[mid].len_index;
     let material = meshes[mid].material;
     for (var vid: u32 = start_index; vid < start_index + len_index; vid+=u32(3)) {
+      traversal_steps += 1u;
       let v0v = verticies[indicies[vid]];
       let v1v = verticies[indicies[vid+u32(1)]];
       let v2v = verticies[indicies[vid+u32(2)]];
@@ -113,6 +201,8 @@ fn hit(hit_info: ptr<function, HitInfo>, ray: Ray) -> bool {
         let normal = v0v.normal * a0 + v1v.normal * a1 + v2v.normal * a2;
 
         (*hit_info).normal = normal;
+        (*hit_info).geometric_normal = normalize(n);
+        (*hit_info).mesh = mid;
         (*hit_info).distance = t;
         (*hit_info).material = material;
       }
@@ -122,6 +212,9 @@ fn hit(hit_info: ptr<function, HitInfo>, ray: Ray) -> bool {
 }
 
 fn miss(ray: Ray) -> vec4<f32> {
+  if (frame_uniform.environment.w > 0.0) {
+    return vec4(frame_uniform.environment.rgb, 1.0);
+  }
   var color = vec4(0.0, 0.5 * f32(ray.dir.y), 0.0, 1.0);
 //  if ((ray.dir.x / 30.0 % 2 + ray.dir.y / 30.0 % 2.0) % 2 == 0) {
 //    color.g = 1.0 - color.g;
@@ -129,54 +222,443 @@ fn miss(ray: Ray) -> vec4<f32> {
   return color;
 }
 
+// Light reaching bounce rays that leave the scene
+fn ambient() -> vec3<f32> {
+  if (frame_uniform.environment.w > 0.0) {
+    return frame_uniform.environment.rgb;
+  }
+  return vec3(0.1, 0.1, 0.1);
+}
+
+// Irradiance from the punctual lights at a surface, each light is tested with a shadow ray
+fn direct_light(hit_info: HitInfo, time: f32) -> vec3<f32> {
+  let normal = normalize(hit_info.normal);
+  let origin = hit_info.hit_point + hit_info.geometric_normal * 0.0001;
+  var irradiance = vec3(0.0);
+  for (var i = 0u; i < min(frame_uniform.light_count, MAX_LIGHTS); i++) {
+    let light = frame_uniform.lights[i];
+    var to_light = -light.direction;
+    var distance = 1e30;
+    var radiance = light.color;
+    if (light.kind != LIGHT_DIRECTIONAL) {
+      let offset = light.position - origin;
+      let distance_squared = max(dot(offset, offset), 1e-8);
+      distance = sqrt(distance_squared);
+      to_light = offset / distance;
+      // Inverse square falloff windowed to the light's range
+      let range_factor = distance_squared * light.inverse_range_squared;
+      let window = clamp(1.0 - range_factor * range_factor, 0.0, 1.0);
+      radiance *= window * window / distance_squared;
+      if (light.kind == LIGHT_SPOT) {
+        let cone = clamp(dot(-to_light, light.direction) * light.spot_scale + light.spot_offset, 0.0, 1.0);
+        radiance *= cone * cone;
+      }
+    }
+    let cos_theta = dot(normal, to_light);
+    if (cos_theta <= 0.0 || all(radiance <= vec3(0.0))) {
+      continue;
+    }
+    var shadow_info: HitInfo;
+    let shadow_ray = Ray(origin, to_light, time);
+    if (hit(&shadow_info, shadow_ray, MESH_CAST_SHADOWS) && shadow_info.distance < distance) {
+      continue;
+    }
+    irradiance += radiance * cos_theta;
+  }
+  return irradiance;
+}
+
 fn light(ray: Ray) -> f32 {
-  let n = max(dot(normalize(ray.dir), -normalize(light_dir)), 0.0);
+  let n = max(dot(normalize(ray.dir), -normalize(frame_uniform.light_dir)), 0.0);
   return n;
 }
 
 
-fn random( p: ptr<function, vec2<f32>> ) -> f32 {
-  let v = *p;
-  let K1 : vec2<f32> = vec2( 23.14069263277926, 2.665144142690225 );
-  let fx = fract( cos( dot(v,K1) ) * 12345.6789 );
-  let fy = fract( cos( dot(v,K1) ) * 6789.12345 );
-  *p = vec2(fract(cos(v.x * fx)), fract(cos(v.y * fy)));
-  return fx;
+// PCG hash, see "Hash Functions for GPU Rendering" (Jarzynski, Olano 2020)
+fn pcg_hash(input: u32) -> u32 {
+  let state = input * 747796405u + 2891336453u;
+  let word = ((state >> ((state >> 28u) + 4u)) ^ state) * 277803737u;
+  return (word >> 22u) ^ word;
+}
+
+fn rng_init(pixel: vec2<u32>, frame: u32) -> u32 {
+  return pcg_hash(pixel.x + pcg_hash(pixel.y + pcg_hash(frame + pcg_hash(settings.seed))));
+}
+
+fn random(state: ptr<function, u32>) -> f32 {
+  let s = *state;
+  *state = s * 747796405u + 2891336453u;
+  let word = ((s >> ((s >> 28u) + 4u)) ^ s) * 277803737u;
+  return f32(((word >> 22u) ^ word) >> 8u) / 16777216.0;
+}
+
+const SAMPLING_RANDOM: u32 = 0u;
+const SAMPLING_SOBOL: u32 = 1u;
+
+// Every sample dimension is a 2D point, bounces take DIMS_PER_BOUNCE consecutive dimensions starting at DIM_BOUNCE
+const DIM_PIXEL: u32 = 0u;
+const DIM_LENS: u32 = 1u;
+const DIM_TIME: u32 = 2u;
+const DIM_BOUNCE: u32 = 3u;
+const DIM_BSDF: u32 = 0u;
+const DIM_LOBE: u32 = 1u;
+const DIMS_PER_BOUNCE: u32 = 2u;
+
+struct SampleState {
+  rng: u32,
+  pixel_seed: u32,
+  index: u32,
+}
+
+fn sample_state_init(pixel: vec2<u32>, frame: u32) -> SampleState {
+  var state: SampleState;
+  state.rng = rng_init(pixel, frame);
+  state.pixel_seed = pcg_hash(pixel.x + pcg_hash(pixel.y + pcg_hash(settings.seed)));
+  state.index = frame;
+  return state;
+}
+
+fn bounce_dimension(bounce: u32, dimension: u32) -> u32 {
+  return DIM_BOUNCE + bounce * DIMS_PER_BOUNCE + dimension;
+}
+
+// First two dimensions of the Sobol sequence
+fn sobol_2d(index: u32) -> vec2<u32> {
+  var x = 0u;
+  var v = 0x80000000u;
+  for (var i = index; i != 0u; i = i >> 1u) {
+    if ((i & 1u) != 0u) {
+      x = x ^ v;
+    }
+    v = v ^ (v >> 1u);
+  }
+  return vec2(reverseBits(index), x);
+}
+
+// Owen scrambling, see "Practical Hash-based Owen Scrambling" (Burley 2020)
+fn laine_karras_permutation(value: u32, seed: u32) -> u32 {
+  var x = value + seed;
+  x = x ^ (x * 0x6c50b47cu);
+  x = x ^ (x * 0xb82f1e52u);
+  x = x ^ (x * 0xc7afe638u);
+  x = x ^ (x * 0x8d22f6e6u);
+  return x;
+}
+
+fn nested_uniform_scramble(x: u32, seed: u32) -> u32 {
+  return reverseBits(laine_karras_permutation(reverseBits(x), seed));
+}
+
+fn sample_2d(state: ptr<function, SampleState>, dimension: u32) -> vec2<f32> {
+  if (settings.sampling == SAMPLING_SOBOL) {
+    let seed = pcg_hash((*state).pixel_seed ^ pcg_hash(dimension));
+    let index = nested_uniform_scramble((*state).index, seed);
+    let point = sobol_2d(index);
+    let x = nested_uniform_scramble(point.x, pcg_hash(seed));
+    let y = nested_uniform_scramble(point.y, pcg_hash(seed + 1u));
+    return vec2(f32(x >> 8u), f32(y >> 8u)) / 16777216.0;
+  }
+  var rng = (*state).rng;
+  let point = vec2(random(&rng), random(&rng));
+  (*state).rng = rng;
+  return point;
+}
+
+fn uniform_sphere(u: vec2<f32>) -> vec3<f32> {
+  let z = 1.0 - 2.0 * u.x;
+  let r = sqrt(max(0.0, 1.0 - z * z));
+  let phi = 6.283185307 * u.y;
+  return vec3(r * cos(phi), r * sin(phi), z);
+}
+
+const FILTER_BOX: u32 = 0u;
+const FILTER_TENT: u32 = 1u;
+const FILTER_GAUSSIAN: u32 = 2u;
+const FILTER_BLACKMAN_HARRIS: u32 = 3u;
+
+fn sample_tent(u: f32, radius: f32) -> f32 {
+  if (u < 0.5) {
+    return radius * (sqrt(2.0 * u) - 1.0);
+  }
+  return radius * (1.0 - sqrt(2.0 - 2.0 * u));
+}
+
+fn blackman_harris(x: f32, radius: f32) -> f32 {
+  let t = 6.283185307 * (x / radius + 1.0) * 0.5;
+  return 0.35875 - 0.48829 * cos(t) + 0.14128 * cos(2.0 * t) - 0.01168 * cos(3.0 * t);
+}
+
+// Sub-pixel offset in xy and its filter weight in z. Box, tent and gaussian are importance sampled and carry a
+// constant weight, Blackman-Harris has no closed form inverse so it is sampled uniformly and weighted instead.
+fn sample_filter(u: vec2<f32>) -> vec3<f32> {
+  let radius = settings.filter_radius;
+  if (settings.pixel_filter == FILTER_TENT) {
+    return vec3(sample_tent(u.x, radius), sample_tent(u.y, radius), 1.0);
+  }
+  if (settings.pixel_filter == FILTER_GAUSSIAN) {
+    let sigma = radius / 3.0;
+    let r = sigma * sqrt(-2.0 * log(max(u.x, 1e-7)));
+    let offset = r * vec2(cos(6.283185307 * u.y), sin(6.283185307 * u.y));
+    return vec3(offset, select(0.0, 1.0, all(abs(offset) <= vec2(radius))));
+  }
+  if (settings.pixel_filter == FILTER_BLACKMAN_HARRIS) {
+    let offset = (u * 2.0 - 1.0) * radius;
+    return vec3(offset, blackman_harris(offset.x, radius) * blackman_harris(offset.y, radius));
+  }
+  return vec3((u * 2.0 - 1.0) * radius, 1.0);
+}
+
+fn sample_disk(u: vec2<f32>) -> vec2<f32> {
+  let offset = u * 2.0 - 1.0;
+  if (all(offset == vec2(0.0))) {
+    return offset;
+  }
+  if (abs(offset.x) > abs(offset.y)) {
+    let theta = 0.785398163 * (offset.y / offset.x);
+    return offset.x * vec2(cos(theta), sin(theta));
+  }
+  let theta = 1.570796327 - 0.785398163 * (offset.x / offset.y);
+  return offset.y * vec2(cos(theta), sin(theta));
+}
+
+// Uniform point on the unit aperture, a regular polygon with aperture_blades sides or a disk
+fn sample_aperture(u: vec2<f32>) -> vec2<f32> {
+  let blades = settings.aperture_blades;
+  if (blades < 3u) {
+    return sample_disk(u);
+  }
+  let sector = min(u32(u.x * f32(blades)), blades - 1u);
+  let ux = u.x * f32(blades) - f32(sector);
+  let step = 6.283185307 / f32(blades);
+  let a0 = settings.aperture_rotation + f32(sector) * step;
+  let v0 = vec2(cos(a0), sin(a0));
+  let v1 = vec2(cos(a0 + step), sin(a0 + step));
+  let su = sqrt(ux);
+  return su * (1.0 - u.y) * v0 + su * u.y * v1;
+}
+
+const PROJECTION_CAMERA: u32 = 0u;
+const PROJECTION_EQUIRECTANGULAR: u32 = 1u;
+const PROJECTION_FISHEYE: u32 = 2u;
+const PROJECTION_CUBE_FACE: u32 = 3u;
+
+struct CameraRay {
+  org: vec3<f32>,
+  dir: vec3<f32>,
+  // False outside of the image circle of the fisheye
+  valid: bool,
+}
+
+// Direction through a cube map texel, s and t in [-1, 1] with t pointing down
+fn cube_face_dir(face: u32, s: f32, t: f32) -> vec3<f32> {
+  if (face == 0u) {
+    return vec3(1.0, -t, -s);
+  } else if (face == 1u) {
+    return vec3(-1.0, -t, s);
+  } else if (face == 2u) {
+    return vec3(s, 1.0, t);
+  } else if (face == 3u) {
+    return vec3(s, -1.0, -t);
+  } else if (face == 4u) {
+    return vec3(s, -t, 1.0);
+  }
+  return vec3(-s, -t, -1.0);
+}
+
+// View space ray through the image position uv, in [0, 1] from the top left corner
+fn camera_ray(uv: vec2<f32>, lens_sample: vec2<f32>) -> CameraRay {
+  var ray: CameraRay;
+  ray.valid = true;
+  ray.org = vec3(0.0);
+  let pi = 3.14159265;
+  if (settings.projection == PROJECTION_EQUIRECTANGULAR) {
+    let phi = (uv.x - 0.5) * 2.0 * pi;
+    let theta = (0.5 - uv.y) * pi;
+    ray.dir = vec3(sin(phi) * cos(theta), sin(theta), -cos(phi) * cos(theta));
+    return ray;
+  }
+  if (settings.projection == PROJECTION_FISHEYE) {
+    let resolution = vec2<f32>(settings.resolution);
+    let p = vec2(uv.x * 2.0 - 1.0, 1.0 - uv.y * 2.0) * resolution / min(resolution.x, resolution.y);
+    let r = length(p);
+    ray.valid = r <= 1.0;
+    let theta = r * settings.fisheye_fov * 0.5;
+    ray.dir = vec3(sin(theta) * p / max(r, 1e-6), -cos(theta));
+    return ray;
+  }
+  if (settings.projection == PROJECTION_CUBE_FACE) {
+    ray.dir = normalize(cube_face_dir(settings.cube_face, uv.x * 2.0 - 1.0, uv.y * 2.0 - 1.0));
+    return ray;
+  }
+
+  let ndc = vec2((uv.x * 2.0 - 1.0) * settings.aspect_scale, -uv.y * 2.0 + 1.0);
+  let ray_target = view.inverse_projection * vec4(ndc, 1.0, 1.0);
+  ray.dir = normalize(ray_target.xyz / ray_target.w);
+  // Orthographic projections have no perspective divide, rays start on the near plane and run parallel
+  if (view.projection[3].w == 1.0) {
+    ray.org = ray_target.xyz / ray_target.w;
+    ray.dir = vec3(0.0, 0.0, -1.0);
+  }
+  if (settings.lens_radius > 0.0) {
+    let focus_point = ray.org + ray.dir * (settings.focus_distance / -ray.dir.z);
+    ray.org += vec3(settings.lens_radius * sample_aperture(lens_sample), 0.0);
+    ray.dir = normalize(focus_point - ray.org);
+  }
+  return ray;
+}
+
+const VIEW_PATH_TRACED: u32 = 0u;
+const VIEW_ALBEDO: u32 = 1u;
+const VIEW_SHADING_NORMAL: u32 = 2u;
+const VIEW_GEOMETRIC_NORMAL: u32 = 3u;
+const VIEW_WORLD_POSITION: u32 = 4u;
+const VIEW_DEPTH: u32 = 5u;
+const VIEW_MATERIAL_ID: u32 = 6u;
+const VIEW_MESH_ID: u32 = 7u;
+const VIEW_BOUNCE_COUNT: u32 = 8u;
+const VIEW_TRAVERSAL_STEPS: u32 = 9u;
+
+const MAX_BOUNCES: i32 = 6;
+
+fn heatmap(t: f32) -> vec3<f32> {
+  let x = clamp(t, 0.0, 1.0);
+  return clamp(vec3(4.0 * x - 2.0, 2.0 - abs(4.0 * x - 2.0), 2.0 - 4.0 * x), vec3(0.0), vec3(1.0));
+}
+
+fn id_color(id: u32) -> vec3<f32> {
+  let h = pcg_hash(id + 1u);
+  return vec3(f32(h & 255u), f32((h >> 8u) & 255u), f32((h >> 16u) & 255u)) / 255.0;
+}
+
+// First hit visualisations, everything except the path traced and bounce count views
+fn debug_view(is_hit: bool, hit_info: HitInfo, steps: u32) -> vec3<f32> {
+  if (settings.view_mode == VIEW_TRAVERSAL_STEPS) {
+    return heatmap(log2(f32(steps) + 1.0) / 16.0);
+  }
+  if (!is_hit) {
+    return vec3(0.0);
+  }
+  let mode = settings.view_mode;
+  if (mode == VIEW_ALBEDO) {
+    return materials[hit_info.material].color.rgb;
+  }
+  if (mode == VIEW_SHADING_NORMAL) {
+    return normalize(hit_info.normal) * 0.5 + 0.5;
+  }
+  if (mode == VIEW_GEOMETRIC_NORMAL) {
+    return hit_info.geometric_normal * 0.5 + 0.5;
+  }
+  if (mode == VIEW_WORLD_POSITION) {
+    return hit_info.hit_point;
+  }
+  if (mode == VIEW_DEPTH) {
+    return vec3(exp(-hit_info.distance * 0.1));
+  }
+  if (mode == VIEW_MATERIAL_ID) {
+    return id_color(hit_info.material);
+  }
+  if (mode == VIEW_MESH_ID) {
+    return id_color(hit_info.mesh);
+  }
+  return vec3(1.0, 0.0, 1.0);
+}
+
+// Last frame's accumulated sum at the location the first hit projected to, or zero when it was disoccluded
+fn reproject_history(position_depth: vec4<f32>, normal: vec3<f32>, is_hit: bool) -> vec4<f32> {
+  let clip = settings.previous_view_proj * vec4(position_depth.xyz, 1.0);
+  if (clip.w <= 0.0) {
+    return vec4(0.0);
+  }
+  let ndc = clip.xy / clip.w / vec2(settings.aspect_scale, 1.0);
+  let size = vec2<i32>(textureDimensions(history_color));
+  let previous = vec2<i32>(floor(vec2(ndc.x * 0.5 + 0.5, 0.5 - ndc.y * 0.5) * vec2<f32>(size)));
+  if (any(previous < vec2(0)) || any(previous >= size)) {
+    return vec4(0.0);
+  }
+  let previous_position = textureLoad(history_depth, previous, 0).xyz;
+  if (length(previous_position - position_depth.xyz) > settings.position_threshold * position_depth.w) {
+    return vec4(0.0);
+  }
+  if (is_hit && dot(textureLoad(history_normal, previous, 0).xyz, normal) < settings.normal_threshold) {
+    return vec4(0.0);
+  }
+  let history = textureLoad(history_color, previous, 0);
+  // A still view keeps summing like the accumulation without reprojection
+  if (settings.moved == 0u) {
+    return history;
+  }
+  // Capping the weight of the history turns the running sum into an exponential moving average
+  let max_weight = (1.0 - settings.temporal_alpha) / max(settings.temporal_alpha, 1e-6);
+  return history * min(1.0, max_weight / max(history.a, 1e-6));
 }
 
 @compute @workgroup_size(32, 32, 1)
 fn main(@builtin(global_invocation_id) invocation_id: vec3<u32>) {
-  var seed: vec2<f32> = seed + vec2(f32(invocation_id.x), f32(invocation_id.y));
+  // The dispatch is rounded up to whole workgroups
+  if (any(invocation_id.xy >= settings.resolution)) {
+    return;
+  }
+  var sample_state = sample_state_init(invocation_id.xy, settings.frame);
 //  let p = view.view_proj;
   let location = vec2<i32>(i32(invocation_id.x), i32(invocation_id.y));
-  let location_normalised = vec4<f32>(f32(invocation_id.x) / 1024.0 * 2.0 - 1.0, -f32(invocation_id.y) / 768.0 * 2.0 + 1.0, 1.0, 1.0);
+  let filter_sample = sample_filter(sample_2d(&sample_state, DIM_PIXEL));
+  let pixel = vec2(f32(invocation_id.x), f32(invocation_id.y)) + 0.5 + filter_sample.xy;
+  let uv = pixel / vec2<f32>(settings.resolution);
 //  let ray_org = vec3<f32>(0.0, 0.0, -2.0);
   var ray : Ray;
-  ray.org = view.world_position;
 //  let ray_dir = vec3<f32>(f32(invocation_id.x) / 1024.0 * 2.0 - 1.0, f32(invocation_id.y) / 1024.0 * 2.0 - 1.0, -1.0);
-  let ray_target = view.inverse_projection * location_normalised;
-  ray.dir = (view.inverse_view * vec4(normalize(ray_target.xyz / ray_target.w), 0.0)).xyz;
+  let camera = camera_ray(uv, sample_2d(&sample_state, DIM_LENS));
+  if (!camera.valid) {
+    textureStore(accumulation, location, vec4(0.0));
+    textureStore(albedo_aov, location, vec4(0.0));
+    textureStore(normal_aov, location, vec4(0.0));
+    textureStore(depth_aov, location, vec4(0.0));
+    textureStore(texture, location, vec4(0.0, 0.0, 0.0, 1.0));
+    return;
+  }
+  // Instances and the camera move linearly between their previous and current transforms within the shutter
+  ray.time = mix(settings.shutter_open, settings.shutter_close, sample_2d(&sample_state, DIM_TIME).x);
+  let inverse_view = mix_mat4(settings.previous_inverse_view, view.inverse_view, ray.time);
+  ray.org = (inverse_view * vec4(camera.org, 1.0)).xyz;
+  ray.dir = normalize((inverse_view * vec4(camera.dir, 0.0)).xyz);
 
   var color = vec4(1.0, 1.0, 1.0, 1.0);
   var light = vec4(0.0, 0.0, 0.0, 0.0);
   var hit_info: HitInfo;
+  let primary_ray = ray;
+  traversal_steps = 0u;
+  let primary_hit = hit(&hit_info, ray, MESH_CAMERA_RAYS);
+  let primary_info = hit_info;
+  let primary_steps = traversal_steps;
   // ---
   var ray_count = 0;
-  while (true) {
-    if (ray_count > 5) {
+  let path_traced = settings.view_mode == VIEW_PATH_TRACED || settings.view_mode == VIEW_BOUNCE_COUNT;
+  while (path_traced) {
+    if (ray_count >= MAX_BOUNCES) {
       break;
     }
     ray_count = ray_count + 1;
-    if (hit(&hit_info, ray)) {
+    var is_hit = primary_hit;
+    if (ray_count > 1) {
+      is_hit = hit(&hit_info, ray, MESH_REFLECTIONS);
+    }
+    if (is_hit) {
 //      color = vec4(materials[hit_info.material].color.rgb, 1.0);
 //      break;
       if (length(materials[hit_info.material].emissive.rgb) > 0.1) {
         light += vec4(color.rgb * materials[hit_info.material].emissive.rgb, 1.0);
         break;
       }
-      let diffuseDir = reflect(ray.dir, hit_info.normal + (materials[hit_info.material].roughness - 0.089) * vec3(random(&seed) - 0.5, random(&seed) - 0.5, random(&seed) - 0.5));
+      // Lambertian response to the lights, weighted by the chance of taking the diffuse lobe below
+      let material = materials[hit_info.material];
+      let diffuse = color.rgb * material.color.rgb * (1.0 - material.metallic) / PI;
+      light += vec4(diffuse * direct_light(hit_info, ray.time), 0.0);
+      let bounce = u32(ray_count - 1);
+      let bsdf_sample = sample_2d(&sample_state, bounce_dimension(bounce, DIM_BSDF));
+      let lobe_sample = sample_2d(&sample_state, bounce_dimension(bounce, DIM_LOBE));
+      let diffuseDir = reflect(ray.dir, hit_info.normal + (materials[hit_info.material].roughness - 0.089) * 0.5 * uniform_sphere(bsdf_sample));
       let specularDir = reflect(ray.dir, hit_info.normal);
-      if (random(&seed) > materials[hit_info.material].metallic) {
+      if (lobe_sample.x > materials[hit_info.material].metallic) {
         color = vec4(color.rgb * materials[hit_info.material].color.rgb, 1.0);
         ray.org = hit_info.hit_point + hit_info.normal * 0.0001;
         let s = materials[hit_info.material].specular;
@@ -190,7 +672,7 @@ fn main(@builtin(global_invocation_id) invocation_id: vec3<u32>) {
         light = miss(ray);
 //          color = light;
       } else {
-        light += vec4(color.rgb * vec3(0.1, 0.1, 0.1), 1.0);
+        light += vec4(color.rgb * ambient(), 1.0);
 //        color = vec4((color.rgb * light(ray)), 1.0);
 //        color = color * 0.0001;
       }
@@ -198,10 +680,45 @@ fn main(@builtin(global_invocation_id) invocation_id: vec3<u32>) {
     }
   }
   color = light;
-  let weight = 1.0 / (f32(iter) + 1.0);
-  let new_color = vec4(textureLoad(texture, location).rgb * (1.0 - weight) + color.rgb * weight, 1.0);
-//  let new_color = color;
-//  let old_color = vec4(textureLoad(texture, location).rgb * f32(iter), 1.0);
-//  let new_color = vec4((old_color + color).rgb / f32(iter + u32(1)), 1.0);
+  if (settings.view_mode == VIEW_BOUNCE_COUNT) {
+    color = vec4(heatmap(f32(ray_count - 1) / f32(MAX_BOUNCES - 1)), 1.0);
+  } else if (!path_traced) {
+    color = vec4(debug_view(primary_hit, primary_info, primary_steps), 1.0);
+  }
+  var albedo = miss(primary_ray).rgb;
+  var normal = vec3(0.0);
+  var position_depth = vec4(primary_ray.org + primary_ray.dir * 1e6, 1e6);
+  if (primary_hit) {
+    let forward = -normalize(inverse_view[2].xyz);
+    albedo = materials[primary_info.material].color.rgb;
+    normal = normalize(primary_info.normal);
+    // Panoramic projections have no single view direction, their depth is the distance to the camera
+    var depth = primary_info.distance;
+    if (settings.projection == PROJECTION_CAMERA) {
+      depth *= dot(primary_ray.dir, forward);
+    }
+    position_depth = vec4(primary_info.hit_point, depth);
+  }
+
+  // rgb holds the filter weighted sum of samples, a the sum of weights
+  var sum = vec4(color.rgb * filter_sample.z, filter_sample.z);
+  if (settings.temporal != 0u) {
+    sum += reproject_history(position_depth, normal, primary_hit);
+  } else if (settings.frame > 0u) {
+    sum += textureLoad(accumulation, location);
+  }
+  textureStore(accumulation, location, sum);
+
+  // AOVs keep the running filter weighted average of the first hit, temporal mode keeps the current one
+  if (settings.frame > 0u && settings.temporal == 0u) {
+    let aov_weight = filter_sample.z / max(sum.a, 1e-6);
+    albedo = mix(textureLoad(albedo_aov, location).rgb, albedo, aov_weight);
+    normal = mix(textureLoad(normal_aov, location).xyz, normal, aov_weight);
+    position_depth = mix(textureLoad(depth_aov, location), position_depth, aov_weight);
+  }
+  textureStore(albedo_aov, location, vec4(albedo, 1.0));
+  textureStore(normal_aov, location, vec4(normal, 1.0));
+  textureStore(depth_aov, location, position_depth);
+  let new_color = vec4(sum.rgb / max(sum.a, 1e-6), 1.0);
   textureStore(texture, location, new_color);
 }
\ No newline at end of file
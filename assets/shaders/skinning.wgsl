@@ -0,0 +1,57 @@
+// Deforms the copies of skinned meshes in the ray traced vertex buffer, one workgroup row per instance
+
+struct Vertex {
+  coord: vec3<f32>,
+  normal: vec3<f32>,
+};
+
+struct SkinVertex {
+  position: vec3<f32>,
+  normal: vec3<f32>,
+  joints: vec4<u32>,
+  weights: vec4<f32>,
+};
+
+struct SkinInstance {
+  skin_vertex: u32,
+  output_vertex: u32,
+  vertex_count: u32,
+  joint_offset: u32,
+  joint_count: u32,
+  pad0: u32,
+  pad1: u32,
+  pad2: u32,
+};
+
+@group(0) @binding(0)
+var<storage, read_write> verticies: array<Vertex>;
+@group(0) @binding(1)
+var<storage> skin_verticies: array<SkinVertex>;
+@group(0) @binding(2)
+var<storage> instances: array<SkinInstance>;
+@group(0) @binding(3)
+var<storage> joints: array<mat4x4<f32>>;
+
+fn joint(instance: SkinInstance, index: u32) -> mat4x4<f32> {
+  // Out of range indices would read the joints of other instances
+  return joints[instance.joint_offset + min(index, instance.joint_count - 1u)];
+}
+
+@compute @workgroup_size(64, 1, 1)
+fn main(@builtin(global_invocation_id) invocation_id: vec3<u32>) {
+  let instance = instances[invocation_id.y];
+  if (invocation_id.x >= instance.vertex_count || instance.joint_count == 0u) {
+    return;
+  }
+  let skin = skin_verticies[instance.skin_vertex + invocation_id.x];
+  let model = joint(instance, skin.joints.x) * skin.weights.x
+    + joint(instance, skin.joints.y) * skin.weights.y
+    + joint(instance, skin.joints.z) * skin.weights.z
+    + joint(instance, skin.joints.w) * skin.weights.w;
+
+  var vertex: Vertex;
+  vertex.coord = (model * vec4(skin.position, 1.0)).xyz;
+  // Exact for rotations and uniform scale, which is what joints of animated characters have
+  vertex.normal = normalize((model * vec4(skin.normal, 0.0)).xyz);
+  verticies[instance.output_vertex + invocation_id.x] = vertex;
+}
@@ -0,0 +1,296 @@
+use crate::render::raytracer::types::{RaytraceSettings, RaytracedCamera};
+use crate::render::readback::types::{ImageReadback, ImageReadbacks};
+use crate::util::export::{rgba32_texels, write_exr, write_pfm, write_png};
+use bevy::prelude::*;
+use std::borrow::Cow;
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+/// File formats a ray traced camera can be saved as, picked from the file extension.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum ExportFormat {
+  /// The denoised display image, or the tonemapped accumulated radiance with [`ExportOptions::raw`].
+  Png,
+  /// The linear accumulated radiance, optionally with the AOVs as extra layers.
+  Exr,
+  /// The linear accumulated radiance.
+  Pfm,
+}
+
+impl ExportFormat {
+  pub fn from_path(path: &Path) -> Option<Self> {
+    let extension = path.extension()?.to_str()?.to_ascii_lowercase();
+    match extension.as_str() {
+      "png" => Some(Self::Png),
+      "exr" => Some(Self::Exr),
+      "pfm" => Some(Self::Pfm),
+      _ => None,
+    }
+  }
+}
+
+/// Maps linear radiance to the displayable range before 8 bit images are encoded as sRGB.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub enum Tonemapper {
+  /// Clips at 1, what the viewport shows.
+  #[default]
+  Clamp,
+  Reinhard,
+  /// Narkowicz's fit of the ACES filmic curve.
+  Aces,
+}
+
+impl Tonemapper {
+  pub const ALL: [Self; 3] = [Self::Clamp, Self::Reinhard, Self::Aces];
+
+  pub fn apply(self, color: Vec3) -> Vec3 {
+    let color = color.max(Vec3::ZERO);
+    let mapped = match self {
+      Self::Clamp => color,
+      Self::Reinhard => color / (color + 1.0),
+      Self::Aces => (color * (2.51 * color + 0.03)) / (color * (2.43 * color + 0.59) + 0.14),
+    };
+    mapped.min(Vec3::ONE)
+  }
+}
+
+#[derive(Clone, Copy, Default, Debug)]
+pub struct ExportOptions {
+  /// Store EXR channels as 16 bit floats.
+  pub half: bool,
+  /// Add albedo, normal, position and depth layers to EXR files.
+  pub aovs: bool,
+  /// Tonemap the accumulated radiance for PNG files and the clipboard instead of taking the denoised display image.
+  pub raw: bool,
+  /// Curve used for raw PNG files and clipboard images.
+  pub tonemapper: Tonemapper,
+}
+
+/// Where an export ends up.
+#[derive(Clone, Debug)]
+pub enum ExportTarget {
+  /// A file, its format picked from the extension.
+  File(PathBuf),
+  /// The system clipboard, as a tonemapped 8 bit image.
+  Clipboard,
+}
+
+impl fmt::Display for ExportTarget {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      Self::File(path) => path.display().fmt(f),
+      Self::Clipboard => f.write_str("the clipboard"),
+    }
+  }
+}
+
+/// Keeps the clipboard open, on X11 copied images only stay available while it lives.
+#[derive(Default)]
+pub struct ClipboardContext(Option<arboard::Clipboard>);
+
+impl ClipboardContext {
+  fn set_rgba(&mut self, size: UVec2, rgba: Vec<u8>) -> std::io::Result<()> {
+    let clipboard = match &mut self.0 {
+      Some(clipboard) => clipboard,
+      empty => empty.insert(arboard::Clipboard::new().map_err(to_io_error)?),
+    };
+    clipboard
+      .set_image(arboard::ImageData {
+        width: size.x as usize,
+        height: size.y as usize,
+        bytes: Cow::Owned(rgba),
+      })
+      .map_err(to_io_error)
+  }
+}
+
+fn to_io_error(err: arboard::Error) -> std::io::Error {
+  std::io::Error::new(std::io::ErrorKind::Other, err)
+}
+
+/// What the read back images hold.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum ExportSource {
+  /// The accumulation of a ray traced camera, followed by its AOVs for layered EXR files.
+  Camera,
+  /// The denoised 8 bit sRGB display image of a camera, written as is.
+  Display,
+}
+
+/// Reads back the images of a ray traced camera and writes them out once they arrived.
+pub struct ExportJob {
+  pub target: ExportTarget,
+  source: ExportSource,
+  format: ExportFormat,
+  options: ExportOptions,
+  size: UVec2,
+  /// Keyword and text pairs stored as PNG text chunks.
+  metadata: Vec<(String, String)>,
+  readbacks: Vec<ImageReadback>,
+}
+
+impl ExportJob {
+  pub fn new(
+    target: ExportTarget,
+    options: ExportOptions,
+    camera: &RaytracedCamera,
+    images: &Assets<Image>,
+    readbacks: &mut ImageReadbacks,
+  ) -> Result<Self, String> {
+    let format = match &target {
+      ExportTarget::File(path) => ExportFormat::from_path(path)
+        .ok_or_else(|| format!("{}: unknown format, expected png, exr or pfm", path.display()))?,
+      ExportTarget::Clipboard => ExportFormat::Png,
+    };
+    let size = camera.size(images).ok_or("the camera has no image yet")?;
+    let source = match format {
+      ExportFormat::Png if !options.raw => ExportSource::Display,
+      _ => ExportSource::Camera,
+    };
+    let handles = match format {
+      ExportFormat::Png if !options.raw => vec![camera.image.clone()],
+      ExportFormat::Exr if options.aovs => vec![
+        camera.accumulation.clone(),
+        camera.aovs.albedo.clone(),
+        camera.aovs.normal.clone(),
+        camera.aovs.depth.clone(),
+      ],
+      ExportFormat::Png | ExportFormat::Exr | ExportFormat::Pfm => vec![camera.accumulation.clone()],
+    };
+    let job = Self {
+      target,
+      source,
+      format,
+      options,
+      size,
+      metadata: vec![],
+      readbacks: handles.into_iter().map(ImageReadback::new).collect(),
+    };
+    readbacks.0.extend(job.readbacks.iter().cloned());
+    Ok(job)
+  }
+
+  /// Records the settings and the sample count the image was rendered with.
+  pub fn with_render_info(mut self, settings: &RaytraceSettings, samples: u32) -> Self {
+    self.metadata = vec![
+      ("Software".to_string(), env!("CARGO_PKG_NAME").to_string()),
+      ("Samples".to_string(), samples.to_string()),
+      ("Settings".to_string(), format!("{:#?}", settings)),
+    ];
+    self
+  }
+
+  /// Writes the output once every image arrived, `None` while still waiting.
+  pub fn poll(&self, readbacks: &mut ImageReadbacks, clipboard: &mut ClipboardContext) -> Option<std::io::Result<()>> {
+    if !self.readbacks.iter().all(ImageReadback::is_done) {
+      return None;
+    }
+    readbacks
+      .0
+      .retain(|readback| !self.readbacks.iter().any(|own| own.is(readback)));
+    let data = self
+      .readbacks
+      .iter()
+      .map(|readback| readback.take().unwrap_or_default())
+      .collect::<Vec<_>>();
+    Some(self.write(data, clipboard))
+  }
+
+  fn write(&self, mut data: Vec<Vec<u8>>, clipboard: &mut ClipboardContext) -> std::io::Result<()> {
+    if self.source == ExportSource::Display {
+      return self.write_rgba8(data.swap_remove(0), clipboard);
+    }
+    // The accumulation holds weighted sums in rgb and the sum of weights in alpha
+    let color = rgba32_texels(&data[0])
+      .into_iter()
+      .map(|texel| {
+        if texel.w > 0.0 {
+          texel.truncate() / texel.w
+        } else {
+          Vec3::ZERO
+        }
+      })
+      .collect::<Vec<_>>();
+    let path = match (&self.target, self.format) {
+      (ExportTarget::Clipboard, _) | (_, ExportFormat::Png) => {
+        return self.write_rgba8(self.tonemap(&color), clipboard)
+      }
+      (ExportTarget::File(path), ExportFormat::Pfm) => return write_pfm(path, self.size, &color),
+      (ExportTarget::File(path), ExportFormat::Exr) => path,
+    };
+
+    let mut channels = vec![];
+    push_channels(&mut channels, "", ["R", "G", "B"], &color);
+    if let [_, albedo, normal, depth] = data.as_slice() {
+      let depth = rgba32_texels(depth);
+      let position = depth.iter().map(|texel| texel.truncate()).collect::<Vec<_>>();
+      push_channels(&mut channels, "albedo.", ["R", "G", "B"], &vec3_texels(albedo));
+      push_channels(&mut channels, "normal.", ["X", "Y", "Z"], &vec3_texels(normal));
+      push_channels(&mut channels, "position.", ["X", "Y", "Z"], &position);
+      channels.push(("depth.Z".to_string(), depth.iter().map(|texel| texel.w).collect()));
+    }
+    write_exr(path, self.size, channels, self.options.half)
+  }
+
+  fn write_rgba8(&self, rgba: Vec<u8>, clipboard: &mut ClipboardContext) -> std::io::Result<()> {
+    match &self.target {
+      ExportTarget::File(path) => write_png(path, self.size, &rgba, &self.metadata),
+      ExportTarget::Clipboard => clipboard.set_rgba(self.size, rgba),
+    }
+  }
+
+  /// Tonemaps and sRGB encodes linear radiance to opaque 8 bit rgba.
+  fn tonemap(&self, color: &[Vec3]) -> Vec<u8> {
+    color
+      .iter()
+      .flat_map(|&color| {
+        let [r, g, b] = self.options.tonemapper.apply(color).to_array().map(encode_srgb);
+        [r, g, b, u8::MAX]
+      })
+      .collect()
+  }
+}
+
+fn encode_srgb(linear: f32) -> u8 {
+  let encoded = if linear <= 0.0031308 {
+    linear * 12.92
+  } else {
+    1.055 * linear.powf(1.0 / 2.4) - 0.055
+  };
+  (encoded * 255.0 + 0.5) as u8
+}
+
+fn vec3_texels(data: &[u8]) -> Vec<Vec3> {
+  rgba32_texels(data).into_iter().map(Vec4::truncate).collect()
+}
+
+fn push_channels(channels: &mut Vec<(String, Vec<f32>)>, layer: &str, names: [&str; 3], texels: &[Vec3]) {
+  for (i, name) in names.into_iter().enumerate() {
+    channels.push((
+      format!("{}{}", layer, name),
+      texels.iter().map(|texel| texel[i]).collect(),
+    ));
+  }
+}
+
+/// Exports started from the editor, written as soon as their images arrive.
+#[derive(Resource, Default)]
+pub struct ExportJobs(pub Vec<ExportJob>);
+
+pub fn write_exports(
+  mut jobs: ResMut<ExportJobs>,
+  mut readbacks: ResMut<ImageReadbacks>,
+  mut clipboard: NonSendMut<ClipboardContext>,
+) {
+  jobs.0.retain(|job| match job.poll(&mut readbacks, &mut clipboard) {
+    None => true,
+    Some(Ok(())) => {
+      info!("Saved to {}", job.target);
+      false
+    }
+    Some(Err(err)) => {
+      error!("Failed to save to {}: {}", job.target, err);
+      false
+    }
+  });
+}
@@ -1,10 +1,18 @@
-use crate::render::raytracer::types::{PBRCameraEntity, RTCameraEntity, RaytracingImage, TextureIter};
-use crate::render::raytracer::SIZE;
+use crate::render::raytracer::types::{
+  NotRaytraced, PBRCameraEntity, PipelineReady, PreviousGlobalTransform, RTCameraEntity, RaytraceDisplay,
+  RaytraceEnvironment, RaytraceMarkerPolicy, RaytraceSettings, RaytraceVisibility, Raytraced, RaytracedCamera,
+  RaytracedCameraBundle, TextureIter, ThinLensCamera, ViewMotion,
+};
 use crate::render::LightDir;
 use bevy::prelude::*;
 use bevy::render::camera::CameraOutputMode;
-use bevy::render::render_resource::{Extent3d, TextureDimension, TextureFormat, TextureUsages};
+use bevy::render::view::RenderLayers;
+use bevy::window::PrimaryWindow;
 use bevy_egui::EguiContexts;
+use std::sync::atomic::Ordering;
+
+pub mod export;
+pub mod scenes;
 
 #[derive(States, PartialEq, Eq, Debug, Clone, Hash, Default)]
 pub enum AppState {
@@ -17,30 +25,24 @@ pub fn setup(
   mut commands: Commands,
   mut images: ResMut<Assets<Image>>,
   mut egui_contexts: EguiContexts,
-  asset_server: Res<AssetServer>,
+  windows: Query<&Window, With<PrimaryWindow>>,
 ) {
-  let mut image = Image::new_fill(
-    Extent3d {
-      width: SIZE[0],
-      height: SIZE[1],
-      depth_or_array_layers: 1,
-    },
-    TextureDimension::D2,
-    &[255, 0, 0, 255],
-    TextureFormat::Rgba8Unorm,
-  );
-  image.texture_descriptor.usage =
-    TextureUsages::COPY_DST | TextureUsages::STORAGE_BINDING | TextureUsages::TEXTURE_BINDING;
-  let image = images.add(image);
+  let window = windows.get_single().ok();
+  let target_size = window.map(|window| UVec2::new(window.physical_width(), window.physical_height()));
+  let raytraced_camera = RaytracedCameraBundle::new(&mut images, render_size(target_size, &default()));
+  let image = raytraced_camera.raytraced_camera.image.clone();
 
-  commands.spawn(SpriteBundle {
-    sprite: Sprite {
-      custom_size: Some(Vec2::new(1024 as f32, 768 as f32)),
+  commands.spawn((
+    SpriteBundle {
+      sprite: Sprite {
+        custom_size: window.map(|window| Vec2::new(window.width(), window.height())),
+        ..default()
+      },
+      texture: image.clone(),
       ..default()
     },
-    texture: image.clone(),
-    ..default()
-  });
+    RaytraceDisplay,
+  ));
   let cam_2d = commands
     .spawn(Camera2dBundle {
       camera: Camera {
@@ -53,31 +55,24 @@ pub fn setup(
     })
     .id();
   let cam_3d = commands
-    .spawn(Camera3dBundle {
-      camera: Camera {
-        order: 1,
-        output_mode: CameraOutputMode::Skip,
+    .spawn((
+      Camera3dBundle {
+        camera: Camera {
+          order: 1,
+          output_mode: CameraOutputMode::Skip,
+          ..default()
+        },
+        transform: Transform::from_xyz(0.0, 1.2, 4.0),
         ..default()
       },
-      transform: Transform::from_xyz(0.0, 1.2, 4.0),
-      ..default()
-    })
+      raytraced_camera,
+    ))
     .id();
 
   commands.insert_resource(RTCameraEntity(cam_2d));
   commands.insert_resource(PBRCameraEntity(cam_3d));
 
-  egui_contexts.add_image(image.clone());
-
-  commands.insert_resource(RaytracingImage(image));
-
-  let scene = asset_server.load("boxge.glb#Scene0");
-
-  commands.spawn(SceneBundle {
-    scene: scene,
-    transform: Transform::from_xyz(0.0, 0.0, 0.0),
-    ..Default::default()
-  });
+  egui_contexts.add_image(image);
 
   // let mesh = Cube {
   //   size: 1.0,
@@ -159,19 +154,117 @@ pub fn setup(
   // });
 }
 
+/// Resolution of the ray traced image, the configured one or the camera target's, scaled by the render scale.
+pub fn render_size(target_size: Option<UVec2>, settings: &RaytraceSettings) -> UVec2 {
+  let size = settings.resolution.or(target_size).unwrap_or(UVec2::new(1024, 768));
+  (size.as_vec2() * settings.render_scale).as_uvec2().max(UVec2::ONE)
+}
+
+/// Reallocates the images of ray traced cameras when their target or configured resolution changes.
+pub fn resize_render_targets(
+  windows: Query<&Window, With<PrimaryWindow>>,
+  pbr_camera_entity: Res<PBRCameraEntity>,
+  mut cameras: Query<(Entity, &Camera, &RaytracedCamera, &RaytraceSettings, &mut TextureIter)>,
+  mut images: ResMut<Assets<Image>>,
+  mut sprites: Query<(&mut Sprite, &Handle<Image>), With<RaytraceDisplay>>,
+) {
+  for (entity, camera, raytraced_camera, settings, mut iter) in cameras.iter_mut() {
+    if entity == pbr_camera_entity.0 {
+      // The window camera is displayed by a sprite covering the window
+      let window_size = windows
+        .get_single()
+        .ok()
+        .map(|window| Vec2::new(window.width(), window.height()));
+      for (mut sprite, texture) in sprites.iter_mut() {
+        if *texture == raytraced_camera.image && sprite.custom_size != window_size {
+          sprite.custom_size = window_size;
+        }
+      }
+    }
+    let size = render_size(camera.physical_target_size(), settings);
+    if raytraced_camera.size(&images).map_or(true, |current| current == size) {
+      continue;
+    }
+    raytraced_camera.resize(&mut images, size);
+    iter.0 = 0;
+  }
+}
+
 pub fn rotate_light(_time: Res<Time>, mut light_dir: ResMut<LightDir>) {
   light_dir.dir[0] = 0.2;
   light_dir.dir[1] = -1.0;
   light_dir.dir[2] = -0.2;
 }
 
+#[allow(clippy::too_many_arguments)]
 pub fn reset_iter(
-  q: Query<Entity, Changed<Transform>>,
+  scene: Query<
+    (),
+    (
+      Without<Camera>,
+      Or<(Changed<GlobalTransform>, Changed<PreviousGlobalTransform>)>,
+    ),
+  >,
+  scene_filtered: Query<
+    (),
+    Or<(
+      Changed<Visibility>,
+      Changed<RenderLayers>,
+      Changed<RaytraceVisibility>,
+      Changed<Raytraced>,
+      Changed<NotRaytraced>,
+    )>,
+  >,
+  lights: Query<(), Or<(With<PointLight>, With<SpotLight>, With<DirectionalLight>)>>,
+  lights_changed: Query<(), Or<(Changed<PointLight>, Changed<SpotLight>, Changed<DirectionalLight>)>>,
+  mut light_count: Local<usize>,
+  environment: Res<RaytraceEnvironment>,
+  mut removed_raytraced: RemovedComponents<Raytraced>,
+  mut removed_not_raytraced: RemovedComponents<NotRaytraced>,
+  mut removed_lenses: RemovedComponents<ThinLensCamera>,
+  policy: Res<RaytraceMarkerPolicy>,
   m: EventReader<AssetEvent<StandardMaterial>>,
-  mut iter: ResMut<TextureIter>,
+  pipeline_ready: Res<PipelineReady>,
+  mut cameras: Query<(
+    Entity,
+    Ref<GlobalTransform>,
+    Option<Ref<PreviousGlobalTransform>>,
+    Option<Ref<ThinLensCamera>>,
+    Option<Ref<RenderLayers>>,
+    Ref<RaytraceSettings>,
+    &mut TextureIter,
+    &mut ViewMotion,
+  )>,
 ) {
-  iter.0 += 1;
-  if !q.is_empty() || !m.is_empty() {
-    iter.0 = 0;
+  let scene_moved = !scene.is_empty();
+  // Meshes joining or leaving the ray traced scene, reprojection can't hide those
+  let scene_filtered = !scene_filtered.is_empty()
+    || removed_raytraced.iter().count() + removed_not_raytraced.iter().count() > 0
+    || policy.is_changed();
+  // Removed lights only show in the count
+  let lighting_changed =
+    !lights_changed.is_empty() || lights.iter().count() != *light_count || environment.is_changed();
+  *light_count = lights.iter().count();
+  let removed_lenses = removed_lenses.iter().collect::<Vec<_>>();
+  for (entity, transform, previous, lens, render_layers, settings, mut iter, mut motion) in cameras.iter_mut() {
+    iter.0 += 1;
+    let camera_moved = transform.is_changed() || previous.map_or(false, |previous| previous.is_changed());
+    motion.set_if_neq(ViewMotion(scene_moved || camera_moved));
+    // Temporal accumulation reprojects the history on motion instead of starting over
+    let moved = (scene_moved || camera_moved) && !settings.temporal_reprojection();
+    let lens_changed = lens.map_or(false, |lens| lens.is_changed()) || removed_lenses.contains(&entity);
+    let layers_changed = render_layers.map_or(false, |render_layers| render_layers.is_changed());
+    let ready = pipeline_ready.load(Ordering::Relaxed);
+    if !ready
+      || moved
+      || lens_changed
+      || layers_changed
+      || scene_filtered
+      || lighting_changed
+      || !m.is_empty()
+      || settings.is_changed()
+    {
+      iter.0 = 0;
+    }
   }
 }
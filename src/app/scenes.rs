@@ -0,0 +1,330 @@
+use crate::loaders::rtscene::loads_as_rtscene;
+use crate::loaders::rtscene::save::save_scene;
+use crate::loaders::rtscene::types::{RtScene, RtSceneCamera, RtSceneInstance};
+use crate::render::raytracer::types::{PBRCameraEntity, ThinLensCamera};
+use bevy::asset::{FileAssetIo, LoadState};
+use bevy::ecs::event::ManualEventReader;
+use bevy::ecs::system::SystemParam;
+use bevy::prelude::*;
+use bevy::scene::SceneInstance;
+use std::path::{Path, PathBuf};
+
+pub const USAGE: &str =
+  "usage: panopticon [scene.glb|scene.obj|scene.ply|scene.rtscene.ron|scene.pbrt|scene.xml...]\n       \
+  panopticon render <scene.glb> [options]";
+
+/// Scenes given on the command line of the interactive app, relative to the working directory.
+pub fn scene_args(args: &[String]) -> Result<Vec<PathBuf>, String> {
+  args
+    .iter()
+    .map(|arg| {
+      if arg.starts_with('-') {
+        return Err(format!("unknown option {}", arg));
+      }
+      std::fs::canonicalize(arg).map_err(|err| format!("{}: {}", arg, err))
+    })
+    .collect()
+}
+
+/// Opens and closes scenes, handled by [`handle_scene_requests`]. Relative paths are relative to the asset folder.
+#[derive(Debug, Clone)]
+pub enum SceneRequest {
+  /// Unloads every open scene and opens this one.
+  Open(PathBuf),
+  /// Opens a scene next to the ones already open.
+  Add(PathBuf),
+  /// Unloads the scene spawned under this root entity.
+  Unload(Entity),
+  /// Writes the open scenes and the camera to a `.rtscene.ron` file, handled by [`save_scenes`].
+  Save(PathBuf),
+}
+
+pub struct OpenScene {
+  pub path: PathBuf,
+  /// `Handle<RtScene>` for ray traced scenes, `Handle<Scene>` otherwise.
+  pub handle: HandleUntyped,
+  /// Root entity the scene is spawned under.
+  pub entity: Entity,
+}
+
+#[derive(Resource, Default)]
+pub struct OpenScenes {
+  pub scenes: Vec<OpenScene>,
+  /// Unloaded scenes that are still loading. They stay hidden and are despawned once spawned, despawning the root
+  /// earlier would leave the scene spawner without a parent.
+  closing: Vec<OpenScene>,
+}
+
+/// Most recently opened scenes first, kept across runs in the user's config directory.
+#[derive(Resource, Default)]
+pub struct RecentScenes(pub Vec<PathBuf>);
+
+impl RecentScenes {
+  const MAX: usize = 16;
+
+  fn file() -> Option<PathBuf> {
+    let config = std::env::var_os("XDG_CONFIG_HOME")
+      .map(PathBuf::from)
+      .or_else(|| std::env::var_os("APPDATA").map(PathBuf::from))
+      .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))?;
+    Some(config.join("panopticon").join("recent_scenes"))
+  }
+
+  /// Reads the list written by a previous run, empty when there is none.
+  pub fn load() -> Self {
+    let recent = Self::file()
+      .and_then(|file| std::fs::read_to_string(file).ok())
+      .map(|text| {
+        text
+          .lines()
+          .filter(|line| !line.is_empty())
+          .map(PathBuf::from)
+          .collect()
+      })
+      .unwrap_or_default();
+    Self(recent)
+  }
+
+  /// Moves `path` to the front and writes the list.
+  pub fn push(&mut self, path: &Path) {
+    self.0.retain(|recent| recent != path);
+    self.0.insert(0, path.to_path_buf());
+    self.0.truncate(Self::MAX);
+    let Some(file) = Self::file() else {
+      return;
+    };
+    let text = self
+      .0
+      .iter()
+      .map(|path| format!("{}\n", path.display()))
+      .collect::<String>();
+    let written = file
+      .parent()
+      .map_or(Ok(()), std::fs::create_dir_all)
+      .and_then(|_| std::fs::write(&file, text));
+    if let Err(err) = written {
+      warn!("Failed to write {}: {}", file.display(), err);
+    }
+  }
+}
+
+/// Folder relative asset paths are loaded from.
+pub fn asset_folder() -> PathBuf {
+  FileAssetIo::get_base_path().join("assets")
+}
+
+/// Scene files in the asset folder, relative to it and sorted.
+pub fn asset_scenes() -> Vec<PathBuf> {
+  let root = asset_folder();
+  let mut scenes = vec![];
+  let mut directories = vec![root.clone()];
+  while let Some(directory) = directories.pop() {
+    let Ok(entries) = std::fs::read_dir(&directory) else {
+      continue;
+    };
+    for path in entries.filter_map(|entry| entry.ok()).map(|entry| entry.path()) {
+      if path.is_dir() {
+        directories.push(path);
+      } else if is_scene_file(&path) {
+        if let Ok(relative) = path.strip_prefix(&root) {
+          scenes.push(relative.to_path_buf());
+        }
+      }
+    }
+  }
+  scenes.sort();
+  scenes
+}
+
+pub fn is_scene_file(path: &Path) -> bool {
+  let extension = path.extension().and_then(|extension| extension.to_str());
+  matches!(
+    extension.map(str::to_ascii_lowercase).as_deref(),
+    Some("gltf" | "glb" | "obj" | "ply")
+  ) || loads_as_rtscene(path)
+}
+
+pub fn handle_scene_requests(
+  mut commands: Commands,
+  mut requests: EventReader<SceneRequest>,
+  asset_server: Res<AssetServer>,
+  mut open: ResMut<OpenScenes>,
+  mut recent: ResMut<RecentScenes>,
+) {
+  for request in requests.iter() {
+    match request {
+      SceneRequest::Open(path) => {
+        let closed = open.scenes.drain(..).collect::<Vec<_>>();
+        for scene in closed {
+          close(&mut commands, &mut open, scene);
+        }
+        spawn(&mut commands, &asset_server, &mut open, &mut recent, path);
+      }
+      SceneRequest::Add(path) => spawn(&mut commands, &asset_server, &mut open, &mut recent, path),
+      SceneRequest::Unload(entity) => {
+        if let Some(index) = open.scenes.iter().position(|scene| scene.entity == *entity) {
+          let scene = open.scenes.remove(index);
+          close(&mut commands, &mut open, scene);
+        }
+      }
+      SceneRequest::Save(_) => {}
+    }
+  }
+}
+
+fn spawn(
+  commands: &mut Commands,
+  asset_server: &AssetServer,
+  open: &mut OpenScenes,
+  recent: &mut RecentScenes,
+  path: &Path,
+) {
+  let (handle, entity) = spawn_scene_root(commands, asset_server, path);
+  open.scenes.push(OpenScene {
+    path: path.to_path_buf(),
+    handle,
+    entity,
+  });
+  recent.push(path);
+}
+
+/// Spawns the root entity a scene file is spawned under once loaded.
+pub fn spawn_scene_root(commands: &mut Commands, asset_server: &AssetServer, path: &Path) -> (HandleUntyped, Entity) {
+  let name = Name::new(path.display().to_string());
+  if loads_as_rtscene(path) {
+    let handle: Handle<RtScene> = asset_server.load(path);
+    let entity = commands.spawn((SpatialBundle::default(), handle.clone(), name)).id();
+    return (handle.clone_untyped(), entity);
+  }
+  let handle: Handle<Scene> = asset_server.load(format!("{}#Scene0", path.display()));
+  let entity = commands
+    .spawn((
+      SceneBundle {
+        scene: handle.clone(),
+        ..default()
+      },
+      name,
+    ))
+    .id();
+  (handle.clone_untyped(), entity)
+}
+
+fn close(commands: &mut Commands, open: &mut OpenScenes, scene: OpenScene) {
+  commands.entity(scene.entity).insert(Visibility::Hidden);
+  open.closing.push(scene);
+}
+
+/// How far spawning a scene root has come, ordered so the combined state of several entities is their maximum.
+#[derive(PartialEq, Eq, PartialOrd, Ord, Debug, Clone, Copy)]
+pub enum SpawnState {
+  Spawned,
+  Failed,
+  Loading,
+}
+
+/// Spawn state of scene roots, following ray traced scenes into the files they reference.
+#[derive(SystemParam)]
+pub struct SceneProgress<'w, 's> {
+  asset_server: Res<'w, AssetServer>,
+  scene_spawner: Res<'w, SceneSpawner>,
+  scenes: Query<'w, 's, (&'static Handle<Scene>, Option<&'static SceneInstance>)>,
+  rtscenes: Query<
+    'w,
+    's,
+    (
+      &'static Handle<RtScene>,
+      Option<&'static RtSceneInstance>,
+      Option<&'static Children>,
+    ),
+  >,
+}
+
+impl SceneProgress<'_, '_> {
+  pub fn state(&self, entity: Entity) -> SpawnState {
+    if let Ok((handle, instance)) = self.scenes.get(entity) {
+      if self.asset_server.get_load_state(handle) == LoadState::Failed {
+        return SpawnState::Failed;
+      }
+      let ready = instance.map_or(false, |instance| self.scene_spawner.instance_is_ready(**instance));
+      return if ready {
+        SpawnState::Spawned
+      } else {
+        SpawnState::Loading
+      };
+    }
+    if let Ok((handle, instance, children)) = self.rtscenes.get(entity) {
+      if self.asset_server.get_load_state(handle) == LoadState::Failed {
+        return SpawnState::Failed;
+      }
+      if instance.is_none() {
+        return SpawnState::Loading;
+      }
+      return children
+        .into_iter()
+        .flatten()
+        .map(|child| self.state(*child))
+        .max()
+        .unwrap_or(SpawnState::Spawned);
+    }
+    SpawnState::Spawned
+  }
+}
+
+/// Despawns unloaded scenes once the spawner is done with them.
+pub fn despawn_closed_scenes(mut commands: Commands, mut open: ResMut<OpenScenes>, progress: SceneProgress) {
+  if open.closing.is_empty() {
+    return;
+  }
+  open.closing.retain(|scene| {
+    let loading = progress.state(scene.entity) == SpawnState::Loading;
+    if !loading {
+      commands.entity(scene.entity).despawn_recursive();
+    }
+    loading
+  });
+}
+
+/// Handles [`SceneRequest::Save`], looking through the ray traced camera.
+pub fn save_scenes(world: &mut World, mut reader: Local<ManualEventReader<SceneRequest>>) {
+  let events = world.resource::<Events<SceneRequest>>();
+  let paths = reader
+    .iter(events)
+    .filter_map(|request| match request {
+      SceneRequest::Save(path) => Some(asset_folder().join(path)),
+      _ => None,
+    })
+    .collect::<Vec<_>>();
+  let camera = world.get_resource::<PBRCameraEntity>().map(|camera| camera.0);
+  for path in paths {
+    match save_scene(world, camera, &path) {
+      Ok(()) => info!("Saved scene to {}", path.display()),
+      Err(err) => error!("Failed to save scene to {}: {}", path.display(), err),
+    }
+  }
+}
+
+/// Moves the ray traced camera to the camera of a newly spawned ray traced scene and applies its lens and settings.
+pub fn adopt_scene_cameras(
+  mut commands: Commands,
+  pbr_camera_entity: Res<PBRCameraEntity>,
+  scene_cameras: Query<(&Transform, &Projection, &RtSceneCamera), Added<RtSceneCamera>>,
+  mut cameras: Query<(&mut Transform, &mut Projection), Without<RtSceneCamera>>,
+) {
+  let Some((transform, projection, scene_camera)) = scene_cameras.iter().last() else {
+    return;
+  };
+  let Ok((mut camera_transform, mut camera_projection)) = cameras.get_mut(pbr_camera_entity.0) else {
+    return;
+  };
+  // Scene roots are spawned at the origin, so the local transform is the global one
+  *camera_transform = *transform;
+  *camera_projection = projection.clone();
+  let mut camera = commands.entity(pbr_camera_entity.0);
+  match scene_camera.lens {
+    Some(lens) => camera.insert(lens),
+    None => camera.remove::<ThinLensCamera>(),
+  };
+  if let Some(settings) = &scene_camera.settings {
+    camera.insert(settings.clone());
+  }
+}
@@ -0,0 +1,473 @@
+use crate::app::export::{ClipboardContext, ExportFormat, ExportJob, ExportOptions, ExportTarget};
+use crate::app::scenes::{spawn_scene_root, SceneProgress, SpawnState};
+use crate::app::{reset_iter, rotate_light};
+use crate::loaders::rtscene::types::RtSceneCamera;
+use crate::loaders::LoadersPlugin;
+use crate::render::raytracer::types::{
+  PBRCameraEntity, RaytraceSettings, RaytracedCamera, RaytracedCameraBundle, SamplingMode, TextureIter, ThinLensCamera,
+};
+use crate::render::raytracer::RaytracePlugin;
+use crate::render::readback::types::ImageReadbacks;
+use crate::render::LightDir;
+use crate::util::export::{read_pfm, rmse};
+use bevy::app::{AppExit, ScheduleRunnerPlugin};
+use bevy::gltf::Gltf;
+use bevy::prelude::*;
+use bevy::render::camera::{CameraOutputMode, RenderTarget};
+use bevy::render::render_resource::{Extent3d, TextureDimension, TextureFormat, TextureUsages};
+use bevy::time::TimeUpdateStrategy;
+use bevy::transform::TransformSystem;
+use bevy::window::ExitCondition;
+use bevy::winit::WinitPlugin;
+use std::collections::VecDeque;
+use std::f32::consts::TAU;
+use std::ops::RangeInclusive;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicI32, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+pub const USAGE: &str = "usage: panopticon render <scene.glb|scene.rtscene.ron> [--spp 1024] [--size 1920x1080] \
+  [--out image.png|image.exr|image.pfm] [--half] [--aovs] [--frames 1-120] [--fps 24] [--turntable SECONDS] \
+  [--sampling random|sobol] [--reference reference.pfm]";
+
+/// Arguments of the `render` command.
+#[derive(Resource, Clone, Debug)]
+pub struct RenderArgs {
+  pub scene: PathBuf,
+  pub spp: u32,
+  pub size: UVec2,
+  pub out: PathBuf,
+  pub export: ExportOptions,
+  /// Inclusive range of frames to render as a numbered sequence, a single still otherwise.
+  pub frames: Option<RangeInclusive<u32>>,
+  pub fps: f64,
+  /// Seconds the camera takes to orbit the origin once.
+  pub turntable: Option<f32>,
+  /// Overrides the sampler of the scene's settings.
+  pub sampling: Option<SamplingMode>,
+  /// Float map the written still is compared against, the RMSE is logged.
+  pub reference: Option<PathBuf>,
+}
+
+impl RenderArgs {
+  pub fn parse(args: &[String]) -> Result<Self, String> {
+    let mut scene = None;
+    let mut spp = 256;
+    let mut size = UVec2::new(1920, 1080);
+    let mut out = PathBuf::from("render.png");
+    let mut export = ExportOptions::default();
+    let mut frames = None;
+    let mut fps = 24.0;
+    let mut turntable = None;
+    let mut sampling = None;
+    let mut reference = None;
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+      let mut value = || args.next().ok_or_else(|| format!("missing value for {}", arg));
+      match arg.as_str() {
+        "--spp" => {
+          spp = value()?
+            .parse()
+            .map_err(|_| format!("invalid sample count for {}", arg))?
+        }
+        "--size" => size = parse_size(value()?)?,
+        "--out" => out = PathBuf::from(value()?),
+        "--half" => export.half = true,
+        "--aovs" => export.aovs = true,
+        "--frames" => frames = Some(parse_frames(value()?)?),
+        "--fps" => {
+          fps = value()?
+            .parse()
+            .ok()
+            .filter(|fps: &f64| *fps > 0.0)
+            .ok_or_else(|| format!("invalid frame rate for {}", arg))?
+        }
+        "--turntable" => {
+          turntable = Some(
+            value()?
+              .parse()
+              .ok()
+              .filter(|period: &f32| *period > 0.0)
+              .ok_or_else(|| format!("invalid period for {}", arg))?,
+          )
+        }
+        "--sampling" => {
+          sampling = Some(match value()?.as_str() {
+            "random" => SamplingMode::Random,
+            "sobol" => SamplingMode::Sobol,
+            other => return Err(format!("unknown sampling {}, expected random or sobol", other)),
+          })
+        }
+        "--reference" => reference = Some(PathBuf::from(value()?)),
+        flag if flag.starts_with("--") => return Err(format!("unknown option {}", flag)),
+        path if scene.is_none() => scene = Some(PathBuf::from(path)),
+        other => return Err(format!("unexpected argument {}", other)),
+      }
+    }
+    let scene = scene.ok_or("missing scene")?;
+    // The asset server resolves relative paths against the asset folder, not the working directory
+    let scene = std::fs::canonicalize(&scene).map_err(|err| format!("{}: {}", scene.display(), err))?;
+    if spp == 0 {
+      return Err("the sample count has to be positive".into());
+    }
+    if ExportFormat::from_path(&out).is_none() {
+      return Err(format!("{}: unknown format, expected png, exr or pfm", out.display()));
+    }
+    if reference.is_some() && (frames.is_some() || ExportFormat::from_path(&out) != Some(ExportFormat::Pfm)) {
+      return Err("--reference compares a single still written as pfm".into());
+    }
+    Ok(Self {
+      scene,
+      spp,
+      size,
+      out,
+      export,
+      frames,
+      fps,
+      turntable,
+      sampling,
+      reference,
+    })
+  }
+
+  /// Output of a frame, `frame_0001.png` for `--out frame.png` when rendering a sequence.
+  pub fn frame_path(&self, frame: u32) -> PathBuf {
+    if self.frames.is_none() {
+      return self.out.clone();
+    }
+    let stem = self.out.file_stem().unwrap_or_default().to_string_lossy();
+    let extension = self.out.extension().unwrap_or_default().to_string_lossy();
+    self.out.with_file_name(format!("{}_{:04}.{}", stem, frame, extension))
+  }
+
+  /// Scene time of a frame, a still is rendered at time zero.
+  pub fn frame_time(&self, frame: u32) -> Duration {
+    Duration::from_secs_f64(frame as f64 / self.fps)
+  }
+}
+
+fn parse_frames(value: &str) -> Result<RangeInclusive<u32>, String> {
+  let invalid = || format!("invalid frame range {}, expected FIRST-LAST or FRAME", value);
+  let (first, last) = value.split_once('-').unwrap_or((value, value));
+  let frames = first.parse().map_err(|_| invalid())?..=last.parse().map_err(|_| invalid())?;
+  if frames.is_empty() {
+    return Err(invalid());
+  }
+  Ok(frames)
+}
+
+fn parse_size(value: &str) -> Result<UVec2, String> {
+  let invalid = || format!("invalid size {}, expected WIDTHxHEIGHT", value);
+  let (width, height) = value.split_once('x').ok_or_else(invalid)?;
+  let size = UVec2::new(
+    width.parse().map_err(|_| invalid())?,
+    height.parse().map_err(|_| invalid())?,
+  );
+  if size.min_element() == 0 {
+    return Err(invalid());
+  }
+  Ok(size)
+}
+
+/// Process exit code, written by the app before it sends [`AppExit`].
+#[derive(Resource, Clone, Default)]
+struct ExitStatus(Arc<AtomicI32>);
+
+#[derive(Resource)]
+struct HeadlessScene {
+  gltf: Handle<Gltf>,
+  entity: Entity,
+}
+
+/// Frames left to render, the first one is in progress.
+#[derive(Resource)]
+struct FrameQueue(VecDeque<u32>);
+
+/// Renders the scene to the sample count without opening a window and writes the image or every frame of the
+/// sequence, returns the exit code.
+pub fn run(args: RenderArgs) -> i32 {
+  let frames = match &args.frames {
+    // Frames written by an earlier run are kept, so an interrupted sequence picks up where it stopped
+    Some(frames) => {
+      let missing = frames
+        .clone()
+        .filter(|frame| !args.frame_path(*frame).exists())
+        .collect::<VecDeque<_>>();
+      let existing = frames.clone().count() - missing.len();
+      if existing > 0 {
+        info!("Skipping {} frames that already exist", existing);
+      }
+      missing
+    }
+    None => VecDeque::from([0]),
+  };
+  if frames.is_empty() {
+    return 0;
+  }
+  let status = ExitStatus::default();
+  App::new()
+    .add_plugins(
+      DefaultPlugins
+        .set(WindowPlugin {
+          primary_window: None,
+          exit_condition: ExitCondition::DontExit,
+          close_when_requested: false,
+        })
+        .set(ImagePlugin::default_nearest())
+        .disable::<WinitPlugin>(),
+    )
+    .add_plugin(ScheduleRunnerPlugin::default())
+    .init_resource::<LightDir>()
+    .insert_resource(args)
+    .insert_resource(FrameQueue(frames))
+    .insert_resource(status.clone())
+    .add_plugin(LoadersPlugin)
+    .add_plugin(RaytracePlugin)
+    .add_startup_system(setup)
+    .add_system(rotate_light)
+    .add_system(reset_iter)
+    .add_system(pose_animations)
+    .add_system(finish.after(reset_iter))
+    .add_system(
+      place_camera
+        .in_base_set(CoreSet::PostUpdate)
+        .after(TransformSystem::TransformPropagate),
+    )
+    .run();
+  status.0.load(Ordering::Relaxed)
+}
+
+fn default_view() -> Transform {
+  Transform::from_xyz(0.0, 1.2, 4.0)
+}
+
+fn setup(
+  mut commands: Commands,
+  mut images: ResMut<Assets<Image>>,
+  asset_server: Res<AssetServer>,
+  args: Res<RenderArgs>,
+  frames: Res<FrameQueue>,
+  time: Res<Time>,
+) {
+  // Time only moves from frame to frame, accumulating samples of a frame leaves the scene as it is
+  commands.insert_resource(TimeUpdateStrategy::ManualInstant(
+    time.startup() + args.frame_time(frames.0[0]),
+  ));
+
+  // Cameras are only extracted with a render target, without a window they draw into an image of the output size
+  let mut target = Image::new_fill(
+    Extent3d {
+      width: args.size.x,
+      height: args.size.y,
+      depth_or_array_layers: 1,
+    },
+    TextureDimension::D2,
+    &[0, 0, 0, 255],
+    TextureFormat::Bgra8UnormSrgb,
+  );
+  target.texture_descriptor.usage =
+    TextureUsages::RENDER_ATTACHMENT | TextureUsages::TEXTURE_BINDING | TextureUsages::COPY_DST;
+
+  let mut raytraced_camera = RaytracedCameraBundle::new(&mut images, args.size);
+  raytraced_camera.settings.resolution = Some(args.size);
+  raytraced_camera.settings.max_samples = args.spp;
+  if let Some(sampling) = args.sampling {
+    raytraced_camera.settings.sampling = sampling;
+  }
+  let camera = commands
+    .spawn((
+      Camera3dBundle {
+        camera: Camera {
+          target: RenderTarget::Image(images.add(target)),
+          output_mode: CameraOutputMode::Skip,
+          ..default()
+        },
+        transform: default_view(),
+        ..default()
+      },
+      raytraced_camera,
+    ))
+    .id();
+  commands.insert_resource(PBRCameraEntity(camera));
+
+  let (_, entity) = spawn_scene_root(&mut commands, &asset_server, &args.scene);
+  let gltf = asset_server.load(args.scene.as_path());
+  commands.insert_resource(HeadlessScene { gltf, entity });
+}
+
+/// Poses the first animation of the file at the current time. Players are kept paused so that the scene only moves
+/// when the time does.
+fn pose_animations(
+  time: Res<Time>,
+  scene: Res<HeadlessScene>,
+  gltfs: Res<Assets<Gltf>>,
+  mut players: Query<&mut AnimationPlayer>,
+) {
+  let Some(clip) = gltfs.get(&scene.gltf).and_then(|gltf| gltf.animations.first()) else {
+    return;
+  };
+  let elapsed = time.elapsed_seconds();
+  for mut player in &mut players {
+    // Players spawned with the scene start out playing
+    if !player.is_paused() {
+      player.start(clip.clone()).repeat().pause();
+    }
+    if player.elapsed() != elapsed {
+      player.set_elapsed(elapsed);
+    }
+  }
+}
+
+/// Looks through the first camera of the scene once it spawned and follows it, the default view is kept otherwise.
+/// The lens and settings of a ray traced scene's camera are applied too, apart from the size, sample count and sampler
+/// given on the command line. A turntable orbits the view around the vertical axis through the origin.
+#[allow(clippy::type_complexity)]
+fn place_camera(
+  mut commands: Commands,
+  mut adopted: Local<bool>,
+  args: Res<RenderArgs>,
+  time: Res<Time>,
+  pbr_camera_entity: Res<PBRCameraEntity>,
+  scene_cameras: Query<
+    (&GlobalTransform, &Projection, Option<&RtSceneCamera>),
+    (With<Camera3d>, Without<RaytracedCamera>),
+  >,
+  mut cameras: Query<(&mut Transform, &mut Projection, &mut RaytraceSettings), With<RaytracedCamera>>,
+) {
+  let Ok((mut camera_transform, mut camera_projection, mut camera_settings)) = cameras.get_mut(pbr_camera_entity.0)
+  else {
+    return;
+  };
+  let view = match scene_cameras.iter().next() {
+    Some((transform, projection, scene_camera)) => {
+      if !*adopted {
+        *camera_projection = projection.clone();
+        if let Some(scene_camera) = scene_camera {
+          if let Some(lens) = scene_camera.lens {
+            commands.entity(pbr_camera_entity.0).insert(lens);
+          } else {
+            commands.entity(pbr_camera_entity.0).remove::<ThinLensCamera>();
+          }
+          if let Some(settings) = &scene_camera.settings {
+            *camera_settings = RaytraceSettings {
+              resolution: camera_settings.resolution,
+              max_samples: camera_settings.max_samples,
+              sampling: args.sampling.unwrap_or(settings.sampling),
+              ..settings.clone()
+            };
+          }
+        }
+        *adopted = true;
+      }
+      transform.compute_transform()
+    }
+    None => default_view(),
+  };
+  let orbit = args.turntable.map_or(Quat::IDENTITY, |period| {
+    Quat::from_rotation_y(TAU * time.elapsed_seconds() / period)
+  });
+  // Only touch the transform when it moves, any change restarts the accumulation
+  camera_transform.set_if_neq(Transform::from_rotation(orbit) * view);
+}
+
+/// Reads the image back once the camera accumulated the requested samples of the spawned scene, then moves on to the
+/// next frame or exits.
+#[allow(clippy::too_many_arguments)]
+fn finish(
+  args: Res<RenderArgs>,
+  time: Res<Time>,
+  mut time_strategy: ResMut<TimeUpdateStrategy>,
+  mut frames: ResMut<FrameQueue>,
+  status: Res<ExitStatus>,
+  scene: Res<HeadlessScene>,
+  progress: SceneProgress,
+  pbr_camera_entity: Res<PBRCameraEntity>,
+  mut cameras: Query<(&RaytracedCamera, &RaytraceSettings, &mut TextureIter)>,
+  images: Res<Assets<Image>>,
+  mut readbacks: ResMut<ImageReadbacks>,
+  mut scene_ready: Local<bool>,
+  mut pending: Local<Option<ExportJob>>,
+  mut exit: EventWriter<AppExit>,
+) {
+  let mut exit_with = |code: i32| {
+    status.0.store(code, Ordering::Relaxed);
+    exit.send(AppExit);
+  };
+  let Ok((camera, settings, mut samples)) = cameras.get_mut(pbr_camera_entity.0) else {
+    return;
+  };
+
+  if let Some(job) = pending.as_ref() {
+    let Some(result) = job.poll(&mut readbacks, &mut ClipboardContext::default()) else {
+      return;
+    };
+    if let Err(err) = result {
+      error!("Failed to write {}: {}", job.target, err);
+      exit_with(1);
+      return;
+    }
+    info!("Wrote {}", job.target);
+    if let Some(reference) = &args.reference {
+      match compare(&args.out, reference) {
+        Ok(error) => info!("RMSE against {} at {} spp: {}", reference.display(), args.spp, error),
+        Err(err) => {
+          error!("{}", err);
+          exit_with(1);
+          return;
+        }
+      }
+    }
+    *pending = None;
+    frames.0.pop_front();
+    let Some(&frame) = frames.0.front() else {
+      exit_with(0);
+      return;
+    };
+    // The new time applies from the next update on, the accumulation restarts once the scene moved
+    *time_strategy = TimeUpdateStrategy::ManualInstant(time.startup() + args.frame_time(frame));
+    samples.0 = 0;
+    return;
+  }
+
+  if !*scene_ready {
+    let state = progress.state(scene.entity);
+    if state == SpawnState::Failed {
+      error!("Failed to load {}", args.scene.display());
+      exit_with(1);
+      return;
+    }
+    *scene_ready = state == SpawnState::Spawned;
+    // Samples taken before the scene spawned don't count
+    samples.0 = 0;
+    return;
+  }
+  if samples.0 >= args.spp {
+    let target = ExportTarget::File(args.frame_path(frames.0[0]));
+    match ExportJob::new(target, args.export, camera, &images, &mut readbacks) {
+      Ok(job) => *pending = Some(job.with_render_info(settings, samples.0)),
+      Err(err) => {
+        error!("{}", err);
+        exit_with(1);
+      }
+    }
+  }
+}
+
+/// Root mean square error of a written float map against a reference of the same size.
+fn compare(path: &Path, reference: &Path) -> Result<f32, String> {
+  let read = |path: &Path| read_pfm(path).map_err(|err| format!("{}: {}", path.display(), err));
+  let (size, rgb) = read(path)?;
+  let (reference_size, reference_rgb) = read(reference)?;
+  if size != reference_size {
+    return Err(format!(
+      "{} is {}x{}, the render {}x{}",
+      reference.display(),
+      reference_size.x,
+      reference_size.y,
+      size.x,
+      size.y
+    ));
+  }
+  Ok(rmse(&rgb, &reference_rgb))
+}
@@ -0,0 +1,551 @@
+use crate::loaders::import::{
+  add_material, camera, conductor, conductor_f0, dielectric, diffuse, emitter, load_imported, metal_f0, plastic,
+  point_light, uniform_environment, vertical_fov, Geometry, ImportedScene, ImportedShape, Report,
+};
+use crate::loaders::rtscene::types::SceneMaterial;
+use crate::render::raytracer::types::{Aperture, ThinLensCamera};
+use bevy::asset::{AssetLoader, Error, LoadContext};
+use bevy::prelude::*;
+use bevy::utils::{BoxedFuture, HashMap};
+use xml::reader::{EventReader, XmlEvent};
+
+/// Mitsuba 3 scenes: the perspective and thin lens sensors with their film size and sample count, PLY and OBJ meshes
+/// and rectangles, diffuse, conductor, dielectric and plastic BSDFs, and area, point and constant emitters. Anything
+/// else is reported and skipped.
+pub struct MitsubaLoader;
+
+impl AssetLoader for MitsubaLoader {
+  fn load<'a>(&'a self, bytes: &'a [u8], load_context: &'a mut LoadContext) -> BoxedFuture<'a, Result<(), Error>> {
+    Box::pin(async move {
+      let imported = parse_mitsuba(bytes).map_err(Error::msg)?;
+      load_imported(imported, load_context).await
+    })
+  }
+
+  fn extensions(&self) -> &[&str] {
+    &["xml"]
+  }
+}
+
+struct Element {
+  name: String,
+  attributes: HashMap<String, String>,
+  children: Vec<Element>,
+}
+
+impl Element {
+  fn attribute(&self, name: &str) -> Option<&str> {
+    self.attributes.get(name).map(String::as_str)
+  }
+
+  fn kind(&self) -> &str {
+    self.attribute("type").unwrap_or_default()
+  }
+
+  fn children<'a>(&'a self, name: &'a str) -> impl Iterator<Item = &'a Element> {
+    self.children.iter().filter(move |child| child.name == name)
+  }
+
+  /// Child holding the property `name`, such as `<float name="fov" value="45"/>`.
+  fn property(&self, name: &str) -> Option<&Element> {
+    self.children.iter().find(|child| child.attribute("name") == Some(name))
+  }
+
+  fn string(&self, name: &str) -> Option<&str> {
+    self.property(name)?.attribute("value")
+  }
+
+  fn float(&self, name: &str) -> Option<f32> {
+    self.string(name)?.trim().parse().ok()
+  }
+
+  /// RGB, grey or constant spectrum, reports spectra and textures it can't use.
+  fn color(&self, name: &str, report: &mut Report) -> Option<Vec3> {
+    let property = self.property(name)?;
+    let values = numbers(property.attribute("value").unwrap_or_default());
+    match (property.name.as_str(), &values[..]) {
+      ("rgb", &[r, g, b]) => Some(Vec3::new(r, g, b)),
+      ("rgb" | "float" | "spectrum", &[value]) => Some(Vec3::splat(value)),
+      (kind, _) => {
+        report.unsupported(format!("{} {}, using the default", kind, name));
+        None
+      }
+    }
+  }
+
+  /// Relative index of refraction of the `int_ior` and `ext_ior` properties, numbers or material names.
+  fn eta(&self, int_default: f32, report: &mut Report) -> f32 {
+    let mut ior = |name: &str, default: f32| {
+      let Some(value) = self.string(name) else {
+        return default;
+      };
+      value
+        .trim()
+        .parse()
+        .ok()
+        .or_else(|| named_ior(value))
+        .unwrap_or_else(|| {
+          report.unsupported(format!("index of refraction \"{}\", using {}", value, default));
+          default
+        })
+    };
+    ior("int_ior", int_default) / ior("ext_ior", 1.000277)
+  }
+}
+
+/// Numbers separated by commas or whitespace.
+fn numbers(text: &str) -> Vec<f32> {
+  text
+    .split(|c: char| c == ',' || c.is_whitespace())
+    .filter_map(|number| number.parse().ok())
+    .collect()
+}
+
+/// Indices of refraction of Mitsuba's named materials.
+fn named_ior(name: &str) -> Option<f32> {
+  Some(match name {
+    "vacuum" => 1.0,
+    "air" => 1.000277,
+    "water" => 1.333,
+    "acetone" => 1.36,
+    "ethanol" => 1.361,
+    "fused quartz" => 1.458,
+    "pyrex" => 1.47,
+    "acrylic glass" | "polypropylene" => 1.49,
+    "bk7" => 1.5046,
+    "sodium chloride" => 1.544,
+    "diamond" => 2.419,
+    _ => return None,
+  })
+}
+
+fn parse_xml(bytes: &[u8]) -> Result<Element, String> {
+  let mut stack = vec![];
+  for event in EventReader::new(bytes) {
+    match event.map_err(|err| err.to_string())? {
+      XmlEvent::StartElement { name, attributes, .. } => stack.push(Element {
+        name: name.local_name,
+        attributes: attributes
+          .into_iter()
+          .map(|attribute| (attribute.name.local_name, attribute.value))
+          .collect(),
+        children: vec![],
+      }),
+      XmlEvent::EndElement { .. } => {
+        let element = stack.pop().ok_or("unbalanced elements")?;
+        match stack.last_mut() {
+          Some(parent) => parent.children.push(element),
+          None => return Ok(element),
+        }
+      }
+      _ => {}
+    }
+  }
+  Err("missing root element".into())
+}
+
+/// Replaces `$name` in attributes with the value of `<default name="name" value="..."/>`.
+fn substitute(element: &mut Element, defaults: &[(String, String)]) {
+  for value in element.attributes.values_mut() {
+    for (name, default) in defaults {
+      *value = value.replace(&format!("${}", name), default);
+    }
+  }
+  for child in &mut element.children {
+    substitute(child, defaults);
+  }
+}
+
+fn parse_mitsuba(bytes: &[u8]) -> Result<ImportedScene, String> {
+  let mut root = parse_xml(bytes)?;
+  if root.name != "scene" {
+    return Err(format!("expected a Mitsuba scene, found <{}>", root.name));
+  }
+  let mut defaults = root
+    .children("default")
+    .filter_map(|default| {
+      Some((
+        default.attribute("name")?.to_string(),
+        default.attribute("value")?.to_string(),
+      ))
+    })
+    .collect::<Vec<_>>();
+  // Longest first, so $spp doesn't replace the start of $spp_preview
+  defaults.sort_by_key(|(name, _)| std::cmp::Reverse(name.len()));
+  substitute(&mut root, &defaults);
+
+  let mut scene = ImportedScene::default();
+  // Shapes refer to BSDFs by id
+  let mut bsdfs = HashMap::new();
+  for element in root.children("bsdf") {
+    let material = bsdf(element, &root, &mut scene.report);
+    let id = element.attribute("id").unwrap_or("Material");
+    bsdfs.insert(id, add_material(&mut scene.description.materials, id, material));
+  }
+  for element in &root.children {
+    match element.name.as_str() {
+      "default" | "bsdf" => {}
+      "shape" => shape(element, &root, &bsdfs, &mut scene)?,
+      "emitter" => light(element, &mut scene)?,
+      "sensor" => sensor(element, &mut scene)?,
+      name => scene
+        .report
+        .unsupported(format!("<{} type=\"{}\">, ignored", name, element.kind())),
+    }
+  }
+  Ok(scene)
+}
+
+/// The `to_world` transform of an element, the identity without one.
+fn to_world(element: &Element) -> Result<Mat4, String> {
+  let Some(transform) = element
+    .children("transform")
+    .find(|transform| transform.attribute("name") == Some("to_world"))
+  else {
+    return Ok(Mat4::IDENTITY);
+  };
+  let mut matrix = Mat4::IDENTITY;
+  for operation in &transform.children {
+    let vector = |default: f32| match numbers(operation.attribute("value").unwrap_or_default())[..] {
+      [value] => Vec3::splat(value),
+      [x, y, z] => Vec3::new(x, y, z),
+      _ => Vec3::from(["x", "y", "z"].map(|axis| {
+        operation
+          .attribute(axis)
+          .and_then(|value| value.trim().parse().ok())
+          .unwrap_or(default)
+      })),
+    };
+    let point = |name: &str| match numbers(operation.attribute(name).unwrap_or_default())[..] {
+      [x, y, z] => Ok(Vec3::new(x, y, z)),
+      _ => Err(format!("lookat is missing its {}", name)),
+    };
+    let operation_matrix = match operation.name.as_str() {
+      "translate" => Mat4::from_translation(vector(0.0)),
+      "scale" => Mat4::from_scale(vector(1.0)),
+      "rotate" => {
+        let axis = vector(0.0).try_normalize().ok_or("rotate around a zero axis")?;
+        let angle = operation
+          .attribute("angle")
+          .and_then(|angle| angle.trim().parse::<f32>().ok());
+        Mat4::from_axis_angle(axis, angle.unwrap_or_default().to_radians())
+      }
+      // Row major
+      "matrix" => {
+        let values = numbers(operation.attribute("value").unwrap_or_default());
+        if values.len() != 16 {
+          return Err("matrix takes 16 numbers".into());
+        }
+        Mat4::from_cols_slice(&values).transpose()
+      }
+      "lookat" => {
+        let origin = point("origin")?;
+        let direction = (point("target")? - origin)
+          .try_normalize()
+          .ok_or("lookat with the origin at the target")?;
+        let up = point("up").unwrap_or(Vec3::Y);
+        let left = up
+          .cross(direction)
+          .try_normalize()
+          .ok_or("lookat with the up vector along the view direction")?;
+        let up = direction.cross(left);
+        Mat4::from_cols(
+          left.extend(0.0),
+          up.extend(0.0),
+          direction.extend(0.0),
+          origin.extend(1.0),
+        )
+      }
+      name => return Err(format!("unknown transform <{}>", name)),
+    };
+    matrix = operation_matrix * matrix;
+  }
+  Ok(matrix)
+}
+
+/// BSDF elements are found by id among the children of `scene`.
+fn bsdf(element: &Element, scene: &Element, report: &mut Report) -> SceneMaterial {
+  let rough = element.kind().starts_with("rough");
+  let alpha = element
+    .float("alpha")
+    .or_else(|| Some((element.float("alpha_u")? + element.float("alpha_v")?) * 0.5))
+    .unwrap_or(if rough { 0.1 } else { 0.0 });
+  let grey = Vec3::splat(0.5);
+  match element.kind() {
+    "twosided" | "bumpmap" | "normalmap" | "mask" => {
+      if element.kind() != "twosided" {
+        report.unsupported(format!("{} BSDF, using the BSDF it wraps", element.kind()));
+      }
+      let referenced = element.children("ref").find_map(|reference| {
+        let id = reference.attribute("id")?;
+        scene.children("bsdf").find(|bsdf| bsdf.attribute("id") == Some(id))
+      });
+      match element.children("bsdf").next().or(referenced) {
+        Some(wrapped) => bsdf(wrapped, scene, report),
+        None => diffuse(grey),
+      }
+    }
+    "diffuse" => diffuse(element.color("reflectance", report).unwrap_or(grey)),
+    "conductor" | "roughconductor" => {
+      let f0 = match element.string("material") {
+        // Mitsuba's default, a perfect mirror
+        Some("none") => Vec3::ONE,
+        Some(name) => metal_f0(name).unwrap_or_else(|| {
+          report.unsupported(format!("conductor \"{}\", imported as a mirror", name));
+          Vec3::ONE
+        }),
+        None => match (element.color("eta", report), element.color("k", report)) {
+          (Some(eta), Some(k)) => conductor_f0(eta, k),
+          _ => Vec3::ONE,
+        },
+      };
+      let specular = element.color("specular_reflectance", report).unwrap_or(Vec3::ONE);
+      conductor(f0 * specular, alpha)
+    }
+    "dielectric" | "roughdielectric" | "thindielectric" => dielectric(element.eta(1.5046, report), alpha, report),
+    "plastic" | "roughplastic" => plastic(
+      element.color("diffuse_reflectance", report).unwrap_or(grey),
+      element.eta(1.49, report),
+      alpha,
+    ),
+    kind => {
+      report.unsupported(format!("BSDF \"{}\", imported as diffuse", kind));
+      diffuse(grey)
+    }
+  }
+}
+
+fn shape(
+  element: &Element,
+  root: &Element,
+  bsdfs: &HashMap<&str, String>,
+  scene: &mut ImportedScene,
+) -> Result<(), String> {
+  let report = &mut scene.report;
+  let geometry = match (element.kind(), element.string("filename")) {
+    ("ply", Some(file)) => Geometry::Ply(file.to_string()),
+    ("obj", Some(file)) => Geometry::Obj(file.to_string()),
+    (kind @ ("ply" | "obj"), None) => {
+      report.unsupported(format!("{} shape without a filename, skipped", kind));
+      return Ok(());
+    }
+    // Two units wide in the XY plane, facing +Z
+    ("rectangle", _) => Geometry::Triangles {
+      positions: vec![[-1.0, -1.0, 0.0], [1.0, -1.0, 0.0], [1.0, 1.0, 0.0], [-1.0, 1.0, 0.0]],
+      normals: vec![[0.0, 0.0, 1.0]; 4],
+      indices: vec![0, 1, 2, 0, 2, 3],
+    },
+    (kind, _) => {
+      report.unsupported(format!("shape \"{}\", skipped", kind));
+      return Ok(());
+    }
+  };
+  let materials = &mut scene.description.materials;
+  let referenced = element.children("ref").find_map(|reference| {
+    let id = reference.attribute("id")?;
+    let material = bsdfs.get(id).cloned();
+    if material.is_none() {
+      report.unsupported(format!("unknown BSDF \"{}\", using the default", id));
+    }
+    material
+  });
+  let material = match element.children("bsdf").next() {
+    Some(inline) => {
+      let material = bsdf(inline, root, report);
+      add_material(materials, element.attribute("id").unwrap_or("Material"), material)
+    }
+    None => referenced.unwrap_or_else(|| add_material(materials, "Default", diffuse(Vec3::splat(0.5)))),
+  };
+  let material = match element.children("emitter").next() {
+    Some(area) if area.kind() == "area" => {
+      let radiance = area.color("radiance", report).unwrap_or(Vec3::ONE);
+      emitter(materials, &material, radiance)
+    }
+    Some(other) => {
+      report.unsupported(format!("shape emitter \"{}\", skipped", other.kind()));
+      material
+    }
+    None => material,
+  };
+  scene.shapes.push(ImportedShape {
+    name: element.attribute("id").map(str::to_string),
+    geometry,
+    to_world: to_world(element)?,
+    material,
+  });
+  Ok(())
+}
+
+fn light(element: &Element, scene: &mut ImportedScene) -> Result<(), String> {
+  let report = &mut scene.report;
+  match element.kind() {
+    "point" => {
+      let position = match element.property("position") {
+        Some(position) => match numbers(position.attribute("value").unwrap_or_default())[..] {
+          [x, y, z] => Vec3::new(x, y, z),
+          _ => Vec3::from(["x", "y", "z"].map(|axis| {
+            position
+              .attribute(axis)
+              .and_then(|value| value.trim().parse().ok())
+              .unwrap_or_default()
+          })),
+        },
+        None => to_world(element)?.transform_point3(Vec3::ZERO),
+      };
+      let intensity = element.color("intensity", report).unwrap_or(Vec3::ONE);
+      scene.description.lights.push(point_light(position, intensity));
+    }
+    "constant" => {
+      let radiance = element.color("radiance", report).unwrap_or(Vec3::ONE);
+      scene.description.environment = Some(uniform_environment(radiance));
+    }
+    kind => report.unsupported(format!("emitter \"{}\", skipped", kind)),
+  }
+  Ok(())
+}
+
+fn sensor(element: &Element, scene: &mut ImportedScene) -> Result<(), String> {
+  if !matches!(element.kind(), "perspective" | "thinlens") {
+    scene
+      .report
+      .unsupported(format!("sensor \"{}\", imported as perspective", element.kind()));
+  }
+  let film = element.children("film").next();
+  let size = |name: &str, default: f32| film.and_then(|film| film.float(name)).unwrap_or(default);
+  let size = Vec2::new(size("width", 768.0), size("height", 576.0));
+  let samples = element
+    .children("sampler")
+    .next()
+    .and_then(|sampler| sampler.float("sample_count"))
+    .map(|samples| samples as u32);
+  // Mitsuba's default 50mm lens on a full frame sensor
+  let fov = element.float("fov").unwrap_or(39.6);
+  let length = match element.string("fov_axis").unwrap_or("x") {
+    "y" => size.y,
+    "diagonal" => size.length(),
+    "smaller" => size.min_element(),
+    "larger" => size.max_element(),
+    _ => size.x,
+  };
+  let lens = element
+    .float("aperture_radius")
+    .filter(|radius| *radius > 0.0)
+    .map(|radius| ThinLensCamera {
+      aperture: Aperture::Radius(radius),
+      focus_distance: element.float("focus_distance").unwrap_or(1e6),
+      ..default()
+    });
+  // Mitsuba cameras look along +Z with +X to the left
+  let to_world = to_world(element)? * Mat4::from_scale(Vec3::new(-1.0, 1.0, -1.0));
+  scene.description.camera = Some(camera(
+    to_world,
+    vertical_fov(fov, length, size.y),
+    lens,
+    size.as_uvec2(),
+    samples,
+  ));
+  Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+  use super::parse_mitsuba;
+  use crate::loaders::import::{metal_f0, Geometry};
+  use crate::loaders::rtscene::types::SceneProjection;
+  use bevy::prelude::*;
+
+  const SCENE: &str = r#"
+    <scene version="3.0.0">
+      <default name="spp" value="32"/>
+      <integrator type="path"/>
+      <sensor type="perspective">
+        <float name="fov" value="45"/>
+        <transform name="to_world">
+          <lookat origin="3, 0, 0" target="0, 0, 0" up="0, 1, 0"/>
+        </transform>
+        <sampler type="independent">
+          <integer name="sample_count" value="$spp"/>
+        </sampler>
+        <film type="hdrfilm">
+          <integer name="width" value="640"/>
+          <integer name="height" value="480"/>
+        </film>
+      </sensor>
+      <bsdf type="roughconductor" id="alu">
+        <string name="material" value="Al"/>
+        <float name="alpha" value="0.1"/>
+      </bsdf>
+      <bsdf type="dielectric" id="glass"/>
+      <shape type="sphere">
+        <ref id="glass"/>
+      </shape>
+      <shape type="rectangle" id="light">
+        <ref id="alu"/>
+        <emitter type="area">
+          <rgb name="radiance" value="2, 2, 2"/>
+        </emitter>
+      </shape>
+    </scene>
+  "#;
+
+  #[test]
+  fn camera() {
+    let scene = parse_mitsuba(SCENE.as_bytes()).unwrap();
+    let camera = scene.description.camera.unwrap();
+    let transform = camera.transform;
+    assert!(transform.translation.abs_diff_eq(Vec3::new(3.0, 0.0, 0.0), 1e-5));
+    assert!((transform.rotation * Vec3::NEG_Z).abs_diff_eq(Vec3::NEG_X, 1e-5));
+    assert!((transform.rotation * Vec3::Y).abs_diff_eq(Vec3::Y, 1e-5));
+    // The 45 degrees span the width
+    let SceneProjection::Perspective { fov, .. } = camera.projection else {
+      panic!("orthographic camera");
+    };
+    let expected = 2.0 * (22.5f32.to_radians().tan() * 480.0 / 640.0).atan().to_degrees();
+    assert!((fov - expected).abs() < 1e-3, "{} != {}", fov, expected);
+    let settings = camera.settings.unwrap();
+    assert_eq!(settings.resolution, Some(UVec2::new(640, 480)));
+    assert_eq!(settings.max_samples, 32);
+  }
+
+  #[test]
+  fn materials() {
+    let scene = parse_mitsuba(SCENE.as_bytes()).unwrap();
+    let materials = &scene.description.materials;
+    let alu = materials["alu"];
+    let f0 = metal_f0("Al").unwrap();
+    assert_eq!(alu.base_color, Color::rgb(f0.x, f0.y, f0.z));
+    assert_eq!(alu.metallic, 1.0);
+    assert!((alu.roughness - 0.1f32.sqrt()).abs() < 1e-5);
+    // BK7 glass in air
+    let glass = materials["glass"];
+    assert_eq!(glass.base_color, Color::BLACK);
+    assert!((glass.metallic - 0.041).abs() < 1e-3, "{}", glass.metallic);
+    assert_eq!(materials["alu emitter"].emissive, Color::rgb(2.0, 2.0, 2.0));
+  }
+
+  #[test]
+  fn rectangle_light() {
+    let scene = parse_mitsuba(SCENE.as_bytes()).unwrap();
+    let [light] = &scene.shapes[..] else {
+      panic!("{} shapes", scene.shapes.len());
+    };
+    assert_eq!(light.name.as_deref(), Some("light"));
+    assert_eq!(light.material, "alu emitter");
+    let Geometry::Triangles { positions, indices, .. } = &light.geometry else {
+      panic!("not a triangle mesh");
+    };
+    assert_eq!(positions.len(), 4);
+    assert_eq!(indices.len(), 6);
+  }
+
+  #[test]
+  fn report() {
+    let report = parse_mitsuba(SCENE.as_bytes()).unwrap().report;
+    assert!(report.0.contains_key("<integrator type=\"path\">, ignored"));
+    assert!(report.0.contains_key("shape \"sphere\", skipped"));
+    assert!(report
+      .0
+      .contains_key("transmission of dielectric materials, imported as reflective"));
+    assert_eq!(report.0.len(), 3);
+  }
+}
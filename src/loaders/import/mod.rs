@@ -0,0 +1,344 @@
+use crate::loaders::obj::parse_obj;
+use crate::loaders::ply::{parse_ply, Ply};
+use crate::loaders::rtscene::load_description;
+use crate::loaders::rtscene::types::{
+  SceneCamera, SceneDescription, SceneLight, SceneMaterial, SceneObject, SceneProjection, SceneShape, SceneTransform,
+};
+use crate::render::raytracer::types::{RaytraceEnvironment, RaytraceSettings, ThinLensCamera};
+use bevy::asset::{Error, LoadContext};
+use bevy::prelude::*;
+use std::collections::BTreeMap;
+use std::f32::consts::PI;
+use std::path::{Path, PathBuf};
+
+pub mod mitsuba;
+pub mod pbrt;
+
+/// Range of imported point lights. Other renderers don't cut lights off, this reaches across any scene that fits the
+/// view.
+const LIGHT_RANGE: f32 = 1000.0;
+
+/// Scene read from another renderer's file, its shapes still to be read and moved into place.
+#[derive(Default)]
+pub struct ImportedScene {
+  /// Camera, environment, materials and lights, objects are added from `shapes`.
+  pub description: SceneDescription,
+  pub shapes: Vec<ImportedShape>,
+  pub report: Report,
+}
+
+pub struct ImportedShape {
+  pub name: Option<String>,
+  pub geometry: Geometry,
+  /// Object to world transform, baked into the vertices.
+  pub to_world: Mat4,
+  /// Name in [`SceneDescription::materials`].
+  pub material: String,
+}
+
+pub enum Geometry {
+  Triangles {
+    positions: Vec<[f32; 3]>,
+    /// Empty for flat shading.
+    normals: Vec<[f32; 3]>,
+    indices: Vec<u32>,
+  },
+  /// PLY file relative to the scene file.
+  Ply(String),
+  /// OBJ file relative to the scene file, its groups merged into one mesh with the shape's material.
+  Obj(String),
+}
+
+/// What an importer skipped or approximated, each reported once with how often it came up.
+#[derive(Default)]
+pub struct Report(pub(crate) BTreeMap<String, usize>);
+
+impl Report {
+  pub fn unsupported(&mut self, what: impl Into<String>) {
+    *self.0.entry(what.into()).or_default() += 1;
+  }
+
+  pub fn log(&self, path: &Path) {
+    for (what, count) in &self.0 {
+      match count {
+        1 => warn!("{}: {}", path.display(), what),
+        _ => warn!("{}: {} ({} times)", path.display(), what, count),
+      }
+    }
+  }
+}
+
+/// Reads the mesh files of the shapes, bakes their transforms and sets the scene as the asset being loaded.
+pub async fn load_imported(mut imported: ImportedScene, load_context: &mut LoadContext<'_>) -> Result<(), Error> {
+  let directory = load_context.path().parent().unwrap_or(Path::new("")).to_path_buf();
+  for shape in imported.shapes {
+    let mesh = match shape.geometry {
+      Geometry::Triangles {
+        positions,
+        normals,
+        indices,
+      } => Ok(Ply {
+        positions,
+        normals: Some(normals),
+        indices,
+      }),
+      Geometry::Ply(file) => read_mesh(load_context, directory.join(file), parse_ply).await,
+      Geometry::Obj(file) => read_mesh(load_context, directory.join(file), obj_mesh).await,
+    };
+    let Ply {
+      positions,
+      normals,
+      indices,
+    } = match mesh {
+      Ok(mesh) => mesh,
+      Err(err) => {
+        imported.report.unsupported(err);
+        continue;
+      }
+    };
+    let normals = normals.unwrap_or_default();
+    if indices.len() % 3 != 0 || indices.iter().any(|index| *index as usize >= positions.len()) {
+      imported.report.unsupported("mesh with indices out of range, skipped");
+      continue;
+    }
+    imported.description.objects.push(SceneObject {
+      name: shape.name,
+      transform: default(),
+      shape: bake(positions, normals, indices, shape.to_world),
+      material: Some(shape.material),
+      visibility: default(),
+    });
+  }
+  imported.report.log(load_context.path());
+  load_description(imported.description, load_context)
+}
+
+async fn read_mesh(
+  load_context: &LoadContext<'_>,
+  path: PathBuf,
+  parse: fn(&[u8]) -> Result<Ply, String>,
+) -> Result<Ply, String> {
+  load_context
+    .read_asset_bytes(&path)
+    .await
+    .map_err(|err| err.to_string())
+    .and_then(|bytes| parse(&bytes))
+    .map_err(|err| format!("failed to read {}, skipped: {}", path.display(), err))
+}
+
+/// The groups of an OBJ file as one mesh, with flat normals unless every group has normals.
+fn obj_mesh(bytes: &[u8]) -> Result<Ply, String> {
+  let obj = parse_obj(&String::from_utf8_lossy(bytes))?;
+  let smooth = obj.groups.iter().all(|group| group.normals.is_some());
+  let mut mesh = Ply {
+    positions: vec![],
+    normals: smooth.then(Vec::new),
+    indices: vec![],
+  };
+  for group in obj.groups {
+    let offset = mesh.positions.len() as u32;
+    mesh.indices.extend(group.indices.iter().map(|index| index + offset));
+    mesh.positions.extend(group.positions);
+    if let (Some(normals), Some(group_normals)) = (&mut mesh.normals, group.normals) {
+      normals.extend(group_normals);
+    }
+  }
+  Ok(mesh)
+}
+
+/// Triangle mesh moved into world space.
+fn bake(positions: Vec<[f32; 3]>, normals: Vec<[f32; 3]>, mut indices: Vec<u32>, to_world: Mat4) -> SceneShape {
+  let normal_matrix = Mat3::from_mat4(to_world).inverse().transpose();
+  let normals = if normals.len() == positions.len() {
+    normals
+      .iter()
+      .map(|normal| (normal_matrix * Vec3::from(*normal)).normalize_or_zero().to_array())
+      .collect()
+  } else {
+    vec![]
+  };
+  let positions = positions
+    .iter()
+    .map(|position| to_world.transform_point3(Vec3::from(*position)).to_array())
+    .collect();
+  // A mirroring transform turns the triangles inside out, flat normals would point into the mesh
+  if to_world.determinant() < 0.0 {
+    for triangle in indices.chunks_exact_mut(3) {
+      triangle.swap(1, 2);
+    }
+  }
+  SceneShape::Mesh {
+    positions,
+    normals,
+    indices,
+  }
+}
+
+/// Adds the material under `name`, or a numbered variant of it when a different material has that name. Returns the
+/// name it is found under.
+pub fn add_material(materials: &mut BTreeMap<String, SceneMaterial>, name: &str, material: SceneMaterial) -> String {
+  let name = (0..)
+    .map(|i| match i {
+      0 => name.to_string(),
+      i => format!("{}{}", name, i),
+    })
+    .find(|name| materials.get(name).map_or(true, |existing| *existing == material))
+    .unwrap();
+  materials.insert(name.clone(), material);
+  name
+}
+
+/// Camera looking along -Z of `to_world` with a vertical field of view of `fov` degrees, rendering `samples` per pixel
+/// at `resolution`.
+pub fn camera(
+  to_world: Mat4,
+  fov: f32,
+  lens: Option<ThinLensCamera>,
+  resolution: UVec2,
+  samples: Option<u32>,
+) -> SceneCamera {
+  let perspective = PerspectiveProjection::default();
+  let settings = RaytraceSettings::default();
+  SceneCamera {
+    transform: SceneTransform {
+      scale: Vec3::ONE,
+      ..Transform::from_matrix(to_world).into()
+    },
+    projection: SceneProjection::Perspective {
+      fov,
+      near: perspective.near,
+      far: perspective.far,
+    },
+    lens,
+    settings: Some(RaytraceSettings {
+      resolution: Some(resolution),
+      max_samples: samples.unwrap_or(settings.max_samples),
+      ..settings
+    }),
+  }
+}
+
+/// Vertical field of view in degrees of a view `height` high whose `fov` spans `length`.
+pub fn vertical_fov(fov: f32, length: f32, height: f32) -> f32 {
+  2.0 * ((fov.to_radians() * 0.5).tan() * height / length).atan().to_degrees()
+}
+
+/// Perceptual roughness of a GGX alpha.
+pub fn roughness(alpha: f32) -> f32 {
+  alpha.max(0.0).sqrt().min(1.0)
+}
+
+/// Bevy's reflectance of the Fresnel term at normal incidence, 0.16 * reflectance^2.
+pub fn reflectance(f0: f32) -> f32 {
+  (f0 / 0.16).sqrt().clamp(0.0, 1.0)
+}
+
+/// Fresnel reflectance at normal incidence of a dielectric with relative index of refraction `eta`.
+pub fn dielectric_f0(eta: f32) -> f32 {
+  ((eta - 1.0) / (eta + 1.0)).powi(2)
+}
+
+pub fn diffuse(color: Vec3) -> SceneMaterial {
+  SceneMaterial {
+    base_color: Color::rgb(color.x, color.y, color.z),
+    emissive: Color::BLACK,
+    roughness: 1.0,
+    metallic: 0.0,
+    reflectance: 0.0,
+  }
+}
+
+/// Metal reflecting `f0` at normal incidence.
+pub fn conductor(f0: Vec3, alpha: f32) -> SceneMaterial {
+  SceneMaterial {
+    base_color: Color::rgb(f0.x, f0.y, f0.z),
+    emissive: Color::BLACK,
+    roughness: roughness(alpha),
+    metallic: 1.0,
+    reflectance: 0.5,
+  }
+}
+
+/// Fresnel reflectance at normal incidence of a conductor with index of refraction `eta` and absorption `k`.
+pub fn conductor_f0(eta: Vec3, k: Vec3) -> Vec3 {
+  ((eta - 1.0) * (eta - 1.0) + k * k) / ((eta + 1.0) * (eta + 1.0) + k * k)
+}
+
+/// Reflectance at normal incidence of the metals other renderers know by name.
+pub fn metal_f0(name: &str) -> Option<Vec3> {
+  Some(match name {
+    "Ag" => Vec3::new(0.972, 0.960, 0.915),
+    "Al" => Vec3::new(0.913, 0.922, 0.924),
+    "Au" => Vec3::new(1.000, 0.782, 0.344),
+    "Cr" => Vec3::new(0.549, 0.556, 0.554),
+    "Cu" => Vec3::new(0.955, 0.638, 0.538),
+    "CuZn" => Vec3::new(0.910, 0.778, 0.423),
+    "Fe" => Vec3::new(0.562, 0.565, 0.578),
+    "Ni" => Vec3::new(0.660, 0.609, 0.526),
+    "Pt" => Vec3::new(0.673, 0.637, 0.585),
+    "Ti" => Vec3::new(0.542, 0.497, 0.449),
+    _ => return None,
+  })
+}
+
+/// Glass with relative index of refraction `eta`. The ray tracer has no transmission, it becomes black glass taking
+/// the mirror bounce as often as its Fresnel reflectance at normal incidence asks for.
+pub fn dielectric(eta: f32, alpha: f32, report: &mut Report) -> SceneMaterial {
+  report.unsupported("transmission of dielectric materials, imported as reflective");
+  let f0 = dielectric_f0(eta);
+  SceneMaterial {
+    base_color: Color::BLACK,
+    emissive: Color::BLACK,
+    roughness: roughness(alpha),
+    metallic: f0,
+    reflectance: reflectance(f0),
+  }
+}
+
+/// Diffuse base under a dielectric coating, the coating's Fresnel reflectance is the chance of a specular bounce.
+pub fn plastic(color: Vec3, eta: f32, alpha: f32) -> SceneMaterial {
+  let f0 = dielectric_f0(eta);
+  SceneMaterial {
+    base_color: Color::rgb(color.x, color.y, color.z),
+    emissive: Color::BLACK,
+    roughness: roughness(alpha),
+    metallic: f0,
+    reflectance: reflectance(f0),
+  }
+}
+
+/// `material` emitting `radiance`, added next to it for area lights.
+pub fn emitter(materials: &mut BTreeMap<String, SceneMaterial>, material: &str, radiance: Vec3) -> String {
+  let emissive = SceneMaterial {
+    emissive: Color::rgb(radiance.x, radiance.y, radiance.z),
+    ..materials.get(material).copied().unwrap_or_default()
+  };
+  add_material(materials, &format!("{} emitter", material), emissive)
+}
+
+/// Linear color with its brightest channel at one, and the brightness.
+fn split_rgb(rgb: Vec3) -> (Color, f32) {
+  let max = rgb.max_element();
+  if max <= 0.0 {
+    return (Color::BLACK, 0.0);
+  }
+  let color = rgb / max;
+  (Color::rgb_linear(color.x, color.y, color.z), max)
+}
+
+/// Point light of radiant intensity `intensity` in W/sr.
+pub fn point_light(position: Vec3, intensity: Vec3) -> SceneLight {
+  let (color, intensity) = split_rgb(intensity);
+  // The ray tracer divides lumens by 4π like Bevy does
+  SceneLight::Point {
+    position,
+    color,
+    intensity: intensity * 4.0 * PI,
+    range: Some(LIGHT_RANGE),
+  }
+}
+
+pub fn uniform_environment(radiance: Vec3) -> RaytraceEnvironment {
+  let (color, intensity) = split_rgb(radiance);
+  RaytraceEnvironment::Uniform { color, intensity }
+}
@@ -0,0 +1,613 @@
+use crate::loaders::import::{
+  add_material, camera, conductor, conductor_f0, dielectric, diffuse, emitter, load_imported, metal_f0, plastic,
+  point_light, uniform_environment, vertical_fov, Geometry, ImportedScene, ImportedShape, Report,
+};
+use crate::loaders::rtscene::types::SceneMaterial;
+use crate::render::raytracer::types::{Aperture, ThinLensCamera};
+use bevy::asset::{AssetLoader, Error, LoadContext};
+use bevy::prelude::*;
+use bevy::utils::{BoxedFuture, HashMap};
+use std::f32::consts::PI;
+
+/// pbrt-v4 scenes: the perspective camera, film resolution and sample count, triangle and PLY meshes, diffuse,
+/// conductor, dielectric and coated diffuse materials, and diffuse area, point and uniform infinite lights. Anything
+/// else is reported and skipped.
+pub struct PbrtLoader;
+
+impl AssetLoader for PbrtLoader {
+  fn load<'a>(&'a self, bytes: &'a [u8], load_context: &'a mut LoadContext) -> BoxedFuture<'a, Result<(), Error>> {
+    Box::pin(async move {
+      let imported = parse_pbrt(&String::from_utf8_lossy(bytes)).map_err(Error::msg)?;
+      load_imported(imported, load_context).await
+    })
+  }
+
+  fn extensions(&self) -> &[&str] {
+    &["pbrt"]
+  }
+}
+
+/// pbrt's world is left-handed, mirroring X keeps images the right way round in Bevy's right-handed one.
+const MIRROR: Mat4 = Mat4::from_cols(Vec4::NEG_X, Vec4::Y, Vec4::Z, Vec4::W);
+
+#[derive(Debug)]
+enum Token {
+  Name(String),
+  String(String),
+  Number(f64),
+  Bool(bool),
+  Open,
+  Close,
+}
+
+fn tokenize(text: &str) -> Result<Vec<Token>, String> {
+  let mut tokens = vec![];
+  let mut chars = text.char_indices().peekable();
+  while let Some((start, c)) = chars.next() {
+    match c {
+      c if c.is_whitespace() => {}
+      '#' => {
+        for (_, c) in chars.by_ref() {
+          if c == '\n' {
+            break;
+          }
+        }
+      }
+      '[' => tokens.push(Token::Open),
+      ']' => tokens.push(Token::Close),
+      '"' => {
+        let mut string = String::new();
+        loop {
+          match chars.next() {
+            Some((_, '"')) => break,
+            Some((_, c)) => string.push(c),
+            None => return Err("unterminated string".into()),
+          }
+        }
+        tokens.push(Token::String(string));
+      }
+      _ => {
+        let mut end = start + c.len_utf8();
+        while let Some((i, c)) = chars.next_if(|(_, c)| !c.is_whitespace() && !matches!(*c, '[' | ']' | '"' | '#')) {
+          end = i + c.len_utf8();
+        }
+        let word = &text[start..end];
+        tokens.push(match word {
+          "true" => Token::Bool(true),
+          "false" => Token::Bool(false),
+          _ => word
+            .parse()
+            .map_or_else(|_| Token::Name(word.to_string()), Token::Number),
+        });
+      }
+    }
+  }
+  Ok(tokens)
+}
+
+enum Value {
+  Number(f64),
+  String(String),
+  Bool(bool),
+}
+
+/// Parameter lists of a directive, `"rgb L" [1 1 1]` is found under `L`.
+#[derive(Default)]
+struct Params(HashMap<String, (String, Vec<Value>)>);
+
+impl Params {
+  fn numbers(&self, name: &str) -> Option<Vec<f32>> {
+    let (_, values) = self.0.get(name)?;
+    let numbers = values.iter().filter_map(|value| match value {
+      Value::Number(number) => Some(*number as f32),
+      _ => None,
+    });
+    Some(numbers.collect())
+  }
+
+  fn float(&self, name: &str) -> Option<f32> {
+    self.numbers(name)?.first().copied()
+  }
+
+  fn int(&self, name: &str) -> Option<u32> {
+    self.float(name).map(|value| value as u32)
+  }
+
+  fn ints(&self, name: &str) -> Option<Vec<u32>> {
+    Some(self.numbers(name)?.into_iter().map(|value| value as u32).collect())
+  }
+
+  fn vec3s(&self, name: &str) -> Option<Vec<[f32; 3]>> {
+    let numbers = self.numbers(name)?;
+    Some(numbers.chunks_exact(3).map(|xyz| [xyz[0], xyz[1], xyz[2]]).collect())
+  }
+
+  fn point(&self, name: &str) -> Option<Vec3> {
+    self.vec3s(name)?.first().copied().map(Vec3::from)
+  }
+
+  fn string(&self, name: &str) -> Option<&str> {
+    let (_, values) = self.0.get(name)?;
+    values.iter().find_map(|value| match value {
+      Value::String(string) => Some(string.as_str()),
+      _ => None,
+    })
+  }
+
+  fn bool(&self, name: &str) -> Option<bool> {
+    let (_, values) = self.0.get(name)?;
+    values.iter().find_map(|value| match value {
+      Value::Bool(value) => Some(*value),
+      Value::String(string) => string.parse().ok(),
+      _ => None,
+    })
+  }
+
+  /// RGB or constant spectrum, reports spectra and textures it can't use.
+  fn color(&self, name: &str, report: &mut Report) -> Option<Vec3> {
+    let (kind, _) = self.0.get(name)?;
+    match (kind.as_str(), &self.numbers(name)?[..]) {
+      ("rgb" | "color", &[r, g, b]) => Some(Vec3::new(r, g, b)),
+      ("spectrum" | "float", &[value]) => Some(Vec3::splat(value)),
+      _ => {
+        report.unsupported(format!("{} parameter {}, using the default", kind, name));
+        None
+      }
+    }
+  }
+}
+
+/// Directive with its arguments, `Shape "trianglemesh" "point3 P" [...]` has the string `trianglemesh`.
+struct Directive {
+  name: String,
+  numbers: Vec<f32>,
+  strings: Vec<String>,
+  params: Params,
+}
+
+impl Directive {
+  fn numbers<const N: usize>(&self) -> Result<[f32; N], String> {
+    self
+      .numbers
+      .clone()
+      .try_into()
+      .map_err(|_| format!("{} takes {} numbers", self.name, N))
+  }
+
+  fn string(&self) -> Result<&str, String> {
+    match self.strings.first() {
+      Some(string) => Ok(string),
+      None => Err(format!("{} is missing its name", self.name)),
+    }
+  }
+}
+
+fn directives(tokens: Vec<Token>) -> Result<Vec<Directive>, String> {
+  let mut tokens = tokens.into_iter().peekable();
+  let mut directives = vec![];
+  while let Some(token) = tokens.next() {
+    let Token::Name(name) = token else {
+      return Err(format!("expected a directive, found {:?}", token));
+    };
+    // The strings in front of the parameter lists, the type of most directives
+    let positional = match name.as_str() {
+      "Option" => 0,
+      "Texture" => 3,
+      _ => 1,
+    };
+    let mut directive = Directive {
+      name,
+      numbers: vec![],
+      strings: vec![],
+      params: default(),
+    };
+    while let Some(token) = tokens.next_if(|token| !matches!(token, Token::Name(_))) {
+      match token {
+        Token::Number(number) => directive.numbers.push(number as f32),
+        Token::Open => {
+          for value in list(&mut tokens)? {
+            match value {
+              Value::Number(number) => directive.numbers.push(number as f32),
+              _ => return Err(format!("{} takes a list of numbers", directive.name)),
+            }
+          }
+        }
+        Token::String(string) if directive.strings.len() < positional => directive.strings.push(string),
+        Token::String(declaration) => {
+          let mut words = declaration.split_whitespace();
+          let (Some(kind), Some(name), None) = (words.next(), words.next(), words.next()) else {
+            return Err(format!("invalid parameter \"{}\"", declaration));
+          };
+          let values = match tokens.next() {
+            Some(Token::Open) => list(&mut tokens)?,
+            Some(token) => vec![value(token)?],
+            None => return Err(format!("missing value of \"{}\"", declaration)),
+          };
+          directive.params.0.insert(name.to_string(), (kind.to_string(), values));
+        }
+        token => return Err(format!("unexpected {:?} after {}", token, directive.name)),
+      }
+    }
+    directives.push(directive);
+  }
+  Ok(directives)
+}
+
+/// Values up to the closing bracket.
+fn list(tokens: &mut impl Iterator<Item = Token>) -> Result<Vec<Value>, String> {
+  let mut values = vec![];
+  loop {
+    match tokens.next() {
+      Some(Token::Close) => return Ok(values),
+      Some(token) => values.push(value(token)?),
+      None => return Err("unterminated list".into()),
+    }
+  }
+}
+
+fn value(token: Token) -> Result<Value, String> {
+  match token {
+    Token::Number(number) => Ok(Value::Number(number)),
+    Token::String(string) => Ok(Value::String(string)),
+    Token::Bool(value) => Ok(Value::Bool(value)),
+    token => Err(format!("unexpected {:?} in a parameter list", token)),
+  }
+}
+
+#[derive(Clone)]
+struct GraphicsState {
+  ctm: Mat4,
+  /// Name in the description's materials, the default material until one is set.
+  material: Option<String>,
+  /// Radiance of the area light the following shapes emit.
+  emission: Option<Vec3>,
+}
+
+enum Saved {
+  Attributes(GraphicsState),
+  Transform(Mat4),
+}
+
+fn parse_pbrt(text: &str) -> Result<ImportedScene, String> {
+  let mut scene = ImportedScene::default();
+  let mut state = GraphicsState {
+    ctm: Mat4::IDENTITY,
+    material: None,
+    emission: None,
+  };
+  let mut stack = vec![];
+  let mut coordinate_systems = HashMap::new();
+  let mut named_materials = HashMap::new();
+  let mut camera_directive = None;
+  let mut resolution = UVec2::new(1280, 720);
+  let mut samples = None;
+  for directive in directives(tokenize(text)?)? {
+    let params = &directive.params;
+    let materials = &mut scene.description.materials;
+    let report = &mut scene.report;
+    match directive.name.as_str() {
+      "Identity" => state.ctm = Mat4::IDENTITY,
+      "Translate" => state.ctm *= Mat4::from_translation(Vec3::from(directive.numbers::<3>()?)),
+      "Scale" => state.ctm *= Mat4::from_scale(Vec3::from(directive.numbers::<3>()?)),
+      "Rotate" => {
+        let [angle, x, y, z] = directive.numbers::<4>()?;
+        let axis = Vec3::new(x, y, z).try_normalize().ok_or("Rotate around a zero axis")?;
+        state.ctm *= Mat4::from_axis_angle(axis, angle.to_radians());
+      }
+      "LookAt" => {
+        let [ex, ey, ez, tx, ty, tz, ux, uy, uz] = directive.numbers::<9>()?;
+        state.ctm *= look_at(Vec3::new(ex, ey, ez), Vec3::new(tx, ty, tz), Vec3::new(ux, uy, uz))?;
+      }
+      "Transform" => state.ctm = Mat4::from_cols_array(&directive.numbers::<16>()?),
+      "ConcatTransform" => state.ctm *= Mat4::from_cols_array(&directive.numbers::<16>()?),
+      "CoordinateSystem" => {
+        coordinate_systems.insert(directive.string()?.to_string(), state.ctm);
+      }
+      "CoordSysTransform" => match coordinate_systems.get(directive.string()?) {
+        Some(ctm) => state.ctm = *ctm,
+        None => report.unsupported(format!("unknown coordinate system \"{}\"", directive.string()?)),
+      },
+      "Camera" => {
+        let camera_to_world = state.ctm.inverse();
+        coordinate_systems.insert("camera".to_string(), camera_to_world);
+        camera_directive = Some((camera_to_world, directive));
+      }
+      "Film" => {
+        resolution = UVec2::new(
+          params.int("xresolution").unwrap_or(resolution.x),
+          params.int("yresolution").unwrap_or(resolution.y),
+        );
+      }
+      "Sampler" => samples = params.int("pixelsamples").or(Some(16)),
+      "WorldBegin" => {
+        state.ctm = Mat4::IDENTITY;
+        coordinate_systems.insert("world".to_string(), Mat4::IDENTITY);
+      }
+      // pbrt-v3 files end the world explicitly
+      "WorldEnd" => {}
+      "AttributeBegin" => stack.push(Saved::Attributes(state.clone())),
+      "AttributeEnd" => match stack.pop() {
+        Some(Saved::Attributes(saved)) => state = saved,
+        _ => return Err("AttributeEnd without AttributeBegin".into()),
+      },
+      "TransformBegin" => stack.push(Saved::Transform(state.ctm)),
+      "TransformEnd" => match stack.pop() {
+        Some(Saved::Transform(ctm)) => state.ctm = ctm,
+        _ => return Err("TransformEnd without TransformBegin".into()),
+      },
+      "Material" => {
+        let material = material(directive.string()?, params, report);
+        state.material = Some(add_material(materials, "Material", material));
+      }
+      "MakeNamedMaterial" => {
+        let name = directive.string()?;
+        let material = material(params.string("type").unwrap_or_default(), params, report);
+        named_materials.insert(name.to_string(), add_material(materials, name, material));
+      }
+      "NamedMaterial" => {
+        let name = directive.string()?;
+        state.material = named_materials.get(name).cloned();
+        if state.material.is_none() {
+          report.unsupported(format!("unknown material \"{}\", using the default", name));
+        }
+      }
+      "AreaLightSource" => match directive.string()? {
+        "diffuse" => {
+          let radiance = params.color("L", report).unwrap_or(Vec3::ONE);
+          state.emission = Some(radiance * params.float("scale").unwrap_or(1.0));
+        }
+        kind => report.unsupported(format!("area light \"{}\", skipped", kind)),
+      },
+      "LightSource" => {
+        let scale = params.float("scale").unwrap_or(1.0);
+        match directive.string()? {
+          "point" => {
+            let mut intensity = params.color("I", report).unwrap_or(Vec3::ONE) * scale;
+            // Radiant power over the sphere, of the brightest channel
+            if let Some(power) = params.float("power").filter(|_| intensity.max_element() > 0.0) {
+              intensity *= power / (4.0 * PI * intensity.max_element());
+            }
+            let position = (MIRROR * state.ctm).transform_point3(params.point("from").unwrap_or_default());
+            scene.description.lights.push(point_light(position, intensity));
+          }
+          "infinite" if params.string("filename").is_some() => {
+            report.unsupported("infinite light with an image map, skipped");
+          }
+          "infinite" => {
+            let radiance = params.color("L", report).unwrap_or(Vec3::ONE) * scale;
+            scene.description.environment = Some(uniform_environment(radiance));
+          }
+          kind => report.unsupported(format!("light \"{}\", skipped", kind)),
+        }
+      }
+      "Shape" => {
+        let geometry = match directive.string()? {
+          "trianglemesh" => {
+            let positions = params.vec3s("P").unwrap_or_default();
+            Geometry::Triangles {
+              // A single triangle may leave its indices out
+              indices: params.ints("indices").unwrap_or_else(|| match positions.len() {
+                3 => vec![0, 1, 2],
+                _ => vec![],
+              }),
+              normals: params.vec3s("N").unwrap_or_default(),
+              positions,
+            }
+          }
+          "plymesh" => match params.string("filename") {
+            Some(file) => Geometry::Ply(file.to_string()),
+            None => {
+              report.unsupported("plymesh without a filename, skipped");
+              continue;
+            }
+          },
+          kind => {
+            report.unsupported(format!("shape \"{}\", skipped", kind));
+            continue;
+          }
+        };
+        let material = state
+          .material
+          .clone()
+          .unwrap_or_else(|| add_material(materials, "Default", diffuse(Vec3::splat(0.5))));
+        let material = match state.emission {
+          Some(radiance) => emitter(materials, &material, radiance),
+          None => material,
+        };
+        scene.shapes.push(ImportedShape {
+          name: None,
+          geometry,
+          to_world: MIRROR * state.ctm,
+          material,
+        });
+      }
+      name => report.unsupported(format!("directive {}, ignored", name)),
+    }
+  }
+
+  if let Some((camera_to_world, directive)) = camera_directive {
+    let params = &directive.params;
+    if directive.string()? != "perspective" {
+      scene
+        .report
+        .unsupported(format!("camera \"{}\", imported as perspective", directive.string()?));
+    }
+    // The field of view spans the shorter side of the image
+    let size = resolution.as_vec2();
+    let fov = vertical_fov(params.float("fov").unwrap_or(90.0), size.min_element(), size.y);
+    let lens = params
+      .float("lensradius")
+      .filter(|radius| *radius > 0.0)
+      .map(|radius| ThinLensCamera {
+        aperture: Aperture::Radius(radius),
+        focus_distance: params.float("focaldistance").unwrap_or(1e6),
+        ..default()
+      });
+    // pbrt cameras look along +Z
+    let to_world = MIRROR * camera_to_world * Mat4::from_scale(Vec3::new(1.0, 1.0, -1.0));
+    scene.description.camera = Some(camera(to_world, fov, lens, resolution, samples));
+  }
+  Ok(scene)
+}
+
+/// World to camera transform of pbrt's LookAt, the camera looks along +Z with +X to the right.
+fn look_at(eye: Vec3, target: Vec3, up: Vec3) -> Result<Mat4, String> {
+  let direction = (target - eye)
+    .try_normalize()
+    .ok_or("LookAt with the eye at the target")?;
+  let right = up
+    .normalize_or_zero()
+    .cross(direction)
+    .try_normalize()
+    .ok_or("LookAt with the up vector along the view direction")?;
+  let up = direction.cross(right);
+  Ok(
+    Mat4::from_cols(
+      right.extend(0.0),
+      up.extend(0.0),
+      direction.extend(0.0),
+      eye.extend(1.0),
+    )
+    .inverse(),
+  )
+}
+
+fn material(kind: &str, params: &Params, report: &mut Report) -> SceneMaterial {
+  let roughness = params.float("roughness").unwrap_or_else(|| {
+    let u = params.float("uroughness").unwrap_or(0.0);
+    let v = params.float("vroughness").unwrap_or(0.0);
+    (u + v) * 0.5
+  });
+  // pbrt maps roughness to alpha = sqrt(roughness) unless told otherwise
+  let alpha = if params.bool("remaproughness").unwrap_or(true) {
+    roughness.sqrt()
+  } else {
+    roughness
+  };
+  match kind {
+    "diffuse" => diffuse(params.color("reflectance", report).unwrap_or(Vec3::splat(0.5))),
+    "coateddiffuse" => plastic(
+      params.color("reflectance", report).unwrap_or(Vec3::splat(0.5)),
+      params.float("eta").unwrap_or(1.5),
+      alpha,
+    ),
+    "conductor" => conductor(conductor_reflectance(params, report), alpha),
+    "dielectric" | "thindielectric" => dielectric(params.float("eta").unwrap_or(1.5), alpha, report),
+    kind => {
+      report.unsupported(format!("material \"{}\", imported as diffuse", kind));
+      diffuse(Vec3::splat(0.5))
+    }
+  }
+}
+
+fn conductor_reflectance(params: &Params, report: &mut Report) -> Vec3 {
+  let copper = metal_f0("Cu").unwrap();
+  if params.0.contains_key("reflectance") {
+    return params.color("reflectance", report).unwrap_or(copper);
+  }
+  // Named spectra such as metal-Au-eta carry the metal in their name
+  if let Some(spectrum) = params.string("eta") {
+    let metal = spectrum
+      .strip_prefix("metal-")
+      .and_then(|metal| metal.strip_suffix("-eta"));
+    return metal.and_then(metal_f0).unwrap_or_else(|| {
+      report.unsupported(format!("conductor spectrum \"{}\", imported as copper", spectrum));
+      copper
+    });
+  }
+  match (params.color("eta", report), params.color("k", report)) {
+    (Some(eta), Some(k)) => conductor_f0(eta, k),
+    // pbrt's default conductor
+    _ => copper,
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::parse_pbrt;
+  use crate::loaders::import::{bake, metal_f0, Geometry};
+  use crate::loaders::rtscene::types::{SceneProjection, SceneShape};
+  use bevy::prelude::*;
+
+  const SCENE: &str = r#"
+    LookAt 3 0 0  0 0 0  0 1 0
+    Camera "perspective" "float fov" 40
+    Film "rgb" "integer xresolution" 300 "integer yresolution" 400
+    Sampler "halton" "integer pixelsamples" 64
+    Integrator "volpath"
+    WorldBegin
+    Material "conductor" "spectrum eta" "metal-Au-eta" "spectrum k" "metal-Au-k" "float roughness" 0.01
+    Shape "trianglemesh" "point3 P" [0 0 0 1 0 0 0 1 0] "integer indices" [0 1 2]
+    Material "dielectric" "float eta" 1.5
+    Shape "sphere"
+  "#;
+
+  #[test]
+  fn camera() {
+    let scene = parse_pbrt(SCENE).unwrap();
+    let camera = scene.description.camera.unwrap();
+    // pbrt's x axis points the other way
+    let transform = camera.transform;
+    assert!(transform.translation.abs_diff_eq(Vec3::new(-3.0, 0.0, 0.0), 1e-5));
+    assert!((transform.rotation * Vec3::NEG_Z).abs_diff_eq(Vec3::X, 1e-5));
+    assert!((transform.rotation * Vec3::Y).abs_diff_eq(Vec3::Y, 1e-5));
+    // The 40 degrees span the narrower width
+    let SceneProjection::Perspective { fov, .. } = camera.projection else {
+      panic!("orthographic camera");
+    };
+    let expected = 2.0 * (20f32.to_radians().tan() * 400.0 / 300.0).atan().to_degrees();
+    assert!((fov - expected).abs() < 1e-3, "{} != {}", fov, expected);
+    let settings = camera.settings.unwrap();
+    assert_eq!(settings.resolution, Some(UVec2::new(300, 400)));
+    assert_eq!(settings.max_samples, 64);
+  }
+
+  #[test]
+  fn materials() {
+    let scene = parse_pbrt(SCENE).unwrap();
+    let [triangle] = &scene.shapes[..] else {
+      panic!("{} shapes", scene.shapes.len());
+    };
+    let gold = scene.description.materials[&triangle.material];
+    let f0 = metal_f0("Au").unwrap();
+    assert_eq!(gold.base_color, Color::rgb(f0.x, f0.y, f0.z));
+    assert_eq!(gold.metallic, 1.0);
+    // Roughness 0.01 is an alpha of 0.1
+    assert!((gold.roughness - 0.1f32.sqrt()).abs() < 1e-5);
+    let glass = scene.description.materials["Material1"];
+    assert_eq!(glass.base_color, Color::BLACK);
+    assert!((glass.metallic - 0.04).abs() < 1e-5);
+  }
+
+  #[test]
+  fn report() {
+    let report = parse_pbrt(SCENE).unwrap().report;
+    assert!(report.0.contains_key("directive Integrator, ignored"));
+    assert!(report.0.contains_key("shape \"sphere\", skipped"));
+    assert!(report
+      .0
+      .contains_key("transmission of dielectric materials, imported as reflective"));
+    assert_eq!(report.0.len(), 3);
+  }
+
+  #[test]
+  fn mirrored_triangle() {
+    let scene = parse_pbrt(SCENE).unwrap();
+    let shape = scene.shapes.into_iter().next().unwrap();
+    let Geometry::Triangles {
+      positions,
+      normals,
+      indices,
+    } = shape.geometry
+    else {
+      panic!("not a triangle mesh");
+    };
+    let SceneShape::Mesh { positions, indices, .. } = bake(positions, normals, indices, shape.to_world) else {
+      panic!("not a mesh");
+    };
+    assert_eq!(positions, [[0.0, 0.0, 0.0], [-1.0, 0.0, 0.0], [0.0, 1.0, 0.0]]);
+    // Flipped back so the triangle still faces the camera on +Z
+    let [a, b, c] = [0, 1, 2].map(|i| Vec3::from(positions[indices[i] as usize]));
+    assert!((b - a).cross(c - a).z > 0.0);
+  }
+}
@@ -0,0 +1,77 @@
+use crate::loaders::import::mitsuba::MitsubaLoader;
+use crate::loaders::import::pbrt::PbrtLoader;
+use crate::loaders::obj::ObjLoader;
+use crate::loaders::ply::PlyLoader;
+use crate::loaders::rtscene::systems::spawn_rtscenes;
+use crate::loaders::rtscene::types::RtScene;
+use crate::loaders::rtscene::RtSceneLoader;
+use bevy::asset::{LoadContext, LoadedAsset};
+use bevy::prelude::*;
+use bevy::render::mesh::{Indices, PrimitiveTopology};
+
+pub mod import;
+pub mod obj;
+pub mod ply;
+pub mod rtscene;
+
+/// Loads OBJ and PLY files into a `Scene0` scene, the label glTF scenes are opened with, and spawns ray traced
+/// scenes, including the ones imported from pbrt and Mitsuba.
+pub struct LoadersPlugin;
+
+impl Plugin for LoadersPlugin {
+  fn build(&self, app: &mut App) {
+    app
+      .add_asset_loader(ObjLoader)
+      .add_asset_loader(PlyLoader)
+      .add_asset::<RtScene>()
+      .add_asset_loader(RtSceneLoader)
+      .add_asset_loader(PbrtLoader)
+      .add_asset_loader(MitsubaLoader)
+      .add_system(spawn_rtscenes);
+  }
+}
+
+/// A mesh of a loaded file with its material.
+struct ScenePart {
+  name: String,
+  mesh: Mesh,
+  material: StandardMaterial,
+}
+
+/// Indexed triangle list, with flat normals when the file has none.
+fn triangle_mesh(positions: Vec<[f32; 3]>, normals: Option<Vec<[f32; 3]>>, indices: Vec<u32>) -> Mesh {
+  let mut mesh = Mesh::new(PrimitiveTopology::TriangleList);
+  mesh.insert_attribute(Mesh::ATTRIBUTE_POSITION, positions);
+  mesh.set_indices(Some(Indices::U32(indices)));
+  match normals {
+    Some(normals) => mesh.insert_attribute(Mesh::ATTRIBUTE_NORMAL, normals),
+    None => {
+      mesh.duplicate_vertices();
+      mesh.compute_flat_normals();
+    }
+  }
+  mesh
+}
+
+/// Triangles of a convex polygon, fanned out from its first vertex.
+fn fan(polygon: &[u32]) -> impl Iterator<Item = u32> + '_ {
+  (1..polygon.len().saturating_sub(1)).flat_map(move |i| [polygon[0], polygon[i], polygon[i + 1]])
+}
+
+/// Adds the meshes and materials as labeled assets and spawns one entity per part into the `Scene0` scene.
+fn set_scene(load_context: &mut LoadContext, parts: Vec<ScenePart>) {
+  let mut world = World::default();
+  for (i, part) in parts.into_iter().enumerate() {
+    let mesh = load_context.set_labeled_asset(&format!("Mesh{}", i), LoadedAsset::new(part.mesh));
+    let material = load_context.set_labeled_asset(&format!("Material{}", i), LoadedAsset::new(part.material));
+    world.spawn((
+      PbrBundle {
+        mesh,
+        material,
+        ..default()
+      },
+      Name::new(part.name),
+    ));
+  }
+  load_context.set_labeled_asset("Scene0", LoadedAsset::new(Scene::new(world)));
+}
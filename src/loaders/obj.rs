@@ -0,0 +1,343 @@
+use crate::loaders::import::{dielectric_f0, reflectance, Report};
+use crate::loaders::{fan, set_scene, triangle_mesh, ScenePart};
+use bevy::asset::{AssetLoader, Error, LoadContext};
+use bevy::prelude::*;
+use bevy::utils::{BoxedFuture, HashMap};
+use std::path::Path;
+
+/// Wavefront OBJ with its MTL libraries, one mesh per material.
+pub struct ObjLoader;
+
+impl AssetLoader for ObjLoader {
+  fn load<'a>(&'a self, bytes: &'a [u8], load_context: &'a mut LoadContext) -> BoxedFuture<'a, Result<(), Error>> {
+    Box::pin(async move {
+      let obj = parse_obj(&String::from_utf8_lossy(bytes)).map_err(Error::msg)?;
+      // Libraries are relative to the OBJ file
+      let directory = load_context.path().parent().unwrap_or(Path::new("")).to_path_buf();
+      let mut materials = HashMap::new();
+      let mut report = Report::default();
+      for library in &obj.libraries {
+        match load_context.read_asset_bytes(directory.join(library)).await {
+          Ok(bytes) => materials.extend(parse_mtl(&String::from_utf8_lossy(&bytes))),
+          Err(err) => warn!("Failed to read {}: {}", library, err),
+        }
+      }
+      let parts = obj
+        .groups
+        .into_iter()
+        .map(|group| ScenePart {
+          material: materials
+            .get(&group.material)
+            .map(|material| material.standard_material(&mut report))
+            .unwrap_or_default(),
+          mesh: triangle_mesh(group.positions, group.normals, group.indices),
+          name: group.material,
+        })
+        .collect();
+      report.log(load_context.path());
+      set_scene(load_context, parts);
+      Ok(())
+    })
+  }
+
+  fn extensions(&self) -> &[&str] {
+    &["obj"]
+  }
+}
+
+pub struct Obj {
+  pub libraries: Vec<String>,
+  pub groups: Vec<ObjGroup>,
+}
+
+/// Faces sharing a material.
+pub struct ObjGroup {
+  pub material: String,
+  pub positions: Vec<[f32; 3]>,
+  /// `None` once a face without normals was added, the mesh then gets flat normals.
+  pub normals: Option<Vec<[f32; 3]>>,
+  pub indices: Vec<u32>,
+  /// Output vertex of every position and normal index pair.
+  vertices: HashMap<(usize, Option<usize>), u32>,
+}
+
+pub fn parse_obj(text: &str) -> Result<Obj, String> {
+  let mut positions = vec![];
+  let mut normals = vec![];
+  let mut libraries = vec![];
+  let mut groups = Vec::<ObjGroup>::new();
+  let mut group_of_material = HashMap::new();
+  let mut material = String::new();
+
+  for (line_number, line) in text.lines().enumerate() {
+    let error = |message: String| format!("line {}: {}", line_number + 1, message);
+    let line = line.split('#').next().unwrap_or_default();
+    let mut tokens = line.split_whitespace();
+    match tokens.next() {
+      Some("v") => positions.push(parse_vec3(tokens).map_err(error)?),
+      Some("vn") => normals.push(parse_vec3(tokens).map_err(error)?),
+      Some("mtllib") => libraries.extend(tokens.map(str::to_string)),
+      Some("usemtl") => material = tokens.collect::<Vec<_>>().join(" "),
+      Some("f") => {
+        let corners = tokens
+          .map(|corner| parse_corner(corner, positions.len(), normals.len()))
+          .collect::<Result<Vec<_>, _>>()
+          .map_err(error)?;
+        if corners.len() < 3 {
+          return Err(error("face with less than 3 vertices".to_string()));
+        }
+        let index = *group_of_material.entry(material.clone()).or_insert_with(|| {
+          groups.push(ObjGroup {
+            material: material.clone(),
+            positions: vec![],
+            normals: Some(vec![]),
+            indices: vec![],
+            vertices: HashMap::new(),
+          });
+          groups.len() - 1
+        });
+        let group = &mut groups[index];
+        if corners.iter().any(|(_, normal)| normal.is_none()) {
+          group.normals = None;
+        }
+        let corners = corners
+          .into_iter()
+          .map(|(position, normal)| {
+            *group.vertices.entry((position, normal)).or_insert_with(|| {
+              group.positions.push(positions[position]);
+              if let Some(group_normals) = &mut group.normals {
+                group_normals.push(normal.map_or([0.0; 3], |normal| normals[normal]));
+              }
+              group.positions.len() as u32 - 1
+            })
+          })
+          .collect::<Vec<_>>();
+        group.indices.extend(fan(&corners));
+      }
+      _ => {}
+    }
+  }
+  // Normals of groups that lost them part way are stale
+  for group in &mut groups {
+    if let Some(normals) = &group.normals {
+      if normals.len() != group.positions.len() {
+        group.normals = None;
+      }
+    }
+  }
+  Ok(Obj { libraries, groups })
+}
+
+fn parse_vec3<'a>(mut tokens: impl Iterator<Item = &'a str>) -> Result<[f32; 3], String> {
+  let mut vector = [0.0; 3];
+  for component in &mut vector {
+    let token = tokens.next().ok_or("missing vector component")?;
+    *component = token.parse().map_err(|_| format!("invalid number {}", token))?;
+  }
+  Ok(vector)
+}
+
+/// Position and normal of a `v`, `v/vt`, `v//vn` or `v/vt/vn` face corner, negative indices count from the end.
+fn parse_corner(corner: &str, positions: usize, normals: usize) -> Result<(usize, Option<usize>), String> {
+  let mut indices = corner.split('/');
+  let position = resolve_index(indices.next().unwrap_or_default(), positions)?;
+  let normal = match indices.nth(1) {
+    Some(normal) if !normal.is_empty() => Some(resolve_index(normal, normals)?),
+    _ => None,
+  };
+  Ok((position, normal))
+}
+
+fn resolve_index(token: &str, len: usize) -> Result<usize, String> {
+  let index = token.parse::<i64>().map_err(|_| format!("invalid index {}", token))?;
+  let resolved = if index < 0 { len as i64 + index } else { index - 1 };
+  if resolved < 0 || resolved >= len as i64 {
+    return Err(format!("index {} out of range", index));
+  }
+  Ok(resolved as usize)
+}
+
+/// The MTL parameters the ray tracer has a use for.
+struct MtlMaterial {
+  diffuse: [f32; 3],
+  specular: [f32; 3],
+  shininess: f32,
+  emissive: [f32; 3],
+  dissolve: f32,
+  optical_density: f32,
+}
+
+impl Default for MtlMaterial {
+  fn default() -> Self {
+    Self {
+      diffuse: [0.8; 3],
+      specular: [0.0; 3],
+      shininess: 0.0,
+      emissive: [0.0; 3],
+      dissolve: 1.0,
+      optical_density: 1.5,
+    }
+  }
+}
+
+impl MtlMaterial {
+  /// The ray tracer ignores alpha and has no specular color, dissolve is dropped and a tinted `Ks` only keeps its
+  /// brightest channel, both with a warning.
+  fn standard_material(&self, report: &mut Report) -> StandardMaterial {
+    let [r, g, b] = self.diffuse;
+    let diffuse = r.max(g).max(b);
+    let [sr, sg, sb] = self.specular;
+    let specular = sr.max(sg).max(sb);
+    if specular - sr.min(sg).min(sb) > 1e-3 {
+      report.unsupported("tinted specular color Ks, using its brightest channel");
+    }
+    if self.dissolve < 1.0 {
+      report.unsupported("dissolve d or Tr, imported as opaque");
+    }
+    // Blinn-Phong exponent to GGX roughness, alpha = sqrt(2 / (Ns + 2)) and perceptual roughness = sqrt(alpha)
+    let roughness = (2.0 / (self.shininess.max(0.0) + 2.0)).sqrt().sqrt();
+    let [er, eg, eb] = self.emissive;
+    StandardMaterial {
+      base_color: Color::rgb(r, g, b),
+      emissive: Color::rgb(er, eg, eb),
+      perceptual_roughness: roughness,
+      // The ray tracer takes the specular bounce with the metallic probability, Ks' share of the reflected light
+      metallic: if specular > 0.0 {
+        specular / (diffuse + specular)
+      } else {
+        0.0
+      },
+      reflectance: reflectance(dielectric_f0(self.optical_density)),
+      ..default()
+    }
+  }
+}
+
+fn parse_mtl(text: &str) -> HashMap<String, MtlMaterial> {
+  let mut materials = HashMap::new();
+  let mut current = None;
+  for line in text.lines() {
+    let line = line.split('#').next().unwrap_or_default();
+    let mut tokens = line.split_whitespace();
+    let Some(keyword) = tokens.next() else {
+      continue;
+    };
+    if keyword == "newmtl" {
+      let name = tokens.collect::<Vec<_>>().join(" ");
+      materials.insert(name.clone(), MtlMaterial::default());
+      current = Some(name);
+      continue;
+    }
+    let Some(material) = current.as_ref().and_then(|name| materials.get_mut(name)) else {
+      continue;
+    };
+    let values = tokens.filter_map(|token| token.parse::<f32>().ok()).collect::<Vec<_>>();
+    // A single value stands for all three channels
+    let color = || match values[..] {
+      [r, g, b, ..] => Some([r, g, b]),
+      [v] => Some([v; 3]),
+      _ => None,
+    };
+    match keyword {
+      "Kd" => material.diffuse = color().unwrap_or(material.diffuse),
+      "Ks" => material.specular = color().unwrap_or(material.specular),
+      "Ke" => material.emissive = color().unwrap_or(material.emissive),
+      "Ns" => material.shininess = values.first().copied().unwrap_or(material.shininess),
+      "d" => material.dissolve = values.first().copied().unwrap_or(material.dissolve),
+      "Tr" => material.dissolve = values.first().map_or(material.dissolve, |tr| 1.0 - tr),
+      "Ni" => material.optical_density = values.first().copied().unwrap_or(material.optical_density),
+      _ => {}
+    }
+  }
+  materials
+}
+
+#[cfg(test)]
+mod tests {
+  use super::{parse_mtl, parse_obj};
+  use crate::loaders::import::Report;
+  use bevy::prelude::*;
+
+  const TRIANGLE: &str = "
+    v 0 0 0
+    v 1 0 0
+    v 0 1 0
+    vn 0 0 1
+  ";
+
+  #[test]
+  fn negative_indices_and_normals() {
+    let obj = parse_obj(&format!("{}f -3//1 -2//-1 -1//1", TRIANGLE)).unwrap();
+    let [group] = &obj.groups[..] else {
+      panic!("{} groups", obj.groups.len());
+    };
+    assert_eq!(group.positions, [[0.0, 0.0, 0.0], [1.0, 0.0, 0.0], [0.0, 1.0, 0.0]]);
+    assert_eq!(group.normals, Some(vec![[0.0, 0.0, 1.0]; 3]));
+    assert_eq!(group.indices, [0, 1, 2]);
+  }
+
+  #[test]
+  fn polygons_share_vertices() {
+    let obj = parse_obj(&format!("{}v 1 1 0\nf 1/1 2/2 4/4 3/3", TRIANGLE)).unwrap();
+    let group = &obj.groups[0];
+    assert_eq!(group.positions.len(), 4);
+    assert_eq!(group.indices, [0, 1, 2, 0, 2, 3]);
+    // Faces without normals are flat shaded
+    assert_eq!(group.normals, None);
+  }
+
+  #[test]
+  fn groups_by_material() {
+    let text = format!("{}usemtl a\nf 1 2 3\nusemtl b\nf 1 2 3\nusemtl a\nf 3 2 1", TRIANGLE);
+    let obj = parse_obj(&text).unwrap();
+    let groups = obj
+      .groups
+      .iter()
+      .map(|group| group.material.as_str())
+      .collect::<Vec<_>>();
+    assert_eq!(groups, ["a", "b"]);
+    assert_eq!(obj.groups[0].indices, [0, 1, 2, 2, 1, 0]);
+  }
+
+  #[test]
+  fn out_of_range_index() {
+    let err = parse_obj(&format!("{}f 1 2 4", TRIANGLE)).err().unwrap();
+    assert!(err.starts_with("line 6:"), "{}", err);
+    assert!(parse_obj(&format!("{}f 1 2 -4", TRIANGLE)).is_err());
+  }
+
+  #[test]
+  fn materials() {
+    let materials = parse_mtl(
+      "
+      newmtl glass
+      Kd 0.2 0.4 0.6
+      Ks 0.5 0.25 0.25
+      Ns 0
+      Ni 1.5
+      d 0.5
+      newmtl lamp
+      Ke 4
+      Tr 0
+      ",
+    );
+    let mut report = Report::default();
+    let glass = materials["glass"].standard_material(&mut report);
+    assert_eq!(glass.base_color, Color::rgb(0.2, 0.4, 0.6));
+    assert_eq!(glass.alpha_mode, AlphaMode::Opaque);
+    assert_eq!(glass.perceptual_roughness, 1.0);
+    // Half the reflected light is specular, with an index of refraction of 1.5 reflecting 4%
+    assert!((glass.metallic - 0.5 / 1.1).abs() < 1e-5);
+    assert!((glass.reflectance - 0.5).abs() < 1e-5);
+    assert!(report
+      .0
+      .contains_key("tinted specular color Ks, using its brightest channel"));
+    assert!(report.0.contains_key("dissolve d or Tr, imported as opaque"));
+
+    let mut report = Report::default();
+    let lamp = materials["lamp"].standard_material(&mut report);
+    assert_eq!(lamp.emissive, Color::rgb(4.0, 4.0, 4.0));
+    assert_eq!(lamp.metallic, 0.0);
+    assert!(report.0.is_empty());
+  }
+}
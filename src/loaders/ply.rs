@@ -0,0 +1,351 @@
+use crate::loaders::{fan, set_scene, triangle_mesh, ScenePart};
+use bevy::asset::{AssetLoader, Error, LoadContext};
+use bevy::prelude::*;
+use bevy::utils::BoxedFuture;
+
+/// Stanford PLY in ASCII or binary, the vertex positions and normals and the faces of it.
+pub struct PlyLoader;
+
+impl AssetLoader for PlyLoader {
+  fn load<'a>(&'a self, bytes: &'a [u8], load_context: &'a mut LoadContext) -> BoxedFuture<'a, Result<(), Error>> {
+    Box::pin(async move {
+      let ply = parse_ply(bytes).map_err(Error::msg)?;
+      let name = load_context
+        .path()
+        .file_stem()
+        .map_or(String::new(), |stem| stem.to_string_lossy().to_string());
+      set_scene(
+        load_context,
+        vec![ScenePart {
+          name,
+          mesh: triangle_mesh(ply.positions, ply.normals, ply.indices),
+          material: default(),
+        }],
+      );
+      Ok(())
+    })
+  }
+
+  fn extensions(&self) -> &[&str] {
+    &["ply"]
+  }
+}
+
+pub struct Ply {
+  pub positions: Vec<[f32; 3]>,
+  pub normals: Option<Vec<[f32; 3]>>,
+  pub indices: Vec<u32>,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Format {
+  Ascii,
+  BinaryLittleEndian,
+  BinaryBigEndian,
+}
+
+#[derive(Clone, Copy)]
+enum Scalar {
+  I8,
+  U8,
+  I16,
+  U16,
+  I32,
+  U32,
+  F32,
+  F64,
+}
+
+impl Scalar {
+  fn parse(name: &str) -> Result<Self, String> {
+    Ok(match name {
+      "char" | "int8" => Self::I8,
+      "uchar" | "uint8" => Self::U8,
+      "short" | "int16" => Self::I16,
+      "ushort" | "uint16" => Self::U16,
+      "int" | "int32" => Self::I32,
+      "uint" | "uint32" => Self::U32,
+      "float" | "float32" => Self::F32,
+      "double" | "float64" => Self::F64,
+      _ => return Err(format!("unknown property type {}", name)),
+    })
+  }
+
+  fn size(self) -> usize {
+    match self {
+      Self::I8 | Self::U8 => 1,
+      Self::I16 | Self::U16 => 2,
+      Self::I32 | Self::U32 | Self::F32 => 4,
+      Self::F64 => 8,
+    }
+  }
+}
+
+enum PropertyType {
+  Scalar(Scalar),
+  /// Length type and item type.
+  List(Scalar, Scalar),
+}
+
+struct Property {
+  name: String,
+  ty: PropertyType,
+}
+
+struct Element {
+  name: String,
+  count: usize,
+  properties: Vec<Property>,
+}
+
+/// Reads the values of the body one at a time, whatever the format.
+struct Reader<'a> {
+  format: Format,
+  bytes: &'a [u8],
+  offset: usize,
+}
+
+impl<'a> Reader<'a> {
+  fn read(&mut self, scalar: Scalar) -> Result<f64, String> {
+    if self.format == Format::Ascii {
+      return self.read_ascii();
+    }
+    let size = scalar.size();
+    let bytes = self
+      .bytes
+      .get(self.offset..self.offset + size)
+      .ok_or("unexpected end of file")?;
+    self.offset += size;
+    let mut buffer = [0; 8];
+    buffer[..size].copy_from_slice(bytes);
+    if self.format == Format::BinaryBigEndian {
+      buffer[..size].reverse();
+    }
+    Ok(match scalar {
+      Scalar::I8 => buffer[0] as i8 as f64,
+      Scalar::U8 => buffer[0] as f64,
+      Scalar::I16 => i16::from_le_bytes([buffer[0], buffer[1]]) as f64,
+      Scalar::U16 => u16::from_le_bytes([buffer[0], buffer[1]]) as f64,
+      Scalar::I32 => i32::from_le_bytes(buffer[..4].try_into().unwrap()) as f64,
+      Scalar::U32 => u32::from_le_bytes(buffer[..4].try_into().unwrap()) as f64,
+      Scalar::F32 => f32::from_le_bytes(buffer[..4].try_into().unwrap()) as f64,
+      Scalar::F64 => f64::from_le_bytes(buffer),
+    })
+  }
+
+  fn read_ascii(&mut self) -> Result<f64, String> {
+    let rest = &self.bytes[self.offset..];
+    let start = rest
+      .iter()
+      .position(|byte| !byte.is_ascii_whitespace())
+      .ok_or("unexpected end of file")?;
+    let len = rest[start..]
+      .iter()
+      .position(|byte| byte.is_ascii_whitespace())
+      .unwrap_or(rest.len() - start);
+    self.offset += start + len;
+    let token = String::from_utf8_lossy(&rest[start..start + len]);
+    token.parse().map_err(|_| format!("invalid number {}", token))
+  }
+}
+
+fn parse_header(bytes: &[u8]) -> Result<(Format, Vec<Element>, usize), String> {
+  let end = bytes
+    .windows(10)
+    .position(|window| window == b"end_header")
+    .ok_or("missing end_header")?;
+  // The body starts after the line break following end_header
+  let body = bytes[end..]
+    .iter()
+    .position(|byte| *byte == b'\n')
+    .map_or(bytes.len(), |newline| end + newline + 1);
+  let header = String::from_utf8_lossy(&bytes[..end]);
+  let mut lines = header.lines();
+  if lines.next().map(str::trim) != Some("ply") {
+    return Err("not a PLY file".to_string());
+  }
+
+  let mut format = None;
+  let mut elements = Vec::<Element>::new();
+  for line in lines {
+    let tokens = line.split_whitespace().collect::<Vec<_>>();
+    match tokens[..] {
+      ["format", name, _] => {
+        format = Some(match name {
+          "ascii" => Format::Ascii,
+          "binary_little_endian" => Format::BinaryLittleEndian,
+          "binary_big_endian" => Format::BinaryBigEndian,
+          _ => return Err(format!("unknown format {}", name)),
+        })
+      }
+      ["element", name, count] => elements.push(Element {
+        name: name.to_string(),
+        count: count.parse().map_err(|_| format!("invalid element count {}", count))?,
+        properties: vec![],
+      }),
+      ["property", "list", len, item, name] => {
+        elements
+          .last_mut()
+          .ok_or("property before element")?
+          .properties
+          .push(Property {
+            name: name.to_string(),
+            ty: PropertyType::List(Scalar::parse(len)?, Scalar::parse(item)?),
+          })
+      }
+      ["property", ty, name] => elements
+        .last_mut()
+        .ok_or("property before element")?
+        .properties
+        .push(Property {
+          name: name.to_string(),
+          ty: PropertyType::Scalar(Scalar::parse(ty)?),
+        }),
+      _ => {}
+    }
+  }
+  Ok((format.ok_or("missing format")?, elements, body))
+}
+
+pub fn parse_ply(bytes: &[u8]) -> Result<Ply, String> {
+  let (format, elements, body) = parse_header(bytes)?;
+  let mut reader = Reader {
+    format,
+    bytes,
+    offset: body,
+  };
+  let mut positions = vec![];
+  let mut normals = vec![];
+  let mut faces = vec![];
+
+  for element in &elements {
+    let property = |name: &str| element.properties.iter().position(|property| property.name == name);
+    let position = [property("x"), property("y"), property("z")];
+    let normal = [property("nx"), property("ny"), property("nz")];
+    let face = property("vertex_indices").or_else(|| property("vertex_index"));
+    for _ in 0..element.count {
+      let mut values = Vec::with_capacity(element.properties.len());
+      let mut list = vec![];
+      for (i, property) in element.properties.iter().enumerate() {
+        match property.ty {
+          PropertyType::Scalar(scalar) => values.push(reader.read(scalar)?),
+          PropertyType::List(len, item) => {
+            let len = reader.read(len)? as usize;
+            let items = (0..len).map(|_| reader.read(item)).collect::<Result<Vec<_>, _>>()?;
+            if Some(i) == face {
+              list = items;
+            }
+            values.push(0.0);
+          }
+        }
+      }
+      let value = |index: Option<usize>| index.map(|index| values[index] as f32);
+      match element.name.as_str() {
+        "vertex" => {
+          if let [Some(x), Some(y), Some(z)] = position.map(value) {
+            positions.push([x, y, z]);
+          }
+          if let [Some(x), Some(y), Some(z)] = normal.map(value) {
+            normals.push([x, y, z]);
+          }
+        }
+        "face" => faces.push(list),
+        _ => {}
+      }
+    }
+  }
+
+  let mut indices = vec![];
+  for face in faces {
+    let face = face.into_iter().map(|index| index as u32).collect::<Vec<_>>();
+    if face.len() < 3 || face.iter().any(|index| *index as usize >= positions.len()) {
+      continue;
+    }
+    indices.extend(fan(&face));
+  }
+  let normals = (!normals.is_empty() && normals.len() == positions.len()).then_some(normals);
+  Ok(Ply {
+    positions,
+    normals,
+    indices,
+  })
+}
+
+#[cfg(test)]
+mod tests {
+  use super::parse_ply;
+
+  const TRIANGLE: [[f32; 3]; 3] = [[0.0, 0.0, 0.0], [1.0, 0.0, 0.0], [0.0, 1.0, 0.0]];
+
+  #[test]
+  fn ascii() {
+    let ply = parse_ply(
+      b"ply
+format ascii 1.0
+comment a square and a face past its vertices
+element vertex 4
+property float x
+property float y
+property float z
+element face 2
+property list uchar int vertex_indices
+end_header
+0 0 0
+1 0 0
+1 1 0
+0 1 0
+4 0 1 2 3
+3 0 1 4
+",
+    )
+    .unwrap();
+    assert_eq!(ply.positions.len(), 4);
+    assert_eq!(ply.normals, None);
+    assert_eq!(ply.indices, [0, 1, 2, 0, 2, 3]);
+  }
+
+  /// Triangle with normals in a binary format, `float` and `int` write a value in its byte order.
+  fn binary(format: &str, float: fn(f32) -> [u8; 4], int: fn(i32) -> [u8; 4]) -> Vec<u8> {
+    let mut bytes = format!(
+      "ply\nformat {} 1.0\nelement vertex 3\nproperty float x\nproperty float y\nproperty float z\n\
+      property float nx\nproperty float ny\nproperty float nz\nelement face 1\n\
+      property list uchar int vertex_indices\nend_header\n",
+      format
+    )
+    .into_bytes();
+    for position in TRIANGLE {
+      for value in position.into_iter().chain([0.0, 0.0, 1.0]) {
+        bytes.extend(float(value));
+      }
+    }
+    bytes.push(3);
+    for index in [0, 1, 2] {
+      bytes.extend(int(index));
+    }
+    bytes
+  }
+
+  #[test]
+  fn binary_little_endian() {
+    let ply = parse_ply(&binary("binary_little_endian", f32::to_le_bytes, i32::to_le_bytes)).unwrap();
+    assert_eq!(ply.positions, TRIANGLE);
+    assert_eq!(ply.normals, Some(vec![[0.0, 0.0, 1.0]; 3]));
+    assert_eq!(ply.indices, [0, 1, 2]);
+  }
+
+  #[test]
+  fn binary_big_endian() {
+    let ply = parse_ply(&binary("binary_big_endian", f32::to_be_bytes, i32::to_be_bytes)).unwrap();
+    assert_eq!(ply.positions, TRIANGLE);
+    assert_eq!(ply.normals, Some(vec![[0.0, 0.0, 1.0]; 3]));
+    assert_eq!(ply.indices, [0, 1, 2]);
+  }
+
+  #[test]
+  fn truncated() {
+    let mut bytes = binary("binary_little_endian", f32::to_le_bytes, i32::to_le_bytes);
+    bytes.truncate(bytes.len() - 2);
+    assert!(parse_ply(&bytes).is_err());
+    assert!(parse_ply(b"solid stl\nend_header\n").is_err());
+  }
+}
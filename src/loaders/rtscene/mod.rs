@@ -0,0 +1,223 @@
+use crate::loaders::rtscene::types::{ObjectAsset, RtScene, SceneDescription, SceneShape};
+use bevy::asset::{AssetLoader, AssetPath, Error, LoadContext, LoadedAsset};
+use bevy::prelude::*;
+use bevy::utils::{BoxedFuture, HashMap};
+use std::path::{Component, Path, PathBuf};
+
+pub mod save;
+pub mod systems;
+pub mod types;
+
+/// Loads `.rtscene.ron` files. Materials and shapes become labeled assets, referenced files are loaded as
+/// dependencies.
+pub struct RtSceneLoader;
+
+impl AssetLoader for RtSceneLoader {
+  fn load<'a>(&'a self, bytes: &'a [u8], load_context: &'a mut LoadContext) -> BoxedFuture<'a, Result<(), Error>> {
+    Box::pin(async move {
+      let description = ron::de::from_bytes::<SceneDescription>(bytes)?;
+      load_description(description, load_context)
+    })
+  }
+
+  fn extensions(&self) -> &[&str] {
+    &["rtscene.ron"]
+  }
+}
+
+/// Sets the scene and its materials and shapes as assets of the file being loaded.
+pub fn load_description(description: SceneDescription, load_context: &mut LoadContext) -> Result<(), Error> {
+  let materials = description
+    .materials
+    .iter()
+    .map(|(name, material)| {
+      let handle = load_context.set_labeled_asset(
+        &format!("Material/{}", name),
+        LoadedAsset::new(material.standard_material()),
+      );
+      (name.clone(), handle)
+    })
+    .collect::<HashMap<_, _>>();
+  let directory = load_context.path().parent().unwrap_or(Path::new("")).to_path_buf();
+  let mut dependencies = vec![];
+  let mut default_material = None;
+  let mut objects = vec![];
+  for (i, object) in description.objects.iter().enumerate() {
+    let name = match &object.name {
+      Some(name) => format!("object {} ({})", i, name),
+      None => format!("object {}", i),
+    };
+    let asset = match &object.shape {
+      SceneShape::File(file) => {
+        if loads_as_rtscene(Path::new(file)) {
+          return Err(Error::msg(format!("{} references another ray traced scene", name)));
+        }
+        let path = normalize(&directory.join(file));
+        dependencies.push(AssetPath::new(path.clone(), None));
+        ObjectAsset::Scene(load_context.get_handle(AssetPath::new(path, Some("Scene0".to_string()))))
+      }
+      shape => {
+        let mesh = shape.mesh().map_err(|err| Error::msg(format!("{}: {}", name, err)))?;
+        let material = match &object.material {
+          Some(material) => materials
+            .get(material)
+            .cloned()
+            .ok_or_else(|| Error::msg(format!("{} uses the unknown material {}", name, material)))?,
+          None => default_material
+            .get_or_insert_with(|| {
+              load_context.set_labeled_asset("DefaultMaterial", LoadedAsset::new(StandardMaterial::default()))
+            })
+            .clone(),
+        };
+        ObjectAsset::Mesh {
+          mesh: load_context.set_labeled_asset(&format!("Mesh{}", i), LoadedAsset::new(mesh)),
+          material,
+        }
+      }
+    };
+    objects.push(asset);
+  }
+  load_context.set_default_asset(LoadedAsset::new(RtScene { description, objects }).with_dependencies(dependencies));
+  Ok(())
+}
+
+pub fn is_rtscene_file(path: &Path) -> bool {
+  let name = path.file_name().map(|name| name.to_string_lossy().to_ascii_lowercase());
+  name.map_or(false, |name| name.ends_with(".rtscene.ron"))
+}
+
+/// Ray traced scenes and the pbrt and Mitsuba scenes imported as one.
+pub fn loads_as_rtscene(path: &Path) -> bool {
+  let extension = path.extension().and_then(|extension| extension.to_str());
+  is_rtscene_file(path) || matches!(extension.map(str::to_ascii_lowercase).as_deref(), Some("pbrt" | "xml"))
+}
+
+/// Resolves `..` and `.` without touching the file system, so a file reached through different relative paths
+/// is loaded once.
+fn normalize(path: &Path) -> PathBuf {
+  let mut normalized = PathBuf::new();
+  for component in path.components() {
+    match component {
+      Component::CurDir => {}
+      Component::ParentDir if matches!(normalized.components().next_back(), Some(Component::Normal(_))) => {
+        normalized.pop();
+      }
+      component => normalized.push(component),
+    }
+  }
+  normalized
+}
+
+#[cfg(test)]
+mod tests {
+  use super::save::capture_scene;
+  use super::types::{ObjectAsset, RtScene, SceneDescription, SceneShape};
+  use super::RtSceneLoader;
+  use crate::render::raytracer::types::RaytraceEnvironment;
+  use bevy::asset::LoadState;
+  use bevy::prelude::*;
+  use bevy::render::mesh::{Indices, PrimitiveTopology};
+  use ron::ser::PrettyConfig;
+  use std::path::{Path, PathBuf};
+  use std::time::Duration;
+
+  /// App with a triangle without normals, the way meshes built in the editor are saved.
+  fn triangle_app() -> App {
+    let mut app = App::new();
+    app
+      .add_plugins(MinimalPlugins)
+      .add_plugin(AssetPlugin::default())
+      .add_asset::<Mesh>()
+      .add_asset::<StandardMaterial>()
+      .add_asset::<RtScene>()
+      .add_asset_loader(RtSceneLoader)
+      .init_resource::<RaytraceEnvironment>();
+    let mut mesh = Mesh::new(PrimitiveTopology::TriangleList);
+    mesh.insert_attribute(
+      Mesh::ATTRIBUTE_POSITION,
+      vec![[0.0, 0.0, 0.0], [1.0, 0.0, 0.0], [0.0, 1.0, 0.0]],
+    );
+    mesh.set_indices(Some(Indices::U32(vec![0, 1, 2])));
+    let mesh = app.world.resource_mut::<Assets<Mesh>>().add(mesh);
+    let material = app
+      .world
+      .resource_mut::<Assets<StandardMaterial>>()
+      .add(Color::RED.into());
+    app.world.spawn((
+      Name::new("Triangle"),
+      mesh,
+      material,
+      GlobalTransform::from(Transform::from_xyz(1.0, 2.0, 3.0)),
+    ));
+    app
+  }
+
+  fn file(name: &str) -> PathBuf {
+    let directory = std::env::temp_dir().join(format!("rtscene-{}", std::process::id()));
+    std::fs::create_dir_all(&directory).unwrap();
+    directory.join(name)
+  }
+
+  /// Writes the description as [`save_scene`](super::save::save_scene) does and loads it back.
+  fn save_and_load(app: &mut App, description: &SceneDescription, path: &Path) -> (Handle<RtScene>, LoadState) {
+    let text = ron::ser::to_string_pretty(description, PrettyConfig::new().depth_limit(3)).unwrap();
+    std::fs::write(path, text).unwrap();
+    let handle = app.world.resource::<AssetServer>().load::<RtScene, _>(path);
+    let mut state = LoadState::Loading;
+    for _ in 0..1000 {
+      app.update();
+      state = app.world.resource::<AssetServer>().get_load_state(&handle);
+      if matches!(state, LoadState::Loaded | LoadState::Failed) {
+        break;
+      }
+      std::thread::sleep(Duration::from_millis(5));
+    }
+    std::fs::remove_file(path).unwrap();
+    (handle, state)
+  }
+
+  #[test]
+  fn round_trip() {
+    let mut app = triangle_app();
+    let path = file("round_trip.rtscene.ron");
+    let description = capture_scene(&app.world, None, path.parent().unwrap());
+    assert_eq!(description.objects.len(), 1);
+    let (handle, state) = save_and_load(&mut app, &description, &path);
+    assert_eq!(state, LoadState::Loaded);
+
+    let scene = app.world.resource::<Assets<RtScene>>().get(&handle).unwrap();
+    assert_eq!(scene.description, description);
+    let [ObjectAsset::Mesh { mesh, material }] = &scene.objects[..] else {
+      panic!("{} objects", scene.objects.len());
+    };
+    // Flat shaded, a vertex per corner
+    let mesh = app.world.resource::<Assets<Mesh>>().get(mesh).unwrap();
+    assert_eq!(mesh.count_vertices(), 3);
+    assert!(mesh.attribute(Mesh::ATTRIBUTE_NORMAL).is_some());
+    let material = app.world.resource::<Assets<StandardMaterial>>().get(material).unwrap();
+    assert_eq!(material.base_color, Color::RED);
+  }
+
+  #[test]
+  fn invalid_meshes_fail_to_load() {
+    let mut app = triangle_app();
+    let path = file("out_of_range.rtscene.ron");
+    let mut description = capture_scene(&app.world, None, path.parent().unwrap());
+    let SceneShape::Mesh { indices, .. } = &mut description.objects[0].shape else {
+      panic!("{:?}", description.objects[0].shape);
+    };
+    indices[2] = 3;
+    let error = description.objects[0].shape.mesh().err();
+    assert_eq!(error.as_deref(), Some("index 3 out of range of 3 positions"));
+    assert_eq!(save_and_load(&mut app, &description, &path).1, LoadState::Failed);
+
+    let SceneShape::Mesh { indices, .. } = &mut description.objects[0].shape else {
+      unreachable!();
+    };
+    indices.truncate(2);
+    let error = description.objects[0].shape.mesh().err();
+    assert_eq!(error.as_deref(), Some("2 indices do not make triangles"));
+    let path = file("not_triangles.rtscene.ron");
+    assert_eq!(save_and_load(&mut app, &description, &path).1, LoadState::Failed);
+  }
+}
@@ -0,0 +1,186 @@
+use crate::app::scenes::asset_folder;
+use crate::loaders::rtscene::types::{
+  RtSceneCamera, RtSceneObject, SceneCamera, SceneDescription, SceneLight, SceneObject, SceneShape, SceneTransform,
+};
+use crate::render::raytracer::types::{RaytraceEnvironment, RaytraceSettings, RaytraceVisibility, ThinLensCamera};
+use bevy::asset::HandleId;
+use bevy::prelude::*;
+use bevy::utils::HashMap;
+use bevy_editor_pls::prelude::NotInScene;
+use ron::ser::PrettyConfig;
+use std::path::{Component, Path, PathBuf};
+
+/// Writes the world as a ray traced scene looking through `camera`.
+pub fn save_scene(world: &World, camera: Option<Entity>, path: &Path) -> Result<(), String> {
+  let directory = path.parent().unwrap_or(Path::new(""));
+  let description = capture_scene(world, camera, directory);
+  // Past the object fields, values such as vertex lists stay on one line
+  let text =
+    ron::ser::to_string_pretty(&description, PrettyConfig::new().depth_limit(3)).map_err(|err| err.to_string())?;
+  std::fs::write(path, text).map_err(|err| format!("{}: {}", path.display(), err))
+}
+
+/// Describes the world as a ray traced scene. Spawned files are referenced relative to `directory` and bring their
+/// own lights back, everything else is written out.
+pub fn capture_scene(world: &World, camera: Option<Entity>, directory: &Path) -> SceneDescription {
+  let mut capture = Capture {
+    world,
+    directory,
+    description: SceneDescription {
+      camera: camera.and_then(|camera| capture_camera(world, camera)),
+      environment: Some(*world.resource::<RaytraceEnvironment>()),
+      ..default()
+    },
+    materials: HashMap::new(),
+  };
+  let mut roots = world
+    .iter_entities()
+    .filter(|entity| !entity.contains::<Parent>())
+    .map(|entity| entity.id())
+    .collect::<Vec<_>>();
+  // Spawn order, so saving an unchanged scene twice gives the same file
+  roots.sort();
+  for root in roots {
+    capture.entity(root);
+  }
+  capture.description
+}
+
+fn capture_camera(world: &World, camera: Entity) -> Option<SceneCamera> {
+  let camera = world.get_entity(camera)?;
+  Some(SceneCamera {
+    transform: camera
+      .get::<GlobalTransform>()
+      .map_or(Transform::IDENTITY, GlobalTransform::compute_transform)
+      .into(),
+    projection: camera.get::<Projection>().map(Into::into).unwrap_or_default(),
+    lens: camera.get::<ThinLensCamera>().copied(),
+    settings: camera.get::<RaytraceSettings>().cloned(),
+  })
+}
+
+struct Capture<'a> {
+  world: &'a World,
+  directory: &'a Path,
+  description: SceneDescription,
+  /// Name of every material written to the description.
+  materials: HashMap<HandleId, String>,
+}
+
+impl Capture<'_> {
+  fn entity(&mut self, entity: Entity) {
+    let world = self.world;
+    let entity = world.entity(entity);
+    // Editor gizmos and the cameras of ray traced scenes, which are written as the scene camera
+    if entity.contains::<NotInScene>() || entity.contains::<RtSceneCamera>() || entity.contains::<Camera>() {
+      return;
+    }
+    let transform = entity
+      .get::<GlobalTransform>()
+      .map_or(Transform::IDENTITY, GlobalTransform::compute_transform);
+    let name = entity.get::<Name>().map(|name| name.to_string());
+    let visibility = entity.get::<RaytraceVisibility>().copied().unwrap_or_default();
+    let object = entity.get::<RtSceneObject>();
+
+    if let Some(scene) = entity.get::<Handle<Scene>>() {
+      // Whatever the file spawned comes back with it
+      if let Some(path) = world.resource::<AssetServer>().get_handle_path(scene) {
+        let path = asset_folder().join(path.path());
+        self.description.objects.push(SceneObject {
+          name,
+          transform: transform.into(),
+          shape: SceneShape::File(relative_path(&path, self.directory).display().to_string()),
+          material: None,
+          visibility,
+        });
+      }
+      return;
+    }
+
+    if let (Some(mesh), Some(material)) = (entity.get::<Handle<Mesh>>(), entity.get::<Handle<StandardMaterial>>()) {
+      let shape = object.and_then(|object| object.shape.clone()).or_else(|| {
+        let mesh = world.resource::<Assets<Mesh>>().get(mesh)?;
+        SceneShape::from_mesh(mesh)
+      });
+      if let Some(shape) = shape {
+        let material = self.material(material, object.and_then(|object| object.material.as_deref()));
+        self.description.objects.push(SceneObject {
+          name,
+          transform: transform.into(),
+          shape,
+          material,
+          visibility,
+        });
+      }
+    }
+
+    if let Some(light) = entity.get::<PointLight>() {
+      self.description.lights.push(SceneLight::Point {
+        position: transform.translation,
+        color: light.color,
+        intensity: light.intensity,
+        range: Some(light.range),
+      });
+    }
+    if let Some(light) = entity.get::<SpotLight>() {
+      self.description.lights.push(SceneLight::Spot {
+        transform: SceneTransform {
+          scale: Vec3::ONE,
+          ..SceneTransform::from(transform)
+        },
+        color: light.color,
+        intensity: light.intensity,
+        inner_angle: light.inner_angle.to_degrees(),
+        outer_angle: light.outer_angle.to_degrees(),
+        range: Some(light.range),
+      });
+    }
+    if let Some(light) = entity.get::<DirectionalLight>() {
+      self.description.lights.push(SceneLight::Directional {
+        direction: transform.forward(),
+        color: light.color,
+        illuminance: light.illuminance,
+      });
+    }
+
+    if let Some(children) = entity.get::<Children>() {
+      for child in children.iter() {
+        self.entity(*child);
+      }
+    }
+  }
+
+  /// Adds the material under the name it was loaded with if that is still free, returns the name it got.
+  fn material(&mut self, handle: &Handle<StandardMaterial>, name: Option<&str>) -> Option<String> {
+    if let Some(name) = self.materials.get(&handle.id()) {
+      return Some(name.clone());
+    }
+    let material = self.world.resource::<Assets<StandardMaterial>>().get(handle)?;
+    let taken = |name: &str| self.description.materials.contains_key(name);
+    let name = match name {
+      Some(name) if !taken(name) => name.to_string(),
+      _ => (self.materials.len()..)
+        .map(|i| format!("Material{}", i))
+        .find(|name| !taken(name))
+        .unwrap(),
+    };
+    self.description.materials.insert(name.clone(), material.into());
+    self.materials.insert(handle.id(), name.clone());
+    Some(name)
+  }
+}
+
+/// `path` relative to `directory` when they share a root, otherwise `path` as is.
+fn relative_path(path: &Path, directory: &Path) -> PathBuf {
+  let path = path.components().collect::<Vec<_>>();
+  let directory = directory.components().collect::<Vec<_>>();
+  let common = path.iter().zip(&directory).take_while(|(a, b)| a == b).count();
+  if common == 0 {
+    return path.iter().collect();
+  }
+  directory[common..]
+    .iter()
+    .map(|_| Component::ParentDir)
+    .chain(path[common..].iter().copied())
+    .collect()
+}
@@ -0,0 +1,152 @@
+use crate::loaders::rtscene::types::{
+  ObjectAsset, RtScene, RtSceneCamera, RtSceneInstance, RtSceneObject, SceneLight, SceneShape,
+};
+use crate::render::raytracer::types::RaytraceEnvironment;
+use bevy::prelude::*;
+
+/// Spawns loaded ray traced scenes under the entities holding their handle, and applies their environment.
+pub fn spawn_rtscenes(
+  mut commands: Commands,
+  scenes: Res<Assets<RtScene>>,
+  mut environment: ResMut<RaytraceEnvironment>,
+  roots: Query<(Entity, &Handle<RtScene>), Without<RtSceneInstance>>,
+) {
+  for (root, handle) in roots.iter() {
+    let Some(scene) = scenes.get(handle) else {
+      continue;
+    };
+    let description = &scene.description;
+    commands.entity(root).insert(RtSceneInstance).with_children(|parent| {
+      if let Some(camera) = &description.camera {
+        parent.spawn((
+          Camera3dBundle {
+            camera: Camera {
+              is_active: false,
+              ..default()
+            },
+            projection: camera.projection.projection(),
+            transform: camera.transform.transform(),
+            ..default()
+          },
+          RtSceneCamera {
+            lens: camera.lens,
+            settings: camera.settings.clone(),
+          },
+          Name::new("Camera"),
+        ));
+      }
+
+      for (i, (object, asset)) in description.objects.iter().zip(&scene.objects).enumerate() {
+        let transform = object.transform.transform();
+        let mut entity = match asset {
+          ObjectAsset::Mesh { mesh, material } => parent.spawn(PbrBundle {
+            mesh: mesh.clone(),
+            material: material.clone(),
+            transform,
+            ..default()
+          }),
+          ObjectAsset::Scene(file) => parent.spawn(SceneBundle {
+            scene: file.clone(),
+            transform,
+            ..default()
+          }),
+        };
+        let shape = match object.shape {
+          // Inline meshes are written back from their asset, there is no need to hold on to a copy
+          SceneShape::Mesh { .. } => None,
+          ref shape => Some(shape.clone()),
+        };
+        entity.insert((
+          RtSceneObject {
+            shape,
+            material: object.material.clone(),
+          },
+          object.visibility,
+          Name::new(object.name.clone().unwrap_or_else(|| format!("Object{}", i))),
+        ));
+      }
+
+      for light in &description.lights {
+        spawn_light(parent, light);
+      }
+    });
+    if let Some(scene_environment) = description.environment {
+      environment.set_if_neq(scene_environment);
+    }
+  }
+}
+
+fn spawn_light(parent: &mut ChildBuilder, light: &SceneLight) {
+  match *light {
+    SceneLight::Point {
+      position,
+      color,
+      intensity,
+      range,
+    } => parent.spawn((
+      PointLightBundle {
+        point_light: PointLight {
+          color,
+          intensity,
+          range: range.unwrap_or(PointLight::default().range),
+          shadows_enabled: true,
+          ..default()
+        },
+        transform: Transform::from_translation(position),
+        ..default()
+      },
+      Name::new("PointLight"),
+    )),
+    SceneLight::Spot {
+      transform,
+      color,
+      intensity,
+      inner_angle,
+      outer_angle,
+      range,
+    } => parent.spawn((
+      SpotLightBundle {
+        spot_light: SpotLight {
+          color,
+          intensity,
+          range: range.unwrap_or(SpotLight::default().range),
+          inner_angle: inner_angle.to_radians(),
+          outer_angle: outer_angle.to_radians(),
+          shadows_enabled: true,
+          ..default()
+        },
+        transform: transform.transform(),
+        ..default()
+      },
+      Name::new("SpotLight"),
+    )),
+    SceneLight::Directional {
+      direction,
+      color,
+      illuminance,
+    } => parent.spawn((
+      DirectionalLightBundle {
+        directional_light: DirectionalLight {
+          color,
+          illuminance,
+          shadows_enabled: true,
+          ..default()
+        },
+        transform: looking_to(direction),
+        ..default()
+      },
+      Name::new("DirectionalLight"),
+    )),
+  };
+}
+
+/// Rotation turning -Z along `direction`, with +Y up unless the direction is vertical. A zero direction points down.
+pub fn looking_to(direction: Vec3) -> Transform {
+  let direction = direction.try_normalize().unwrap_or(Vec3::NEG_Y);
+  let up = if direction.cross(Vec3::Y).length_squared() < 1e-6 {
+    Vec3::Z
+  } else {
+    Vec3::Y
+  };
+  Transform::IDENTITY.looking_at(direction, up)
+}
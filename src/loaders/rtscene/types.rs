@@ -0,0 +1,341 @@
+use crate::loaders::triangle_mesh;
+use crate::render::raytracer::types::{RaytraceEnvironment, RaytraceSettings, RaytraceVisibility, ThinLensCamera};
+use bevy::prelude::*;
+use bevy::reflect::TypeUuid;
+use bevy::render::camera::ScalingMode;
+use bevy::render::mesh::PrimitiveTopology;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+/// Contents of a `.rtscene.ron` file. Every field can be left out.
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+#[serde(default)]
+pub struct SceneDescription {
+  pub camera: Option<SceneCamera>,
+  pub environment: Option<RaytraceEnvironment>,
+  /// Materials by name, referenced by [`SceneObject::material`]. Sorted so saved files diff well.
+  pub materials: BTreeMap<String, SceneMaterial>,
+  pub objects: Vec<SceneObject>,
+  pub lights: Vec<SceneLight>,
+}
+
+/// Translation, rotation and scale, or a position looking at a target.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(default)]
+pub struct SceneTransform {
+  pub translation: Vec3,
+  pub rotation: Quat,
+  pub scale: Vec3,
+  /// Turns -Z towards this point with +Y up, replaces `rotation`.
+  pub look_at: Option<Vec3>,
+}
+
+impl Default for SceneTransform {
+  fn default() -> Self {
+    Transform::IDENTITY.into()
+  }
+}
+
+impl SceneTransform {
+  pub fn transform(&self) -> Transform {
+    let transform = Transform {
+      translation: self.translation,
+      rotation: self.rotation,
+      scale: self.scale,
+    };
+    match self.look_at {
+      Some(target) if target != self.translation => transform.looking_at(target, Vec3::Y),
+      _ => transform,
+    }
+  }
+}
+
+impl From<Transform> for SceneTransform {
+  fn from(transform: Transform) -> Self {
+    Self {
+      translation: transform.translation,
+      rotation: transform.rotation,
+      scale: transform.scale,
+      look_at: None,
+    }
+  }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct SceneCamera {
+  #[serde(default)]
+  pub transform: SceneTransform,
+  #[serde(default)]
+  pub projection: SceneProjection,
+  /// Depth of field, a pinhole camera without.
+  #[serde(default)]
+  pub lens: Option<ThinLensCamera>,
+  /// Settings of the ray traced camera looking through this one, the app's own are kept without.
+  #[serde(default)]
+  pub settings: Option<RaytraceSettings>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+pub enum SceneProjection {
+  /// Vertical field of view in degrees.
+  Perspective { fov: f32, near: f32, far: f32 },
+  /// Height of the view in world units.
+  Orthographic { height: f32, near: f32, far: f32 },
+}
+
+impl Default for SceneProjection {
+  fn default() -> Self {
+    let perspective = PerspectiveProjection::default();
+    Self::Perspective {
+      fov: perspective.fov.to_degrees(),
+      near: perspective.near,
+      far: perspective.far,
+    }
+  }
+}
+
+impl SceneProjection {
+  pub fn projection(&self) -> Projection {
+    match *self {
+      Self::Perspective { fov, near, far } => Projection::Perspective(PerspectiveProjection {
+        fov: fov.to_radians(),
+        near,
+        far,
+        ..default()
+      }),
+      Self::Orthographic { height, near, far } => Projection::Orthographic(OrthographicProjection {
+        near,
+        far,
+        scaling_mode: ScalingMode::FixedVertical(height),
+        ..default()
+      }),
+    }
+  }
+}
+
+impl From<&Projection> for SceneProjection {
+  fn from(projection: &Projection) -> Self {
+    match projection {
+      Projection::Perspective(perspective) => Self::Perspective {
+        fov: perspective.fov.to_degrees(),
+        near: perspective.near,
+        far: perspective.far,
+      },
+      Projection::Orthographic(orthographic) => Self::Orthographic {
+        height: orthographic.area.height(),
+        near: orthographic.near,
+        far: orthographic.far,
+      },
+    }
+  }
+}
+
+/// The [`StandardMaterial`] parameters the ray tracer reads.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(default)]
+pub struct SceneMaterial {
+  pub base_color: Color,
+  pub emissive: Color,
+  pub roughness: f32,
+  pub metallic: f32,
+  pub reflectance: f32,
+}
+
+impl Default for SceneMaterial {
+  fn default() -> Self {
+    (&StandardMaterial::default()).into()
+  }
+}
+
+impl SceneMaterial {
+  pub fn standard_material(&self) -> StandardMaterial {
+    StandardMaterial {
+      base_color: self.base_color,
+      emissive: self.emissive,
+      perceptual_roughness: self.roughness,
+      metallic: self.metallic,
+      reflectance: self.reflectance,
+      alpha_mode: if self.base_color.a() < 1.0 {
+        AlphaMode::Blend
+      } else {
+        AlphaMode::Opaque
+      },
+      ..default()
+    }
+  }
+}
+
+impl From<&StandardMaterial> for SceneMaterial {
+  fn from(material: &StandardMaterial) -> Self {
+    Self {
+      base_color: material.base_color,
+      emissive: material.emissive,
+      roughness: material.perceptual_roughness,
+      metallic: material.metallic,
+      reflectance: material.reflectance,
+    }
+  }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct SceneObject {
+  #[serde(default)]
+  pub name: Option<String>,
+  #[serde(default)]
+  pub transform: SceneTransform,
+  pub shape: SceneShape,
+  /// Name in [`SceneDescription::materials`], the default material when unset. Files keep their own materials.
+  #[serde(default)]
+  pub material: Option<String>,
+  #[serde(default)]
+  pub visibility: RaytraceVisibility,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub enum SceneShape {
+  /// glTF, OBJ or PLY file, relative to the scene file.
+  File(String),
+  Sphere {
+    radius: f32,
+  },
+  Box {
+    size: Vec3,
+  },
+  /// Square in the XZ plane facing +Y.
+  Plane {
+    size: f32,
+  },
+  /// Triangle list, flat shaded when `normals` is empty.
+  Mesh {
+    positions: Vec<[f32; 3]>,
+    #[serde(default)]
+    normals: Vec<[f32; 3]>,
+    indices: Vec<u32>,
+  },
+}
+
+impl SceneShape {
+  /// Geometry of every shape but [`SceneShape::File`]. Inline meshes must be triangle lists indexing their positions.
+  pub fn mesh(&self) -> Result<Mesh, String> {
+    Ok(match self {
+      Self::File(file) => return Err(format!("{} is a file, not a mesh", file)),
+      Self::Sphere { radius } => shape::UVSphere {
+        radius: *radius,
+        sectors: 64,
+        stacks: 32,
+      }
+      .into(),
+      Self::Box { size } => shape::Box::new(size.x, size.y, size.z).into(),
+      Self::Plane { size } => shape::Plane {
+        size: *size,
+        subdivisions: 0,
+      }
+      .into(),
+      Self::Mesh {
+        positions,
+        normals,
+        indices,
+      } => {
+        if indices.len() % 3 != 0 {
+          return Err(format!("{} indices do not make triangles", indices.len()));
+        }
+        if let Some(index) = indices.iter().find(|index| **index as usize >= positions.len()) {
+          return Err(format!("index {} out of range of {} positions", index, positions.len()));
+        }
+        triangle_mesh(
+          positions.clone(),
+          (normals.len() == positions.len()).then(|| normals.clone()),
+          indices.clone(),
+        )
+      }
+    })
+  }
+
+  /// [`SceneShape::Mesh`] of a triangle list mesh.
+  pub fn from_mesh(mesh: &Mesh) -> Option<Self> {
+    if mesh.primitive_topology() != PrimitiveTopology::TriangleList {
+      return None;
+    }
+    let positions = mesh.attribute(Mesh::ATTRIBUTE_POSITION)?.as_float3()?.to_vec();
+    let normals = mesh
+      .attribute(Mesh::ATTRIBUTE_NORMAL)
+      .and_then(|normals| normals.as_float3())
+      .map_or(vec![], <[_]>::to_vec);
+    let indices = match mesh.indices() {
+      Some(indices) => indices.iter().map(|index| index as u32).collect(),
+      None => (0..positions.len() as u32).collect(),
+    };
+    Some(Self::Mesh {
+      positions,
+      normals,
+      indices,
+    })
+  }
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+pub enum SceneLight {
+  /// Intensity in lumens, as [`PointLight`].
+  Point {
+    position: Vec3,
+    color: Color,
+    intensity: f32,
+    #[serde(default)]
+    range: Option<f32>,
+  },
+  /// Intensity in lumens, cone angles in degrees from the axis.
+  Spot {
+    #[serde(default)]
+    transform: SceneTransform,
+    color: Color,
+    intensity: f32,
+    inner_angle: f32,
+    outer_angle: f32,
+    #[serde(default)]
+    range: Option<f32>,
+  },
+  /// Illuminance in lux, shining along `direction`.
+  Directional {
+    direction: Vec3,
+    color: Color,
+    illuminance: f32,
+  },
+}
+
+/// Ray traced scene loaded from a `.rtscene.ron` file, spawned by
+/// [`spawn_rtscenes`](crate::loaders::rtscene::systems::spawn_rtscenes) under the entity holding its handle.
+#[derive(TypeUuid)]
+#[uuid = "5b1a8a42-6f0e-4c57-9d36-3f6e1c2a9b7d"]
+pub struct RtScene {
+  pub description: SceneDescription,
+  /// Asset of every object in [`SceneDescription::objects`], in the same order.
+  pub objects: Vec<ObjectAsset>,
+}
+
+pub enum ObjectAsset {
+  Mesh {
+    mesh: Handle<Mesh>,
+    material: Handle<StandardMaterial>,
+  },
+  /// `Scene0` of the referenced file.
+  Scene(Handle<Scene>),
+}
+
+/// Inserted on the root of an [`RtScene`] once its content is spawned.
+#[derive(Component)]
+pub struct RtSceneInstance;
+
+/// Camera of a spawned [`RtScene`]. It renders nothing itself, apps look through it with their ray traced camera.
+#[derive(Component, Clone)]
+pub struct RtSceneCamera {
+  pub lens: Option<ThinLensCamera>,
+  pub settings: Option<RaytraceSettings>,
+}
+
+/// Object spawned from an [`RtScene`], kept to write it back unchanged on save.
+#[derive(Component, Clone)]
+pub struct RtSceneObject {
+  /// `None` for [`SceneShape::Mesh`], which is written back from the mesh asset.
+  pub shape: Option<SceneShape>,
+  pub material: Option<String>,
+}
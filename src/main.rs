@@ -1,15 +1,30 @@
-use crate::app::{reset_iter, rotate_light, AppState};
-use crate::render::raytracer::types::{ShaderMaterial, ShaderMesh, ShaderVertex};
+use crate::app::export::{write_exports, ClipboardContext, ExportJobs};
+use crate::app::scenes::{
+  adopt_scene_cameras, despawn_closed_scenes, handle_scene_requests, save_scenes, scene_args, OpenScenes, RecentScenes,
+  SceneRequest, USAGE,
+};
+use crate::app::{reset_iter, resize_render_targets, rotate_light, AppState};
+use crate::headless::RenderArgs;
+use crate::loaders::LoadersPlugin;
+use crate::render::raytracer::types::{
+  ShaderFrame, ShaderLight, ShaderMaterial, ShaderMesh, ShaderSettings, ShaderVertex,
+};
 use crate::render::raytracer::RaytracePlugin;
+use crate::render::skinning::types::{ShaderSkinInstance, ShaderSkinVertex};
 use crate::render::LightDir;
 use crate::ui::main_menu::main_menu;
 use crate::ui::rt_viewport::RTViewportWindow;
+use crate::ui::scene_browser::SceneBrowserWindow;
+use crate::ui::settings_window::RaytraceSettingsWindow;
 use app::setup;
 use bevy::prelude::*;
 use bevy::window::{ExitCondition, WindowResolution};
 use bevy_editor_pls::{AddEditorWindow, EditorPlugin};
+use std::path::PathBuf;
 
 pub mod app;
+pub mod headless;
+pub mod loaders;
 pub mod render;
 pub mod ui;
 pub mod util;
@@ -18,14 +33,32 @@ fn main() {
   assert_eq!(std::mem::size_of::<ShaderMaterial>() % 16, 0);
   assert_eq!(std::mem::size_of::<ShaderMesh>() % 16, 0);
   assert_eq!(std::mem::size_of::<ShaderVertex>() % 16, 0);
-  App::new()
+  assert_eq!(std::mem::size_of::<ShaderSettings>() % 16, 0);
+  assert_eq!(std::mem::size_of::<ShaderFrame>() % 16, 0);
+  assert_eq!(std::mem::size_of::<ShaderLight>() % 16, 0);
+  assert_eq!(std::mem::size_of::<ShaderSkinVertex>() % 16, 0);
+  // Stride of the padded SkinInstance array in skinning.wgsl
+  assert_eq!(std::mem::size_of::<ShaderSkinInstance>(), 32);
+  let args = std::env::args().collect::<Vec<_>>();
+  if args.get(1).map(String::as_str) == Some("render") {
+    let code = match RenderArgs::parse(&args[2..]) {
+      Ok(args) => headless::run(args),
+      Err(err) => {
+        eprintln!("{}\n{}", err, headless::USAGE);
+        2
+      }
+    };
+    std::process::exit(code);
+  }
+  let mut app = App::new();
+  app
     .add_plugins(
       DefaultPlugins
         .set(WindowPlugin {
           primary_window: Some(Window {
             resolution: WindowResolution::new(1024.0, 768.0),
             title: "Lode Runner".to_string(),
-            resizable: false,
+            resizable: true,
             ..default()
           }),
           exit_condition: ExitCondition::OnAllClosed,
@@ -35,15 +68,51 @@ fn main() {
     )
     .add_plugin(EditorPlugin::default())
     .add_editor_window::<RTViewportWindow>()
+    .add_editor_window::<RaytraceSettingsWindow>()
+    .add_editor_window::<SceneBrowserWindow>()
     .init_resource::<LightDir>()
+    .init_resource::<ExportJobs>()
+    .init_non_send_resource::<ClipboardContext>()
+    .init_resource::<OpenScenes>()
+    .insert_resource(RecentScenes::load())
+    .add_event::<SceneRequest>()
     .add_state::<AppState>()
     .add_startup_system(setup)
     // .add_plugin(EguiPlugin)
+    .add_plugin(LoadersPlugin)
     .add_plugin(RaytracePlugin)
     .add_system(main_menu.in_set(OnUpdate(AppState::MainMenu)))
     // .add_system(debug_ui.in_set(OnUpdate(AppState::Render)))
     .add_system(rotate_light.in_set(OnUpdate(AppState::Render)))
     .add_system(reset_iter.in_set(OnUpdate(AppState::Render)))
-    .insert_resource(ClearColor(Color::BLACK))
-    .run();
+    .add_system(resize_render_targets.after(reset_iter))
+    .add_system(write_exports)
+    .add_system(handle_scene_requests)
+    .add_system(despawn_closed_scenes)
+    .add_system(save_scenes)
+    .add_system(
+      adopt_scene_cameras
+        .in_set(OnUpdate(AppState::Render))
+        .before(reset_iter),
+    )
+    .insert_resource(ClearColor(Color::BLACK));
+  // Scenes from the command line are opened together, the bundled one otherwise
+  let mut scenes = match scene_args(&args[1..]) {
+    Ok(scenes) => scenes,
+    Err(err) => {
+      eprintln!("{}\n{}", err, USAGE);
+      std::process::exit(2);
+    }
+  };
+  if scenes.is_empty() {
+    scenes.push(PathBuf::from("boxge.glb"));
+  }
+  for (i, scene) in scenes.into_iter().enumerate() {
+    app.world.send_event(if i == 0 {
+      SceneRequest::Open(scene)
+    } else {
+      SceneRequest::Add(scene)
+    });
+  }
+  app.run();
 }
@@ -0,0 +1,31 @@
+use crate::render::denoiser::node::DenoiseNode;
+use crate::render::denoiser::pipeline::DenoisePipeline;
+use crate::render::denoiser::systems::{prepare_denoise_textures, queue_denoise_bind_groups};
+use bevy::prelude::*;
+use bevy::render::render_graph::RenderGraph;
+use bevy::render::{RenderApp, RenderSet};
+
+pub mod node;
+pub mod pipeline;
+pub mod systems;
+pub mod types;
+
+/// Filters the ray traced image between the raytrace node and the camera driver, expects the raytrace node to be
+/// in the render graph already.
+pub struct DenoisePlugin;
+
+impl Plugin for DenoisePlugin {
+  fn build(&self, app: &mut App) {
+    let render_app = app.sub_app_mut(RenderApp);
+    render_app
+      .init_resource::<DenoisePipeline>()
+      .add_system(prepare_denoise_textures.in_set(RenderSet::Prepare))
+      .add_system(queue_denoise_bind_groups.in_set(RenderSet::Queue));
+
+    let node = DenoiseNode::from_world(&mut render_app.world);
+    let mut render_graph = render_app.world.resource_mut::<RenderGraph>();
+    render_graph.add_node("denoise", node);
+    render_graph.add_node_edge("raytrace", "denoise");
+    render_graph.add_node_edge("denoise", bevy::render::main_graph::node::CAMERA_DRIVER);
+  }
+}
@@ -0,0 +1,66 @@
+use crate::render::denoiser::pipeline::DenoisePipeline;
+use crate::render::denoiser::types::DenoiseBindGroups;
+use crate::render::raytracer::types::{RaytraceSettings, TextureIter};
+use bevy::prelude::*;
+use bevy::render::render_graph;
+use bevy::render::render_resource::{ComputePassDescriptor, PipelineCache};
+use bevy::render::renderer::RenderContext;
+
+pub struct DenoiseNode {
+  cameras: QueryState<(
+    &'static DenoiseBindGroups,
+    &'static RaytraceSettings,
+    &'static TextureIter,
+  )>,
+}
+
+impl FromWorld for DenoiseNode {
+  fn from_world(world: &mut World) -> Self {
+    Self {
+      cameras: QueryState::new(world),
+    }
+  }
+}
+
+impl render_graph::Node for DenoiseNode {
+  fn update(&mut self, world: &mut World) {
+    self.cameras.update_archetypes(world);
+  }
+
+  fn run(
+    &self,
+    _graph: &mut render_graph::RenderGraphContext,
+    render_context: &mut RenderContext,
+    world: &World,
+  ) -> Result<(), render_graph::NodeRunError> {
+    let pipeline_cache = world.resource::<PipelineCache>();
+    let pipeline = world.resource::<DenoisePipeline>();
+    let (Some(intermediate_pipeline), Some(final_pipeline)) = (
+      pipeline_cache.get_compute_pipeline(pipeline.pipeline),
+      pipeline_cache.get_compute_pipeline(pipeline.final_pipeline),
+    ) else {
+      return Ok(());
+    };
+
+    let mut pass = render_context
+      .command_encoder()
+      .begin_compute_pass(&ComputePassDescriptor::default());
+    for (bind_groups, settings, texture_iter) in self.cameras.iter_manual(world) {
+      // Same condition as the raytrace node, the output of converged cameras is left untouched
+      if !settings.needs_samples(texture_iter.0) {
+        continue;
+      }
+      let size = bind_groups.size;
+      for denoise_pass in &bind_groups.passes {
+        if denoise_pass.last {
+          pass.set_pipeline(final_pipeline);
+        } else {
+          pass.set_pipeline(intermediate_pipeline);
+        }
+        pass.set_bind_group(0, &denoise_pass.bind_group, &[]);
+        pass.dispatch_workgroups((size.x + 7) / 8, (size.y + 7) / 8, 1);
+      }
+    }
+    Ok(())
+  }
+}
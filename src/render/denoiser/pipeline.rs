@@ -0,0 +1,94 @@
+use bevy::prelude::*;
+use bevy::render::render_resource::{
+  BindGroupLayout, BindGroupLayoutDescriptor, BindGroupLayoutEntry, BindingType, BufferBindingType,
+  CachedComputePipelineId, ComputePipelineDescriptor, PipelineCache, ShaderStages, StorageTextureAccess, TextureFormat,
+  TextureSampleType, TextureViewDimension,
+};
+use bevy::render::renderer::RenderDevice;
+use std::borrow::Cow;
+
+#[derive(Resource)]
+pub struct DenoisePipeline {
+  pub bind_group_layout: BindGroupLayout,
+  pub final_bind_group_layout: BindGroupLayout,
+  /// Writes to the intermediate ping-pong textures.
+  pub pipeline: CachedComputePipelineId,
+  /// Writes to the ray tracing output image.
+  pub final_pipeline: CachedComputePipelineId,
+}
+
+fn bind_group_layout(render_device: &RenderDevice, output_format: TextureFormat) -> BindGroupLayout {
+  let texture_entry = |binding| BindGroupLayoutEntry {
+    binding,
+    visibility: ShaderStages::COMPUTE,
+    ty: BindingType::Texture {
+      sample_type: TextureSampleType::Float { filterable: false },
+      view_dimension: TextureViewDimension::D2,
+      multisampled: false,
+    },
+    count: None,
+  };
+  render_device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+    label: None,
+    entries: &[
+      texture_entry(0),
+      texture_entry(1),
+      texture_entry(2),
+      texture_entry(3),
+      BindGroupLayoutEntry {
+        binding: 4,
+        visibility: ShaderStages::COMPUTE,
+        ty: BindingType::StorageTexture {
+          access: StorageTextureAccess::WriteOnly,
+          format: output_format,
+          view_dimension: TextureViewDimension::D2,
+        },
+        count: None,
+      },
+      BindGroupLayoutEntry {
+        binding: 5,
+        visibility: ShaderStages::COMPUTE,
+        ty: BindingType::Buffer {
+          ty: BufferBindingType::Uniform,
+          has_dynamic_offset: false,
+          min_binding_size: None,
+        },
+        count: None,
+      },
+    ],
+  })
+}
+
+impl FromWorld for DenoisePipeline {
+  fn from_world(world: &mut World) -> Self {
+    let render_device = world.resource::<RenderDevice>();
+    let bind_group_layout = bind_group_layout(render_device, TextureFormat::Rgba16Float);
+    let final_bind_group_layout = bind_group_layout(render_device, TextureFormat::Rgba8Unorm);
+
+    let shader = world.resource::<AssetServer>().load("shaders/denoise.wgsl");
+    let pipeline_cache = world.resource::<PipelineCache>();
+    let pipeline = pipeline_cache.queue_compute_pipeline(ComputePipelineDescriptor {
+      label: None,
+      layout: vec![bind_group_layout.clone()],
+      push_constant_ranges: vec![],
+      shader: shader.clone(),
+      shader_defs: vec![],
+      entry_point: Cow::from("main"),
+    });
+    let final_pipeline = pipeline_cache.queue_compute_pipeline(ComputePipelineDescriptor {
+      label: None,
+      layout: vec![final_bind_group_layout.clone()],
+      push_constant_ranges: vec![],
+      shader,
+      shader_defs: vec!["FINAL_PASS".into()],
+      entry_point: Cow::from("main"),
+    });
+
+    DenoisePipeline {
+      bind_group_layout,
+      final_bind_group_layout,
+      pipeline,
+      final_pipeline,
+    }
+  }
+}
@@ -0,0 +1,164 @@
+use crate::render::denoiser::pipeline::DenoisePipeline;
+use crate::render::denoiser::types::{DenoiseBindGroups, DenoiseParams, DenoisePass, DenoiseTextures};
+use crate::render::raytracer::types::{RaytraceSettings, RaytracedCamera};
+use bevy::ecs::system::EntityCommands;
+use bevy::prelude::*;
+use bevy::render::render_asset::RenderAssets;
+use bevy::render::render_resource::{
+  BindGroupDescriptor, BindGroupEntry, BindingResource, BufferInitDescriptor, BufferUsages, Extent3d,
+  TextureDescriptor, TextureDimension, TextureFormat, TextureUsages,
+};
+use bevy::render::renderer::RenderDevice;
+use bevy::render::texture::TextureCache;
+
+pub fn prepare_denoise_textures(
+  mut commands: Commands,
+  mut texture_cache: ResMut<TextureCache>,
+  render_device: Res<RenderDevice>,
+  gpu_images: Res<RenderAssets<Image>>,
+  cameras: Query<(Entity, &RaytracedCamera, &RaytraceSettings)>,
+) {
+  for (entity, camera, settings) in cameras.iter() {
+    if !settings.denoiser.enabled {
+      continue;
+    }
+    let Some(output) = gpu_images.get(&camera.image) else {
+      continue;
+    };
+    let descriptor = TextureDescriptor {
+      label: Some("denoise_texture"),
+      size: Extent3d {
+        width: output.size.x as u32,
+        height: output.size.y as u32,
+        depth_or_array_layers: 1,
+      },
+      mip_level_count: 1,
+      sample_count: 1,
+      dimension: TextureDimension::D2,
+      format: TextureFormat::Rgba16Float,
+      usage: TextureUsages::STORAGE_BINDING | TextureUsages::TEXTURE_BINDING,
+      view_formats: &[],
+    };
+    commands.entity(entity).insert(DenoiseTextures {
+      ping: texture_cache.get(&render_device, descriptor.clone()),
+      pong: texture_cache.get(&render_device, descriptor),
+    });
+  }
+}
+
+pub fn queue_denoise_bind_groups(
+  mut commands: Commands,
+  pipeline: Res<DenoisePipeline>,
+  render_device: Res<RenderDevice>,
+  gpu_images: Res<RenderAssets<Image>>,
+  cameras: Query<(Entity, &RaytracedCamera, &RaytraceSettings, &DenoiseTextures)>,
+) {
+  for (entity, camera, settings, textures) in cameras.iter() {
+    queue_camera_passes(
+      &mut commands.entity(entity),
+      &pipeline,
+      &render_device,
+      &gpu_images,
+      camera,
+      settings,
+      textures,
+    );
+  }
+}
+
+fn queue_camera_passes(
+  commands: &mut EntityCommands,
+  pipeline: &DenoisePipeline,
+  render_device: &RenderDevice,
+  gpu_images: &RenderAssets<Image>,
+  camera: &RaytracedCamera,
+  settings: &RaytraceSettings,
+  textures: &DenoiseTextures,
+) {
+  let denoiser = &settings.denoiser;
+  let (Some(output), Some(accumulation), Some(albedo), Some(normal), Some(depth)) = (
+    gpu_images.get(&camera.image),
+    gpu_images.get(&camera.accumulation),
+    gpu_images.get(&camera.aovs.albedo),
+    gpu_images.get(&camera.aovs.normal),
+    gpu_images.get(&camera.aovs.depth),
+  ) else {
+    return;
+  };
+  let iterations = denoiser.iterations.max(1);
+  let mut passes = vec![];
+  for i in 0..iterations {
+    let first = i == 0;
+    let last = i == iterations - 1;
+    // Even passes write to ping and odd ones to pong, the last pass writes to the output image
+    let input = if first {
+      &accumulation.texture_view
+    } else if i % 2 == 1 {
+      &textures.ping.default_view
+    } else {
+      &textures.pong.default_view
+    };
+    let target = if last {
+      &output.texture_view
+    } else if i % 2 == 0 {
+      &textures.ping.default_view
+    } else {
+      &textures.pong.default_view
+    };
+
+    let params_buffer = render_device.create_buffer_with_data(&BufferInitDescriptor {
+      label: None,
+      contents: bytemuck::bytes_of(&DenoiseParams {
+        step: 1 << i,
+        first: first as u32,
+        last: last as u32,
+        sigma_color: denoiser.sigma_color,
+        sigma_normal: denoiser.sigma_normal,
+        sigma_depth: denoiser.sigma_depth,
+        pad: [0; 8],
+      }),
+      usage: BufferUsages::COPY_SRC | BufferUsages::UNIFORM,
+    });
+
+    let bind_group = render_device.create_bind_group(&BindGroupDescriptor {
+      label: None,
+      layout: if last {
+        &pipeline.final_bind_group_layout
+      } else {
+        &pipeline.bind_group_layout
+      },
+      entries: &[
+        BindGroupEntry {
+          binding: 0,
+          resource: BindingResource::TextureView(input),
+        },
+        BindGroupEntry {
+          binding: 1,
+          resource: BindingResource::TextureView(&albedo.texture_view),
+        },
+        BindGroupEntry {
+          binding: 2,
+          resource: BindingResource::TextureView(&normal.texture_view),
+        },
+        BindGroupEntry {
+          binding: 3,
+          resource: BindingResource::TextureView(&depth.texture_view),
+        },
+        BindGroupEntry {
+          binding: 4,
+          resource: BindingResource::TextureView(target),
+        },
+        BindGroupEntry {
+          binding: 5,
+          resource: BindingResource::Buffer(params_buffer.as_entire_buffer_binding()),
+        },
+      ],
+    });
+    passes.push(DenoisePass { bind_group, last });
+  }
+
+  commands.insert(DenoiseBindGroups {
+    passes,
+    size: output.size.as_uvec2(),
+  });
+}
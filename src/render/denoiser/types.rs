@@ -0,0 +1,33 @@
+use bevy::prelude::*;
+use bevy::render::render_resource::BindGroup;
+use bevy::render::texture::CachedTexture;
+use bytemuck::{Pod, Zeroable};
+
+#[derive(Component)]
+pub struct DenoiseTextures {
+  pub ping: CachedTexture,
+  pub pong: CachedTexture,
+}
+
+pub struct DenoisePass {
+  pub bind_group: BindGroup,
+  pub last: bool,
+}
+
+#[derive(Component)]
+pub struct DenoiseBindGroups {
+  pub passes: Vec<DenoisePass>,
+  pub size: UVec2,
+}
+
+#[repr(C)]
+#[derive(Zeroable, Pod, Copy, Clone)]
+pub struct DenoiseParams {
+  pub step: i32,
+  pub first: u32,
+  pub last: u32,
+  pub sigma_color: f32,
+  pub sigma_normal: f32,
+  pub sigma_depth: f32,
+  pub pad: [u8; 8],
+}
@@ -0,0 +1,81 @@
+use bevy::render::render_resource::{Buffer, BufferDescriptor, BufferUsages};
+use bevy::render::renderer::{RenderDevice, RenderQueue};
+use bytemuck::Pod;
+
+/// Smallest allocation, storage buffers can't be empty.
+const MIN_SIZE: u64 = 256;
+
+/// GPU buffer that is kept across frames and only reallocated, to the next power of two, when the contents outgrow
+/// it. Uploads are diffed against the previous contents so that only the changed ranges are written.
+pub struct GrowableBuffer {
+  label: &'static str,
+  usage: BufferUsages,
+  buffer: Option<Buffer>,
+  /// What the GPU holds, compared against new contents chunk by chunk.
+  contents: Vec<u8>,
+}
+
+impl GrowableBuffer {
+  pub fn new(label: &'static str, usage: BufferUsages) -> Self {
+    Self {
+      label,
+      usage: usage | BufferUsages::COPY_DST,
+      buffer: None,
+      contents: vec![],
+    }
+  }
+
+  /// `None` until the first write.
+  pub fn buffer(&self) -> Option<&Buffer> {
+    self.buffer.as_ref()
+  }
+
+  pub fn write<T: Pod>(&mut self, render_device: &RenderDevice, render_queue: &RenderQueue, items: &[T]) {
+    self.write_bytes(
+      render_device,
+      render_queue,
+      bytemuck::cast_slice(items),
+      std::mem::size_of::<T>(),
+    );
+  }
+
+  /// Replaces the contents, comparing them in chunks of `chunk` bytes. Chunks have to be a multiple of 4 bytes, the
+  /// copy alignment of wgpu.
+  pub fn write_bytes(&mut self, render_device: &RenderDevice, render_queue: &RenderQueue, bytes: &[u8], chunk: usize) {
+    let size = (bytes.len() as u64).max(MIN_SIZE);
+    if self.buffer.as_ref().map_or(true, |buffer| buffer.size() < size) {
+      let buffer = render_device.create_buffer(&BufferDescriptor {
+        label: Some(self.label),
+        size: size.next_power_of_two(),
+        usage: self.usage,
+        mapped_at_creation: false,
+      });
+      render_queue.write_buffer(&buffer, 0, bytes);
+      self.buffer = Some(buffer);
+      self.contents = bytes.to_vec();
+      return;
+    }
+    let Some(buffer) = &self.buffer else {
+      return;
+    };
+
+    let mut changed_from = None;
+    for (i, new) in bytes.chunks(chunk.max(1)).enumerate() {
+      let start = i * chunk;
+      let changed = self.contents.get(start..start + new.len()) != Some(new);
+      match (changed, changed_from) {
+        (true, None) => changed_from = Some(start),
+        (false, Some(from)) => {
+          render_queue.write_buffer(buffer, from as u64, &bytes[from..start]);
+          changed_from = None;
+        }
+        _ => {}
+      }
+    }
+    if let Some(from) = changed_from {
+      render_queue.write_buffer(buffer, from as u64, &bytes[from..]);
+    }
+    self.contents.clear();
+    self.contents.extend_from_slice(bytes);
+  }
+}
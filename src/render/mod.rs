@@ -2,7 +2,11 @@ use bevy::prelude::*;
 use bevy::render::extract_resource::ExtractResource;
 use bytemuck_derive::{Pod, Zeroable};
 
+pub mod denoiser;
+pub mod growable_buffer;
 pub mod raytracer;
+pub mod readback;
+pub mod skinning;
 
 #[derive(Copy, Clone, Pod, Zeroable)]
 #[repr(C)]
@@ -1,11 +1,20 @@
+use crate::render::denoiser::DenoisePlugin;
 use crate::render::raytracer::node::RayTraceNode;
 use crate::render::raytracer::pipeline::RaytracingPipeline;
-use crate::render::raytracer::systems::{extract_meshes, prepare_meshes, queue_bind_group};
+use crate::render::raytracer::systems::{
+  extract_camera_render_layers, extract_lights, extract_meshes, prepare_history_textures, prepare_meshes,
+  queue_bind_group, update_previous_transforms,
+};
 use crate::render::raytracer::types::{
-  MaterialStorage, MeshStorage, PBRCameraEntity, RaytracingImage, TextureIter, VertexStorage,
+  ExtractedLights, HistoryTextureCache, ImageBindGroupCache, MaterialStorage, MeshStorage, PipelineReady,
+  PreviousGlobalTransform, RaytraceEnvironment, RaytraceMarkerPolicy, RaytraceSettings, RaytracedCamera, SceneBuffers,
+  TextureIter, ThinLensCamera, VertexStorage, ViewMotion,
 };
+use crate::render::readback::ReadbackPlugin;
+use crate::render::skinning::SkinningPlugin;
 use crate::render::LightDir;
 use bevy::prelude::*;
+use bevy::render::extract_component::ExtractComponentPlugin;
 use bevy::render::extract_resource::ExtractResourcePlugin;
 use bevy::render::render_graph::RenderGraph;
 use bevy::render::{RenderApp, RenderSet};
@@ -15,29 +24,48 @@ pub mod pipeline;
 pub mod systems;
 pub mod types;
 
-pub const SIZE: [u32; 2] = [1024, 768];
-
 pub struct RaytracePlugin;
 
 impl Plugin for RaytracePlugin {
   fn build(&self, app: &mut App) {
-    app.insert_resource(TextureIter(0));
-    app.add_plugin(ExtractResourcePlugin::<TextureIter>::default());
-    app.add_plugin(ExtractResourcePlugin::<RaytracingImage>::default());
-    app.add_plugin(ExtractResourcePlugin::<PBRCameraEntity>::default());
+    let pipeline_ready = PipelineReady::default();
+    app.insert_resource(pipeline_ready.clone());
+    app.init_resource::<RaytraceMarkerPolicy>();
+    app.init_resource::<RaytraceEnvironment>();
     app.add_plugin(ExtractResourcePlugin::<LightDir>::default());
+    app.add_plugin(ExtractResourcePlugin::<RaytraceEnvironment>::default());
+    app.add_plugin(ExtractComponentPlugin::<RaytracedCamera>::default());
+    app.add_plugin(ExtractComponentPlugin::<RaytraceSettings>::default());
+    app.add_plugin(ExtractComponentPlugin::<TextureIter>::default());
+    app.add_plugin(ExtractComponentPlugin::<ViewMotion>::default());
+    app.add_plugin(ExtractComponentPlugin::<ThinLensCamera>::default());
+    app.add_plugin(ExtractComponentPlugin::<PreviousGlobalTransform>::default());
+    app.add_system(update_previous_transforms.in_base_set(CoreSet::First));
     let render_app = app.sub_app_mut(RenderApp);
     render_app
+      .insert_resource(pipeline_ready)
       .init_resource::<RaytracingPipeline>()
       .init_resource::<VertexStorage>()
       .init_resource::<MeshStorage>()
       .init_resource::<MaterialStorage>()
+      .init_resource::<SceneBuffers>()
+      .init_resource::<HistoryTextureCache>()
+      .init_resource::<ImageBindGroupCache>()
+      .init_resource::<ExtractedLights>()
+      .add_system(extract_camera_render_layers.in_schedule(ExtractSchedule))
+      .add_system(extract_lights.in_schedule(ExtractSchedule))
       .add_system(extract_meshes.in_schedule(ExtractSchedule))
       .add_system(prepare_meshes.in_set(RenderSet::Prepare))
+      .add_system(prepare_history_textures.in_set(RenderSet::Prepare))
       .add_system(queue_bind_group.in_set(RenderSet::Queue));
 
+    let node = RayTraceNode::from_world(&mut render_app.world);
     let mut render_graph = render_app.world.resource_mut::<RenderGraph>();
-    render_graph.add_node("raytrace", RayTraceNode { view: None });
+    render_graph.add_node("raytrace", node);
     render_graph.add_node_edge("raytrace", bevy::render::main_graph::node::CAMERA_DRIVER);
+
+    app.add_plugin(SkinningPlugin);
+    app.add_plugin(DenoisePlugin);
+    app.add_plugin(ReadbackPlugin);
   }
 }
@@ -1,25 +1,43 @@
 use crate::render::raytracer::pipeline::RaytracingPipeline;
-use crate::render::raytracer::types::{PBRCameraEntity, RaytracingBindGroups, TextureIter};
-use crate::render::raytracer::SIZE;
+use crate::render::raytracer::types::{
+  CameraBindGroups, HistoryTextures, PipelineReady, RaytraceSettings, RaytracedCamera, RaytracingBindGroups,
+  TextureIter,
+};
 use bevy::prelude::*;
+use bevy::render::render_asset::RenderAssets;
 use bevy::render::render_graph;
-use bevy::render::render_resource::{BindGroupDescriptor, BindGroupEntry, ComputePassDescriptor, PipelineCache};
+use bevy::render::render_resource::{
+  BindGroupDescriptor, BindGroupEntry, ComputePassDescriptor, Extent3d, PipelineCache, Texture,
+};
 use bevy::render::renderer::RenderContext;
-use bevy::render::view::{ExtractedView, ViewUniformOffset, ViewUniforms};
+use bevy::render::view::{ViewUniformOffset, ViewUniforms};
+use std::sync::atomic::Ordering;
 
+type CameraQuery = (
+  &'static ViewUniformOffset,
+  &'static CameraBindGroups,
+  &'static RaytracedCamera,
+  &'static RaytraceSettings,
+  &'static TextureIter,
+  Option<&'static HistoryTextures>,
+);
+
+/// Traces one frame for every ray traced camera.
 pub struct RayTraceNode {
-  pub view: Option<u32>,
+  cameras: QueryState<CameraQuery>,
+}
+
+impl FromWorld for RayTraceNode {
+  fn from_world(world: &mut World) -> Self {
+    Self {
+      cameras: QueryState::new(world),
+    }
+  }
 }
 
 impl render_graph::Node for RayTraceNode {
   fn update(&mut self, world: &mut World) {
-    let entity = world.resource::<PBRCameraEntity>().0;
-    let view = world
-      .query_filtered::<&ViewUniformOffset, With<ExtractedView>>()
-      .get(world, entity)
-      .ok()
-      .map(|x| x.offset);
-    self.view = view.or(self.view);
+    self.cameras.update_archetypes(world);
   }
 
   fn run(
@@ -28,41 +46,87 @@ impl render_graph::Node for RayTraceNode {
     render_context: &mut RenderContext,
     world: &World,
   ) -> Result<(), render_graph::NodeRunError> {
-    if world.resource::<TextureIter>().0 > 100 {
+    let Some(bind_groups) = world.get_resource::<RaytracingBindGroups>() else {
       return Ok(());
-    }
-    let bind_groups = &world.resource::<RaytracingBindGroups>();
+    };
     let pipeline_cache = world.resource::<PipelineCache>();
     let pipeline = world.resource::<RaytracingPipeline>();
+    let Some(compute_pipeline) = pipeline_cache.get_compute_pipeline(pipeline.pipeline) else {
+      return Ok(());
+    };
+    let Some(view_binding) = world.resource::<ViewUniforms>().uniforms.binding() else {
+      return Ok(());
+    };
 
-    let view_uniforms_resource = world.resource::<ViewUniforms>();
-    let view_uniforms = &view_uniforms_resource.uniforms;
+    // Converged cameras keep their last image
+    let cameras = self
+      .cameras
+      .iter_manual(world)
+      .filter(|(_, _, _, settings, texture_iter, _)| settings.needs_samples(texture_iter.0))
+      .collect::<Vec<_>>();
+    for (_, _, camera, settings, _, history) in &cameras {
+      if let (true, Some(history)) = (settings.temporal_reprojection(), history) {
+        copy_history(render_context, world, camera, history);
+      }
+    }
 
-    let entries = vec![BindGroupEntry {
-      binding: 0,
-      resource: view_uniforms.binding().unwrap(),
-    }];
-    let bind_group = render_context.render_device().create_bind_group(&BindGroupDescriptor {
+    let view_bind_group = render_context.render_device().create_bind_group(&BindGroupDescriptor {
       label: None,
       layout: &pipeline.view_bind_group_layout,
-      entries: &entries,
+      entries: &[BindGroupEntry {
+        binding: 0,
+        resource: view_binding,
+      }],
     });
 
     let mut pass = render_context
       .command_encoder()
       .begin_compute_pass(&ComputePassDescriptor::default());
-
-    pass.set_bind_group(0, &bind_group, &[self.view.unwrap()]);
-    pass.set_bind_group(1, &bind_groups.image, &[]);
+    pass.set_pipeline(compute_pipeline);
     pass.set_bind_group(2, &bind_groups.meshes, &[]);
     pass.set_bind_group(3, &bind_groups.materials, &[]);
-    pass.set_bind_group(4, &bind_groups.light_dir, &[]);
-    pass.set_bind_group(5, &bind_groups.seed, &[]);
-
-    if let Some(pipeline) = pipeline_cache.get_compute_pipeline(pipeline.pipeline) {
-      pass.set_pipeline(pipeline);
-      pass.dispatch_workgroups(SIZE[0] / 32, SIZE[1] / 32, 1);
+    pass.set_bind_group(4, &bind_groups.frame, &[]);
+    for (view_offset, camera_bind_groups, ..) in cameras {
+      pass.set_bind_group(0, &view_bind_group, &[view_offset.offset]);
+      pass.set_bind_group(1, &camera_bind_groups.image, &[]);
+      pass.set_bind_group(5, &bind_groups.settings, &[camera_bind_groups.settings_offset]);
+      let size = camera_bind_groups.size;
+      pass.dispatch_workgroups((size.x + 31) / 32, (size.y + 31) / 32, 1);
     }
+    world.resource::<PipelineReady>().store(true, Ordering::Relaxed);
     Ok(())
   }
 }
+
+/// Snapshots last frame's accumulation and first hit AOVs before the pass overwrites them.
+fn copy_history(
+  render_context: &mut RenderContext,
+  world: &World,
+  camera: &RaytracedCamera,
+  history: &HistoryTextures,
+) {
+  let gpu_images = world.resource::<RenderAssets<Image>>();
+  let sources = [
+    (&camera.accumulation, &history.color.texture),
+    (&camera.aovs.normal, &history.normal.texture),
+    (&camera.aovs.depth, &history.depth.texture),
+  ];
+  for (image, target) in sources {
+    let Some(source) = gpu_images.get(image) else {
+      continue;
+    };
+    copy_texture(render_context, &source.texture, target, source.size.as_uvec2());
+  }
+}
+
+fn copy_texture(render_context: &mut RenderContext, source: &Texture, target: &Texture, size: UVec2) {
+  render_context.command_encoder().copy_texture_to_texture(
+    source.as_image_copy(),
+    target.as_image_copy(),
+    Extent3d {
+      width: size.x,
+      height: size.y,
+      depth_or_array_layers: 1,
+    },
+  );
+}
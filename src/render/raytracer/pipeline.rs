@@ -1,8 +1,9 @@
+use crate::render::raytracer::types::{ShaderFrame, ShaderSettings};
 use bevy::prelude::*;
 use bevy::render::render_resource::{
-  BindGroupLayout, BindGroupLayoutDescriptor, BindGroupLayoutEntry, BindingType, BufferBindingType,
+  BindGroupLayout, BindGroupLayoutDescriptor, BindGroupLayoutEntry, BindingType, BufferBindingType, BufferSize,
   CachedComputePipelineId, ComputePipelineDescriptor, PipelineCache, ShaderStages, ShaderType, StorageTextureAccess,
-  TextureFormat, TextureViewDimension,
+  TextureFormat, TextureSampleType, TextureViewDimension,
 };
 use bevy::render::renderer::RenderDevice;
 use bevy::render::view::ViewUniform;
@@ -14,8 +15,8 @@ pub struct RaytracingPipeline {
   pub texture_bind_group_layout: BindGroupLayout,
   pub meshes_bind_group_layout: BindGroupLayout,
   pub materials_bind_group_layout: BindGroupLayout,
-  pub light_dir_bind_group_layout: BindGroupLayout,
-  pub seed_bind_group_layout: BindGroupLayout,
+  pub frame_bind_group_layout: BindGroupLayout,
+  pub settings_bind_group_layout: BindGroupLayout,
   pub pipeline: CachedComputePipelineId,
 }
 
@@ -56,10 +57,70 @@ impl FromWorld for RaytracingPipeline {
             BindGroupLayoutEntry {
               binding: 1,
               visibility: ShaderStages::COMPUTE,
-              ty: BindingType::Buffer {
-                ty: BufferBindingType::Uniform,
-                has_dynamic_offset: false,
-                min_binding_size: None,
+              ty: BindingType::StorageTexture {
+                access: StorageTextureAccess::ReadWrite,
+                format: TextureFormat::Rgba32Float,
+                view_dimension: TextureViewDimension::D2,
+              },
+              count: None,
+            },
+            BindGroupLayoutEntry {
+              binding: 2,
+              visibility: ShaderStages::COMPUTE,
+              ty: BindingType::StorageTexture {
+                access: StorageTextureAccess::ReadWrite,
+                format: TextureFormat::Rgba32Float,
+                view_dimension: TextureViewDimension::D2,
+              },
+              count: None,
+            },
+            BindGroupLayoutEntry {
+              binding: 3,
+              visibility: ShaderStages::COMPUTE,
+              ty: BindingType::StorageTexture {
+                access: StorageTextureAccess::ReadWrite,
+                format: TextureFormat::Rgba32Float,
+                view_dimension: TextureViewDimension::D2,
+              },
+              count: None,
+            },
+            BindGroupLayoutEntry {
+              binding: 4,
+              visibility: ShaderStages::COMPUTE,
+              ty: BindingType::StorageTexture {
+                access: StorageTextureAccess::ReadWrite,
+                format: TextureFormat::Rgba32Float,
+                view_dimension: TextureViewDimension::D2,
+              },
+              count: None,
+            },
+            BindGroupLayoutEntry {
+              binding: 5,
+              visibility: ShaderStages::COMPUTE,
+              ty: BindingType::Texture {
+                sample_type: TextureSampleType::Float { filterable: false },
+                view_dimension: TextureViewDimension::D2,
+                multisampled: false,
+              },
+              count: None,
+            },
+            BindGroupLayoutEntry {
+              binding: 6,
+              visibility: ShaderStages::COMPUTE,
+              ty: BindingType::Texture {
+                sample_type: TextureSampleType::Float { filterable: false },
+                view_dimension: TextureViewDimension::D2,
+                multisampled: false,
+              },
+              count: None,
+            },
+            BindGroupLayoutEntry {
+              binding: 7,
+              visibility: ShaderStages::COMPUTE,
+              ty: BindingType::Texture {
+                sample_type: TextureSampleType::Float { filterable: false },
+                view_dimension: TextureViewDimension::D2,
+                multisampled: false,
               },
               count: None,
             },
@@ -101,16 +162,6 @@ impl FromWorld for RaytracingPipeline {
               },
               count: None,
             },
-            BindGroupLayoutEntry {
-              binding: 3,
-              visibility: ShaderStages::COMPUTE,
-              ty: BindingType::Buffer {
-                ty: BufferBindingType::Uniform,
-                has_dynamic_offset: false,
-                min_binding_size: None,
-              },
-              count: None,
-            },
           ],
         });
     let materials_bind_group_layout =
@@ -130,7 +181,7 @@ impl FromWorld for RaytracingPipeline {
           }],
         });
 
-    let light_dir_bind_group_layout =
+    let frame_bind_group_layout =
       world
         .resource::<RenderDevice>()
         .create_bind_group_layout(&BindGroupLayoutDescriptor {
@@ -141,13 +192,13 @@ impl FromWorld for RaytracingPipeline {
             ty: BindingType::Buffer {
               ty: BufferBindingType::Uniform,
               has_dynamic_offset: false,
-              min_binding_size: None,
+              min_binding_size: BufferSize::new(std::mem::size_of::<ShaderFrame>() as u64),
             },
             count: None,
           }],
         });
 
-    let seed_bind_group_layout =
+    let settings_bind_group_layout =
       world
         .resource::<RenderDevice>()
         .create_bind_group_layout(&BindGroupLayoutDescriptor {
@@ -157,8 +208,8 @@ impl FromWorld for RaytracingPipeline {
             visibility: ShaderStages::COMPUTE,
             ty: BindingType::Buffer {
               ty: BufferBindingType::Uniform,
-              has_dynamic_offset: false,
-              min_binding_size: None,
+              has_dynamic_offset: true,
+              min_binding_size: BufferSize::new(std::mem::size_of::<ShaderSettings>() as u64),
             },
             count: None,
           }],
@@ -173,8 +224,8 @@ impl FromWorld for RaytracingPipeline {
         texture_bind_group_layout.clone(),
         meshes_bind_group_layout.clone(),
         materials_bind_group_layout.clone(),
-        light_dir_bind_group_layout.clone(),
-        seed_bind_group_layout.clone(),
+        frame_bind_group_layout.clone(),
+        settings_bind_group_layout.clone(),
       ],
       push_constant_ranges: vec![],
       shader,
@@ -187,8 +238,8 @@ impl FromWorld for RaytracingPipeline {
       texture_bind_group_layout,
       meshes_bind_group_layout,
       materials_bind_group_layout,
-      light_dir_bind_group_layout,
-      seed_bind_group_layout,
+      frame_bind_group_layout,
+      settings_bind_group_layout,
       pipeline,
     }
   }
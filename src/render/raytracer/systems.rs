@@ -1,286 +1,847 @@
 use crate::render::raytracer::pipeline::RaytracingPipeline;
 use crate::render::raytracer::types::{
-  ExtractedMesh, MaterialBuffer, MaterialStorage, MeshBuffer, MeshStorage, RaytracingBindGroups, RaytracingImage,
-  ShaderMaterial, ShaderMesh, ShaderVertex, TextureIter, VertexBuffer, VertexStorage,
+  render_layer_mask, CameraBindGroups, ExtractedLights, ExtractedMesh, HistoryTextureCache, HistoryTextures,
+  ImageBindGroupCache, MaterialStorage, MeshGeometry, MeshRange, MeshStorage, NotRaytraced, PreviousGlobalTransform,
+  RaytraceEnvironment, RaytraceMarkerPolicy, RaytraceSettings, RaytraceVisibility, Raytraced, RaytracedCamera,
+  RaytracingBindGroups, SceneBuffers, ShaderFrame, ShaderLight, ShaderMaterial, ShaderMesh, ShaderSettings,
+  ShaderVertex, TextureIter, ThinLensCamera, VertexStorage, ViewMotion, MAX_LIGHTS,
 };
+use crate::render::skinning::types::ShaderSkinVertex;
 use crate::render::LightDir;
+use crate::util::triangulate::triangulate;
+use bevy::asset::{Asset, HandleId};
 use bevy::prelude::*;
+use bevy::render::mesh::skinning::SkinnedMesh;
 use bevy::render::render_asset::RenderAssets;
 use bevy::render::render_resource::{
-  BindGroupDescriptor, BindGroupEntry, BindingResource, BufferInitDescriptor, BufferUsages,
+  BindGroupDescriptor, BindGroupEntry, BindingResource, BufferBinding, BufferSize, Extent3d, TextureDescriptor,
+  TextureDimension, TextureFormat, TextureUsages, TextureViewDescriptor,
 };
-use bevy::render::renderer::RenderDevice;
+use bevy::render::renderer::{RenderDevice, RenderQueue};
+use bevy::render::texture::CachedTexture;
+use bevy::render::view::{ExtractedView, RenderLayers};
 use bevy::render::Extract;
-use bevy::utils::HashMap;
+use bevy::utils::HashSet;
 use bevy_editor_pls::prelude::NotInScene;
 use itertools::Itertools;
-use rand::Rng;
 
+pub fn prepare_history_textures(
+  mut commands: Commands,
+  mut cache: ResMut<HistoryTextureCache>,
+  render_device: Res<RenderDevice>,
+  gpu_images: Res<RenderAssets<Image>>,
+  cameras: Query<(Entity, &RaytracedCamera)>,
+) {
+  cache.0.retain(|entity, _| cameras.contains(*entity));
+  for (entity, camera) in cameras.iter() {
+    let Some(accumulation) = gpu_images.get(&camera.accumulation) else {
+      continue;
+    };
+    let size = accumulation.size.as_uvec2();
+    if let Some(history) = cache.0.get(&entity).filter(|history| history.size == size) {
+      commands.entity(entity).insert(history.clone());
+      continue;
+    }
+    let descriptor = TextureDescriptor {
+      label: Some("history_texture"),
+      size: Extent3d {
+        width: size.x,
+        height: size.y,
+        depth_or_array_layers: 1,
+      },
+      mip_level_count: 1,
+      sample_count: 1,
+      dimension: TextureDimension::D2,
+      format: TextureFormat::Rgba32Float,
+      usage: TextureUsages::COPY_DST | TextureUsages::TEXTURE_BINDING,
+      view_formats: &[],
+    };
+    let create = || {
+      let texture = render_device.create_texture(&descriptor);
+      CachedTexture {
+        default_view: texture.create_view(&TextureViewDescriptor::default()),
+        texture,
+      }
+    };
+    let history = HistoryTextures {
+      size,
+      color: create(),
+      normal: create(),
+      depth: create(),
+    };
+    cache.0.insert(entity, history.clone());
+    commands.entity(entity).insert(history);
+  }
+}
+
+/// Uploads the frame uniforms and hands out the bind groups, which are only rebuilt when a buffer was reallocated or
+/// a camera's images changed.
+#[allow(clippy::too_many_arguments)]
 pub fn queue_bind_group(
   mut commands: Commands,
   pipeline: Res<RaytracingPipeline>,
   gpu_images: Res<RenderAssets<Image>>,
-  image: Res<RaytracingImage>,
   render_device: Res<RenderDevice>,
+  render_queue: Res<RenderQueue>,
   light_dir: Res<LightDir>,
-  texture_iter: Res<TextureIter>,
-  mesh_storage: Res<MeshStorage>,
-  vertex_buffer: Res<VertexBuffer>,
-  mesh_buffer: Res<MeshBuffer>,
-  material_buffer: Res<MaterialBuffer>,
+  environment: Res<RaytraceEnvironment>,
+  lights: Res<ExtractedLights>,
+  cameras: Query<(
+    Entity,
+    &ExtractedView,
+    &RaytracedCamera,
+    &RaytraceSettings,
+    &TextureIter,
+    &ViewMotion,
+    &HistoryTextures,
+    Option<&ThinLensCamera>,
+    Option<&PreviousGlobalTransform>,
+    Option<&RenderLayers>,
+  )>,
+  mut buffers: ResMut<SceneBuffers>,
+  mut image_bind_groups: ResMut<ImageBindGroupCache>,
+  scene_bind_groups: Option<Res<RaytracingBindGroups>>,
 ) {
-  let view = &gpu_images[&image.0];
+  // Every block of the uniform buffer starts at a valid dynamic offset
+  let alignment = render_device.limits().min_uniform_buffer_offset_alignment as usize;
+  let stride = std::mem::size_of::<ShaderSettings>().max(std::mem::size_of::<ShaderFrame>());
+  let stride = (stride + alignment - 1) / alignment * alignment;
+  let mut frame = ShaderFrame {
+    light_dir: light_dir.dir,
+    mesh_count: buffers.mesh_count,
+    environment: environment.shader_environment(),
+    light_count: lights.0.len() as u32,
+    pad: [0; 3],
+    lights: [ShaderLight::default(); MAX_LIGHTS],
+  };
+  frame.lights[..lights.0.len()].copy_from_slice(&lights.0);
+  let mut uniforms = bytemuck::bytes_of(&frame).to_vec();
+  uniforms.resize(stride, 0);
 
-  let texture_iter_buffer = render_device.create_buffer_with_data(&BufferInitDescriptor {
-    label: None,
-    contents: bytemuck::cast_slice(&[texture_iter.0]),
-    usage: BufferUsages::COPY_SRC | BufferUsages::UNIFORM,
-  });
+  image_bind_groups.0.retain(|entity, _| cameras.contains(*entity));
+  for (entity, view, camera, settings, texture_iter, motion, history, lens, previous, render_layers) in cameras.iter() {
+    // Images of a freshly spawned or resized camera are uploaded a frame later
+    let (Some(image), Some(accumulation), Some(albedo), Some(normal), Some(depth)) = (
+      gpu_images.get(&camera.image),
+      gpu_images.get(&camera.accumulation),
+      gpu_images.get(&camera.aovs.albedo),
+      gpu_images.get(&camera.aovs.normal),
+      gpu_images.get(&camera.aovs.depth),
+    ) else {
+      continue;
+    };
 
-  let image_bind_group = render_device.create_bind_group(&BindGroupDescriptor {
-    label: None,
-    layout: &pipeline.texture_bind_group_layout,
-    entries: &[
-      BindGroupEntry {
-        binding: 0,
-        resource: BindingResource::TextureView(&view.texture_view),
-      },
-      BindGroupEntry {
-        binding: 1,
-        resource: BindingResource::Buffer(texture_iter_buffer.as_entire_buffer_binding()),
-      },
-    ],
-  });
+    let views = [
+      &image.texture_view,
+      &accumulation.texture_view,
+      &albedo.texture_view,
+      &normal.texture_view,
+      &depth.texture_view,
+      &history.color.default_view,
+      &history.normal.default_view,
+      &history.depth.default_view,
+    ];
+    let view_ids = views.iter().map(|view| view.id()).collect::<Vec<_>>();
+    let image_bind_group = match image_bind_groups.0.get(&entity) {
+      Some((bound, bind_group)) if *bound == view_ids => bind_group.clone(),
+      _ => {
+        let entries = views
+          .iter()
+          .enumerate()
+          .map(|(binding, view)| BindGroupEntry {
+            binding: binding as u32,
+            resource: BindingResource::TextureView(view),
+          })
+          .collect::<Vec<_>>();
+        let bind_group = render_device.create_bind_group(&BindGroupDescriptor {
+          label: Some("raytrace_image_bind_group"),
+          layout: &pipeline.texture_bind_group_layout,
+          entries: &entries,
+        });
+        image_bind_groups.0.insert(entity, (view_ids, bind_group.clone()));
+        bind_group
+      }
+    };
 
-  let num_of_meshes_buffer = render_device.create_buffer_with_data(&BufferInitDescriptor {
-    label: None,
-    contents: (mesh_storage.meshes.len() as u32).to_le_bytes().as_slice(),
-    usage: BufferUsages::COPY_SRC | BufferUsages::UNIFORM,
-  });
+    let image_size = image.size;
+    let previous_inverse_view = previous.map_or(view.transform, |previous| previous.0).compute_matrix();
+    let lens_radius = lens.map_or(0.0, |lens| lens.lens_radius(&view.projection));
+    let lens = lens.copied().unwrap_or_default();
+    // The projection follows the camera target, widen or narrow it to the aspect ratio of the image
+    let viewport = view.viewport.zw().as_vec2().max(Vec2::ONE);
+    let aspect_scale = (image_size.x / image_size.y) / (viewport.x / viewport.y);
+    let temporal = &settings.temporal;
+    let (shutter_open, shutter_close) = if settings.motion_blur {
+      (settings.shutter_open, settings.shutter_close)
+    } else {
+      (1.0, 1.0)
+    };
 
-  let materials_bind_group = render_device.create_bind_group(&BindGroupDescriptor {
-    label: None,
-    layout: &pipeline.materials_bind_group_layout,
-    entries: &[BindGroupEntry {
-      binding: 0,
-      resource: BindingResource::Buffer(material_buffer.buffer.as_entire_buffer_binding()),
-    }],
-  });
+    let settings_offset = uniforms.len();
+    uniforms.extend_from_slice(bytemuck::bytes_of(&ShaderSettings {
+      seed: settings.seed,
+      frame: texture_iter.0,
+      sampling: settings.sampling as u32,
+      filter: settings.filter as u32,
+      filter_radius: settings.filter_radius,
+      lens_radius,
+      focus_distance: lens.focus_distance,
+      aperture_blades: lens.blades,
+      aperture_rotation: lens.blade_rotation,
+      shutter_open,
+      shutter_close,
+      view_mode: settings.view_mode as u32,
+      previous_inverse_view,
+      previous_view_proj: view.projection * previous_inverse_view.inverse(),
+      temporal: settings.temporal_reprojection() as u32,
+      temporal_alpha: temporal.alpha,
+      position_threshold: temporal.position_threshold,
+      normal_threshold: temporal.normal_threshold,
+      resolution: image_size.as_uvec2().to_array(),
+      aspect_scale,
+      projection: settings.projection as u32,
+      fisheye_fov: settings.fisheye_fov.to_radians(),
+      cube_face: settings.cube_face as u32,
+      render_layers: render_layer_mask(render_layers),
+      moved: motion.0 as u32,
+    }));
+    uniforms.resize(settings_offset + stride, 0);
+
+    commands.entity(entity).insert(CameraBindGroups {
+      size: image_size.as_uvec2(),
+      image: image_bind_group,
+      settings_offset: settings_offset as u32,
+    });
+  }
+  buffers
+    .uniforms
+    .write_bytes(&render_device, &render_queue, &uniforms, stride);
+
+  let (Some(vertices), Some(indices), Some(meshes), Some(materials), Some(uniform_buffer)) = (
+    buffers.vertices.buffer(),
+    buffers.indices.buffer(),
+    buffers.meshes.buffer(),
+    buffers.materials.buffer(),
+    buffers.uniforms.buffer(),
+  ) else {
+    return;
+  };
+  let buffer_ids = [vertices, indices, meshes, materials, uniform_buffer].map(|buffer| buffer.id());
+  if scene_bind_groups.map_or(false, |bind_groups| bind_groups.buffers == buffer_ids) {
+    return;
+  }
 
   let meshes_bind_group = render_device.create_bind_group(&BindGroupDescriptor {
-    label: None,
+    label: Some("raytrace_meshes_bind_group"),
     layout: &pipeline.meshes_bind_group_layout,
     entries: &[
       BindGroupEntry {
         binding: 0,
-        resource: BindingResource::Buffer(vertex_buffer.vertex_buffer.as_entire_buffer_binding()),
+        resource: vertices.as_entire_binding(),
       },
       BindGroupEntry {
         binding: 1,
-        resource: BindingResource::Buffer(vertex_buffer.index_buffer.as_entire_buffer_binding()),
+        resource: indices.as_entire_binding(),
       },
       BindGroupEntry {
         binding: 2,
-        resource: BindingResource::Buffer(mesh_buffer.buffer.as_entire_buffer_binding()),
-      },
-      BindGroupEntry {
-        binding: 3,
-        resource: BindingResource::Buffer(num_of_meshes_buffer.as_entire_buffer_binding()),
+        resource: meshes.as_entire_binding(),
       },
     ],
   });
-
-  let light_dir_buffer = render_device.create_buffer_with_data(&BufferInitDescriptor {
-    label: None,
-    contents: bytemuck::bytes_of(light_dir.as_ref()),
-    usage: BufferUsages::COPY_SRC | BufferUsages::UNIFORM,
-  });
-
-  let light_dir_bind_group = render_device.create_bind_group(&BindGroupDescriptor {
-    label: None,
-    layout: &pipeline.light_dir_bind_group_layout,
+  let materials_bind_group = render_device.create_bind_group(&BindGroupDescriptor {
+    label: Some("raytrace_materials_bind_group"),
+    layout: &pipeline.materials_bind_group_layout,
     entries: &[BindGroupEntry {
       binding: 0,
-      resource: BindingResource::Buffer(light_dir_buffer.as_entire_buffer_binding()),
+      resource: materials.as_entire_binding(),
     }],
   });
-
-  let seed_buffer = render_device.create_buffer_with_data(&BufferInitDescriptor {
-    label: None,
-    contents: bytemuck::bytes_of(&rand::thread_rng().gen::<[f32; 2]>()),
-    usage: BufferUsages::COPY_SRC | BufferUsages::UNIFORM,
+  let frame_bind_group = render_device.create_bind_group(&BindGroupDescriptor {
+    label: Some("raytrace_frame_bind_group"),
+    layout: &pipeline.frame_bind_group_layout,
+    entries: &[BindGroupEntry {
+      binding: 0,
+      resource: BindingResource::Buffer(BufferBinding {
+        buffer: uniform_buffer,
+        offset: 0,
+        size: BufferSize::new(std::mem::size_of::<ShaderFrame>() as u64),
+      }),
+    }],
   });
-
-  let seed_bind_group = render_device.create_bind_group(&BindGroupDescriptor {
-    label: None,
-    layout: &pipeline.seed_bind_group_layout,
+  let settings_bind_group = render_device.create_bind_group(&BindGroupDescriptor {
+    label: Some("raytrace_settings_bind_group"),
+    layout: &pipeline.settings_bind_group_layout,
     entries: &[BindGroupEntry {
       binding: 0,
-      resource: BindingResource::Buffer(seed_buffer.as_entire_buffer_binding()),
+      resource: BindingResource::Buffer(BufferBinding {
+        buffer: uniform_buffer,
+        offset: 0,
+        size: BufferSize::new(std::mem::size_of::<ShaderSettings>() as u64),
+      }),
     }],
   });
-
   commands.insert_resource(RaytracingBindGroups {
-    image: image_bind_group,
+    buffers: buffer_ids,
     meshes: meshes_bind_group,
     materials: materials_bind_group,
-    light_dir: light_dir_bind_group,
-    seed: seed_bind_group,
+    frame: frame_bind_group,
+    settings: settings_bind_group,
   });
 }
 
+pub fn update_previous_transforms(
+  mut commands: Commands,
+  mut tracked: Query<(&GlobalTransform, &mut PreviousGlobalTransform)>,
+  untracked: Query<
+    (Entity, &GlobalTransform),
+    (Or<(With<Handle<Mesh>>, With<Camera>)>, Without<PreviousGlobalTransform>),
+  >,
+) {
+  for (transform, mut previous) in tracked.iter_mut() {
+    previous.set_if_neq(PreviousGlobalTransform(*transform));
+  }
+  for (entity, transform) in untracked.iter() {
+    commands.entity(entity).insert(PreviousGlobalTransform(*transform));
+  }
+}
+
+/// Bevy leaves the render layers of cameras in the main world, ray traced cameras match them against the meshes.
+pub fn extract_camera_render_layers(
+  mut commands: Commands,
+  cameras: Extract<Query<(Entity, &RenderLayers), With<RaytracedCamera>>>,
+) {
+  for (entity, render_layers) in cameras.iter() {
+    commands.get_or_spawn(entity).insert(*render_layers);
+  }
+}
+
+/// Exposure Bevy's raster pipeline applies to directional lights, f/4 at 1/250 s and ISO 100.
+const DIRECTIONAL_LIGHT_EXPOSURE: f32 = 1.0 / (4000.0 * 1.2);
+
+/// Converts the visible lights to the units of Bevy's raster pipeline, so both light a scene alike.
+pub fn extract_lights(
+  mut extracted: ResMut<ExtractedLights>,
+  point_lights: Extract<Query<(&PointLight, &GlobalTransform, &ComputedVisibility)>>,
+  spot_lights: Extract<Query<(&SpotLight, &GlobalTransform, &ComputedVisibility)>>,
+  directional_lights: Extract<Query<(&DirectionalLight, &GlobalTransform, &ComputedVisibility)>>,
+) {
+  let rgb = |color: Color, scale: f32| {
+    let [r, g, b, _] = color.as_linear_rgba_f32();
+    [r * scale, g * scale, b * scale]
+  };
+  let inverse_range_squared = |range: f32| if range > 0.0 { 1.0 / (range * range) } else { 0.0 };
+  let point = point_lights
+    .iter()
+    .filter(|(_, _, visibility)| visibility.is_visible_in_hierarchy())
+    .map(|(light, transform, _)| ShaderLight {
+      position: transform.translation().to_array(),
+      kind: ShaderLight::POINT,
+      inverse_range_squared: inverse_range_squared(light.range),
+      color: rgb(light.color, light.intensity / (4.0 * std::f32::consts::PI)),
+      ..default()
+    });
+  let spot = spot_lights
+    .iter()
+    .filter(|(_, _, visibility)| visibility.is_visible_in_hierarchy())
+    .map(|(light, transform, _)| {
+      let cos_outer = light.outer_angle.cos();
+      let spot_scale = 1.0 / (light.inner_angle.cos() - cos_outer).max(1e-4);
+      ShaderLight {
+        position: transform.translation().to_array(),
+        kind: ShaderLight::SPOT,
+        direction: transform.forward().to_array(),
+        inverse_range_squared: inverse_range_squared(light.range),
+        color: rgb(light.color, light.intensity / (4.0 * std::f32::consts::PI)),
+        spot_scale,
+        spot_offset: -cos_outer * spot_scale,
+        ..default()
+      }
+    });
+  let directional = directional_lights
+    .iter()
+    .filter(|(_, _, visibility)| visibility.is_visible_in_hierarchy())
+    .map(|(light, transform, _)| ShaderLight {
+      kind: ShaderLight::DIRECTIONAL,
+      direction: transform.forward().to_array(),
+      color: rgb(light.color, light.illuminance * DIRECTIONAL_LIGHT_EXPOSURE),
+      ..default()
+    });
+  extracted.0.clear();
+  extracted
+    .0
+    .extend(directional.chain(spot).chain(point).take(MAX_LIGHTS));
+}
+
 pub fn extract_meshes(
   mesh_assets: Extract<Res<Assets<Mesh>>>,
   material_assets: Extract<Res<Assets<StandardMaterial>>>,
+  policy: Extract<Res<RaytraceMarkerPolicy>>,
   meshes_changed: Extract<
     Query<
       Entity,
       (
         Without<NotInScene>,
         Or<(
-          Changed<Transform>,
+          Changed<GlobalTransform>,
+          Changed<PreviousGlobalTransform>,
           Changed<Handle<Mesh>>,
           Changed<Handle<StandardMaterial>>,
+          Changed<RenderLayers>,
+          Changed<RaytraceVisibility>,
+          Changed<SkinnedMesh>,
         )>,
       ),
     >,
   >,
-  meshes: Extract<Query<(&Transform, &Handle<Mesh>, &Handle<StandardMaterial>), Without<NotInScene>>>,
-  mesh_events: Extract<EventReader<AssetEvent<Mesh>>>,
-  material_events: Extract<EventReader<AssetEvent<StandardMaterial>>>,
+  meshes: Extract<
+    Query<
+      (
+        Entity,
+        &GlobalTransform,
+        Option<&PreviousGlobalTransform>,
+        &Handle<Mesh>,
+        &Handle<StandardMaterial>,
+        Option<&ComputedVisibility>,
+        Option<&RenderLayers>,
+        Option<&RaytraceVisibility>,
+        Option<&Raytraced>,
+        Option<&NotRaytraced>,
+        Option<&SkinnedMesh>,
+      ),
+      Without<NotInScene>,
+    >,
+  >,
+  mut removed_meshes: Extract<RemovedComponents<Handle<Mesh>>>,
+  mut removed_materials: Extract<RemovedComponents<Handle<StandardMaterial>>>,
+  mut mesh_events: Extract<EventReader<AssetEvent<Mesh>>>,
+  mut material_events: Extract<EventReader<AssetEvent<StandardMaterial>>>,
   mut vertex_storage: ResMut<VertexStorage>,
   mut mesh_storage: ResMut<MeshStorage>,
   mut material_storage: ResMut<MaterialStorage>,
 ) {
-  if !mesh_events.is_empty() {
-    let meshes_unique = meshes.iter().map(|(_, m, _)| m.clone()).unique().collect::<Vec<_>>();
-    let mut vertex_buffer = vec![];
-    let mut index_buffer = vec![];
-    let mut mesh_map = HashMap::new();
-    for handle in &meshes_unique {
-      let mesh = mesh_assets.get(handle).unwrap();
-      let position = mesh.attribute(Mesh::ATTRIBUTE_POSITION).unwrap();
-      let normal = mesh.attribute(Mesh::ATTRIBUTE_NORMAL).unwrap();
-      let index = mesh.indices().unwrap();
+  // Despawned entities show up as removed components
+  let removed = removed_meshes.iter().count() + removed_materials.iter().count() > 0;
+  let mesh_events = mesh_events.iter().map(asset_event_id).collect::<Vec<_>>();
+  let material_events = material_events.iter().map(asset_event_id).collect::<Vec<_>>();
+  let included = meshes
+    .iter()
+    .filter(|(_, _, _, _, _, visibility, _, _, raytraced, not_raytraced, _)| {
+      let marked = match **policy {
+        RaytraceMarkerPolicy::OptOut => not_raytraced.is_none(),
+        RaytraceMarkerPolicy::OptIn => raytraced.is_some(),
+      };
+      marked && visibility.map_or(true, |visibility| visibility.is_visible_in_hierarchy())
+    })
+    .collect::<Vec<_>>();
+  // Visibility is recomputed every frame, so it's the resulting set of instances that is compared
+  let included_changed = included
+    .iter()
+    .map(|(entity, ..)| *entity)
+    .ne(mesh_storage.meshes.iter().map(|mesh| mesh.entity));
+  let instances_changed = removed || included_changed || policy.is_changed() || !meshes_changed.is_empty();
+  if !instances_changed && mesh_events.is_empty() && material_events.is_empty() {
+    return;
+  }
 
-      let vertex_base = vertex_buffer.len();
-      vertex_buffer.extend(
-        position
-          .as_float3()
-          .unwrap()
+  if instances_changed {
+    mesh_storage.meshes = included
+      .into_iter()
+      .map(
+        |(entity, transform, previous_transform, mesh, material, _, render_layers, visibility, _, _, skin)| {
+          ExtractedMesh {
+            entity,
+            skinned: skin.is_some(),
+            transform: *transform,
+            previous_transform: previous_transform.map_or(*transform, |previous| previous.0),
+            material: material.id(),
+            mesh: mesh.id(),
+            flags: visibility.copied().unwrap_or_default().flags(),
+            render_layers: render_layer_mask(render_layers),
+          }
+        },
+      )
+      .collect();
+  }
+  let used_meshes = mesh_storage.meshes.iter().map(|mesh| mesh.mesh).collect::<HashSet<_>>();
+  let used_materials = mesh_storage
+    .meshes
+    .iter()
+    .map(|mesh| mesh.material)
+    .collect::<HashSet<_>>();
+  let skinned = mesh_storage
+    .meshes
+    .iter()
+    .filter(|mesh| mesh.skinned)
+    .map(|mesh| (mesh.entity, mesh.mesh))
+    .sorted_by_key(|(entity, _)| *entity)
+    .collect();
+  // Only flag the storages when their contents change, that's what triggers the uploads in `prepare_meshes`
+  if update_vertex_storage(
+    vertex_storage.bypass_change_detection(),
+    &mesh_assets,
+    &mesh_events,
+    &used_meshes,
+    skinned,
+  ) {
+    vertex_storage.set_changed();
+  }
+  if update_material_storage(
+    material_storage.bypass_change_detection(),
+    &material_assets,
+    &material_events,
+    &used_materials,
+  ) {
+    material_storage.set_changed();
+  }
+}
+
+fn asset_event_id<T: Asset>(event: &AssetEvent<T>) -> HandleId {
+  match event {
+    AssetEvent::Created { handle } | AssetEvent::Modified { handle } | AssetEvent::Removed { handle } => handle.id(),
+  }
+}
+
+/// Drops the geometry of meshes that had an event or are no longer used and triangulates the used ones that are
+/// missing. Returns whether the packed buffers changed.
+fn update_vertex_storage(
+  storage: &mut VertexStorage,
+  mesh_assets: &Assets<Mesh>,
+  events: &[HandleId],
+  used: &HashSet<HandleId>,
+  skinned: Vec<(Entity, HandleId)>,
+) -> bool {
+  let mut changed = storage.skinned != skinned;
+  storage.skinned = skinned;
+  for id in events {
+    // Created and modified meshes are triangulated again below if they are in use
+    changed |= storage.geometry.remove(id).is_some();
+    storage.skipped.remove(id);
+  }
+  let len = storage.geometry.len();
+  storage.geometry.retain(|id, _| used.contains(id));
+  storage.skipped.retain(|id| used.contains(id));
+  changed |= storage.geometry.len() != len;
+
+  for &id in used {
+    if storage.geometry.contains_key(&id) || storage.skipped.contains(&id) {
+      continue;
+    }
+    // Meshes still loading are picked up by the event of their creation
+    let Some(mesh) = mesh_assets.get(&Handle::weak(id)) else {
+      continue;
+    };
+    match triangulate(mesh) {
+      Ok(triangles) => {
+        let verticies = triangles
+          .positions
           .iter()
-          .zip(normal.as_float3().unwrap().iter())
+          .zip(triangles.normals.iter())
           .map(|(p, n)| ShaderVertex {
-            position: *p,
-            normal: *n,
+            position: p.to_array(),
+            normal: n.to_array(),
             p1: [0; 4],
             p2: [0; 4],
-          }),
-      );
-      let index_first = index_buffer.len();
-      index_buffer.extend(index.iter().map(|x| (vertex_base + x) as u32).collect::<Vec<_>>());
-      let index_len = index_buffer.len() - index_first;
-      mesh_map.insert(handle.id(), (index_first, index_len));
+          })
+          .collect();
+        let skin = triangles
+          .positions
+          .iter()
+          .zip(triangles.normals.iter())
+          .zip(triangles.joints.iter())
+          .map(|((p, n), joints)| ShaderSkinVertex {
+            position: p.to_array(),
+            normal: n.to_array(),
+            joints: joints.indices,
+            weights: joints.weights,
+            p1: [0; 4],
+            p2: [0; 4],
+          })
+          .collect();
+        storage.geometry.insert(
+          id,
+          MeshGeometry {
+            verticies,
+            indicies: triangles.indices,
+            skin,
+          },
+        );
+        changed = true;
+      }
+      Err(err) => {
+        warn!("Skipping mesh {:?}: {}", id, err);
+        storage.skipped.insert(id);
+      }
     }
-    *vertex_storage = VertexStorage {
-      mesh_map,
-      verticies: vertex_buffer,
-      indicies: index_buffer,
-    };
   }
-  if !material_events.is_empty() {
-    let mut material_map = HashMap::new();
-    let mut material_vec = vec![];
-    let materials_unique = meshes.iter().map(|(_, _, m)| m.clone()).unique().collect::<Vec<_>>();
-    for handle in &materials_unique {
-      let material = material_assets.get(handle).unwrap();
-      material_vec.push(ShaderMaterial {
-        color: material.base_color.as_rgba_f32(),
-        emissive: material.emissive.as_rgba_f32(),
-        roughness: material.perceptual_roughness,
-        metallic: material.metallic,
-        specular: material.reflectance,
-        pad: [0; 4],
-      });
-      material_map.insert(handle.id(), material_vec.len() - 1);
+  if changed {
+    pack_geometry(storage);
+  }
+  changed
+}
+
+/// Concatenates the geometry of all meshes, in a stable order so unchanged meshes mostly keep their place in the
+/// buffers. The deformed copies of skinned instances follow, in their bind pose until the skinning pass runs.
+fn pack_geometry(storage: &mut VertexStorage) {
+  storage.mesh_map.clear();
+  storage.skinned_map.clear();
+  storage.verticies.clear();
+  storage.indicies.clear();
+  storage.skin_verticies.clear();
+  for id in storage.geometry.keys().copied().sorted() {
+    let geometry = &storage.geometry[&id];
+    let mut range = append_geometry(&mut storage.verticies, &mut storage.indicies, geometry);
+    if !geometry.skin.is_empty() {
+      range.skin_first = Some(storage.skin_verticies.len());
+      storage.skin_verticies.extend_from_slice(&geometry.skin);
     }
-    *material_storage = MaterialStorage {
-      material_vec,
-      material_map,
+    storage.mesh_map.insert(id, range);
+  }
+  for (entity, id) in &storage.skinned {
+    let (Some(geometry), Some(source)) = (storage.geometry.get(id), storage.mesh_map.get(id)) else {
+      continue;
     };
+    if source.skin_first.is_none() {
+      continue;
+    }
+    let range = append_geometry(&mut storage.verticies, &mut storage.indicies, geometry);
+    storage.skinned_map.insert(
+      *entity,
+      MeshRange {
+        skin_first: source.skin_first,
+        ..range
+      },
+    );
   }
-  if meshes_changed.is_empty() {
-    return;
+}
+
+fn append_geometry(verticies: &mut Vec<ShaderVertex>, indicies: &mut Vec<u32>, geometry: &MeshGeometry) -> MeshRange {
+  let vertex_first = verticies.len();
+  verticies.extend_from_slice(&geometry.verticies);
+  let index_first = indicies.len();
+  indicies.extend(geometry.indicies.iter().map(|index| vertex_first as u32 + index));
+  MeshRange {
+    index_first,
+    index_len: geometry.indicies.len(),
+    vertex_first,
+    vertex_len: geometry.verticies.len(),
+    skin_first: None,
   }
-  let mut extracted_meshes = vec![];
-  for (transform, mesh, mat) in meshes.iter() {
-    extracted_meshes.push(ExtractedMesh {
-      transform: transform.clone(),
-      material: mat.id(),
-      mesh: mesh.id(),
-    });
+}
+
+/// Releases the slots of removed and unused materials and writes the used ones that are new or had an event. Returns
+/// whether the table changed.
+fn update_material_storage(
+  storage: &mut MaterialStorage,
+  material_assets: &Assets<StandardMaterial>,
+  events: &[HandleId],
+  used: &HashSet<HandleId>,
+) -> bool {
+  let mut changed = false;
+  let released = storage
+    .material_map
+    .keys()
+    .filter(|id| !used.contains(id))
+    .copied()
+    .collect::<Vec<_>>();
+  for id in released {
+    if let Some(slot) = storage.material_map.remove(&id) {
+      storage.free_slots.push(slot);
+      changed = true;
+    }
   }
-  *mesh_storage = MeshStorage {
-    meshes: extracted_meshes,
-  };
+
+  for &id in used {
+    let slot = storage.material_map.get(&id).copied();
+    if slot.is_some() && !events.contains(&id) {
+      continue;
+    }
+    let Some(material) = material_assets.get(&Handle::weak(id)) else {
+      // Removed while still referenced, instances using it are left out until it's back
+      if let Some(slot) = storage.material_map.remove(&id) {
+        storage.free_slots.push(slot);
+        changed = true;
+      }
+      continue;
+    };
+    let material = ShaderMaterial {
+      color: material.base_color.as_rgba_f32(),
+      emissive: material.emissive.as_rgba_f32(),
+      roughness: material.perceptual_roughness,
+      metallic: material.metallic,
+      specular: material.reflectance,
+      pad: [0; 4],
+    };
+    let slot = match slot.or_else(|| storage.free_slots.pop()) {
+      Some(slot) => {
+        storage.material_vec[slot] = material;
+        slot
+      }
+      None => {
+        storage.material_vec.push(material);
+        storage.material_vec.len() - 1
+      }
+    };
+    storage.material_map.insert(id, slot);
+    changed = true;
+  }
+  changed
 }
 
 pub fn prepare_meshes(
-  mut commands: Commands,
-  render_device: ResMut<RenderDevice>,
+  render_device: Res<RenderDevice>,
+  render_queue: Res<RenderQueue>,
   vertex_storage: Res<VertexStorage>,
   mesh_storage: Res<MeshStorage>,
   material_storage: Res<MaterialStorage>,
+  mut buffers: ResMut<SceneBuffers>,
 ) {
   if vertex_storage.is_changed() {
-    let vertex_buffer = render_device.create_buffer_with_data(&BufferInitDescriptor {
-      label: None,
-      contents: bytemuck::cast_slice(vertex_storage.verticies.as_slice()),
-      usage: BufferUsages::COPY_SRC | BufferUsages::STORAGE,
-    });
-    let index_buffer = render_device.create_buffer_with_data(&BufferInitDescriptor {
-      label: None,
-      contents: bytemuck::cast_slice(vertex_storage.indicies.as_slice()),
-      usage: BufferUsages::COPY_SRC | BufferUsages::STORAGE,
-    });
-    commands.insert_resource(VertexBuffer {
-      vertex_buffer,
-      index_buffer,
-    });
+    buffers
+      .vertices
+      .write(&render_device, &render_queue, &vertex_storage.verticies);
+    buffers
+      .indices
+      .write(&render_device, &render_queue, &vertex_storage.indicies);
   }
-  if mesh_storage.is_changed() {
+  // Instances whose mesh or material is missing from the storages are left out until they arrive
+  if mesh_storage.is_changed() || vertex_storage.is_changed() || material_storage.is_changed() {
     let meshes = mesh_storage
       .meshes
       .iter()
-      .map(
+      .filter_map(
         |ExtractedMesh {
+           entity,
+           skinned,
            transform,
+           previous_transform,
            material,
            mesh,
+           flags,
+           render_layers,
          }| {
-          ShaderMesh {
-            transform: transform.compute_matrix(),
-            start_index: vertex_storage.mesh_map.get(mesh).unwrap().0 as u32,
-            len_index: vertex_storage.mesh_map.get(mesh).unwrap().1 as u32,
-            material: *material_storage.material_map.get(material).unwrap() as u32,
-            pad: [0; 4],
-          }
+          let deformed = skinned.then(|| vertex_storage.skinned_map.get(entity)).flatten();
+          let (range, transform, previous_transform) = match deformed {
+            // The skinning pass writes world space positions, the previous transform carries them back along the
+            // motion of the root. The deformation of the previous frame isn't kept, joints move without blur.
+            Some(range) => (
+              range,
+              Mat4::IDENTITY,
+              previous_transform.compute_matrix() * transform.compute_matrix().inverse(),
+            ),
+            None => (
+              vertex_storage.mesh_map.get(mesh)?,
+              transform.compute_matrix(),
+              previous_transform.compute_matrix(),
+            ),
+          };
+          Some(ShaderMesh {
+            transform,
+            previous_transform,
+            start_index: range.index_first as u32,
+            len_index: range.index_len as u32,
+            material: *material_storage.material_map.get(material)? as u32,
+            flags: *flags,
+            render_layers: *render_layers,
+            pad: [0; 3],
+          })
         },
       )
       .collect::<Vec<_>>();
-    let buffer = render_device.create_buffer_with_data(&BufferInitDescriptor {
-      label: None,
-      contents: bytemuck::cast_slice(meshes.as_slice()),
-      usage: BufferUsages::COPY_SRC | BufferUsages::STORAGE,
-    });
-    commands.insert_resource(MeshBuffer { buffer });
+    buffers.meshes.write(&render_device, &render_queue, &meshes);
+    buffers.mesh_count = meshes.len() as u32;
   }
   if material_storage.is_changed() {
-    let buffer = render_device.create_buffer_with_data(&BufferInitDescriptor {
-      label: None,
-      contents: bytemuck::cast_slice(material_storage.material_vec.as_slice()),
-      usage: BufferUsages::COPY_SRC | BufferUsages::STORAGE,
-    });
-    commands.insert_resource(MaterialBuffer { buffer });
+    buffers
+      .materials
+      .write(&render_device, &render_queue, &material_storage.material_vec);
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::extract_meshes;
+  use crate::render::raytracer::types::{MaterialStorage, MeshStorage, RaytraceMarkerPolicy, VertexStorage};
+  use bevy::gltf::GltfPlugin;
+  use bevy::prelude::*;
+  use bevy::render::MainWorld;
+  use bevy::transform::systems::{propagate_transforms, sync_simple_transforms};
+  use std::time::Duration;
+
+  /// Loads a scene from `assets/` the way the app does, without a renderer.
+  fn load_scene(path: &str) -> World {
+    let mut app = App::new();
+    app
+      .add_plugins(MinimalPlugins)
+      .add_plugin(AssetPlugin::default())
+      .add_asset::<Mesh>()
+      .add_asset::<StandardMaterial>()
+      .add_asset::<Image>()
+      .add_asset::<Scene>()
+      .add_plugin(GltfPlugin);
+    let handle = app.world.resource::<AssetServer>().load::<Scene, _>(path);
+    for _ in 0..1000 {
+      app.update();
+      if app.world.resource::<Assets<Scene>>().contains(&handle) {
+        break;
+      }
+      std::thread::sleep(Duration::from_millis(5));
+    }
+    let scene = app.world.resource_mut::<Assets<Scene>>().remove(&handle);
+    let mut world = scene.unwrap_or_else(|| panic!("{} did not load", path)).world;
+    let mut schedule = Schedule::new();
+    schedule.add_systems((sync_simple_transforms, propagate_transforms));
+    schedule.run(&mut world);
+    // Visibility is propagated by the renderer, every node of the scene counts as visible
+    let entities = world
+      .query_filtered::<Entity, With<ComputedVisibility>>()
+      .iter(&world)
+      .collect::<Vec<_>>();
+    for entity in entities {
+      world.entity_mut(entity).remove::<ComputedVisibility>();
+    }
+    world.insert_resource(app.world.remove_resource::<Assets<Mesh>>().unwrap());
+    world.insert_resource(app.world.remove_resource::<Assets<StandardMaterial>>().unwrap());
+    world.init_resource::<Events<AssetEvent<Mesh>>>();
+    world.init_resource::<Events<AssetEvent<StandardMaterial>>>();
+    world.init_resource::<RaytraceMarkerPolicy>();
+    world
+  }
+
+  #[test]
+  fn nested_transforms_match_the_hierarchy() {
+    let mut render_world = World::new();
+    render_world.init_resource::<MainWorld>();
+    std::mem::swap(
+      &mut **render_world.resource_mut::<MainWorld>(),
+      &mut load_scene("tests/nested.gltf#Scene0"),
+    );
+    render_world.init_resource::<VertexStorage>();
+    render_world.init_resource::<MeshStorage>();
+    render_world.init_resource::<MaterialStorage>();
+    let mut schedule = Schedule::new();
+    schedule.add_system(extract_meshes);
+    schedule.run(&mut render_world);
+
+    let main_world = render_world.resource::<MainWorld>();
+    let meshes = &render_world.resource::<MeshStorage>().meshes;
+    // root, child, grandchild and leaf
+    assert_eq!(meshes.len(), 4);
+    for mesh in meshes {
+      assert_eq!(Some(&mesh.transform), main_world.get::<GlobalTransform>(mesh.entity));
+      // The local transforms of the node and its ancestors, composed by hand
+      let mut expected = Mat4::IDENTITY;
+      let mut entity = Some(mesh.entity);
+      while let Some(current) = entity {
+        expected = main_world.get::<Transform>(current).unwrap().compute_matrix() * expected;
+        entity = main_world.get::<Parent>(current).map(Parent::get);
+      }
+      assert!(
+        mesh.transform.compute_matrix().abs_diff_eq(expected, 1e-5),
+        "{:?} != {:?}",
+        mesh.transform.compute_matrix(),
+        expected
+      );
+    }
   }
 }
@@ -1,31 +1,542 @@
+use crate::render::growable_buffer::GrowableBuffer;
+use crate::render::skinning::types::ShaderSkinVertex;
 use bevy::asset::HandleId;
+use bevy::ecs::query::QueryItem;
 use bevy::prelude::*;
+use bevy::render::extract_component::ExtractComponent;
 use bevy::render::extract_resource::ExtractResource;
-use bevy::render::render_resource::{BindGroup, Buffer};
-use bevy::utils::hashbrown::HashMap;
+use bevy::render::render_resource::{
+  BindGroup, BufferId, BufferUsages, Extent3d, TextureDimension, TextureFormat, TextureUsages, TextureViewId,
+};
+use bevy::render::texture::CachedTexture;
+use bevy::render::view::RenderLayers;
+use bevy::utils::hashbrown::{HashMap, HashSet};
 use bytemuck::{Pod, Zeroable};
+use serde::{Deserialize, Serialize};
+use std::sync::atomic::AtomicBool;
+use std::sync::Arc;
 
-#[derive(Resource, Clone, Deref, ExtractResource)]
-pub struct RaytracingImage(pub Handle<Image>);
+/// Path traces the camera it is attached to into its own set of images.
+#[derive(Component, Clone, ExtractComponent)]
+pub struct RaytracedCamera {
+  /// Display image, usable as a sprite or egui texture.
+  pub image: Handle<Image>,
+  /// Floating point sum of filter weighted samples in rgb and of their weights in alpha.
+  pub accumulation: Handle<Image>,
+  pub aovs: AovImages,
+}
+
+/// First hit guides for the denoiser, averaged over the same samples as the color.
+#[derive(Clone)]
+pub struct AovImages {
+  pub albedo: Handle<Image>,
+  /// World space shading normal.
+  pub normal: Handle<Image>,
+  /// World space position in rgb and linear view depth in alpha.
+  pub depth: Handle<Image>,
+}
+
+impl RaytracedCamera {
+  pub fn new(images: &mut Assets<Image>, size: UVec2) -> Self {
+    let mut image = Image::new_fill(
+      extent(size),
+      TextureDimension::D2,
+      &[0, 0, 0, 255],
+      TextureFormat::Rgba8Unorm,
+    );
+    image.texture_descriptor.usage =
+      TextureUsages::COPY_DST | TextureUsages::STORAGE_BINDING | TextureUsages::TEXTURE_BINDING;
+    Self {
+      image: images.add(image),
+      accumulation: images.add(float_storage_image(size)),
+      aovs: AovImages {
+        albedo: images.add(float_storage_image(size)),
+        normal: images.add(float_storage_image(size)),
+        depth: images.add(float_storage_image(size)),
+      },
+    }
+  }
+
+  pub fn size(&self, images: &Assets<Image>) -> Option<UVec2> {
+    images.get(&self.image).map(|image| image.size().as_uvec2())
+  }
+
+  /// Reallocates every image of the camera, their content is lost.
+  pub fn resize(&self, images: &mut Assets<Image>, size: UVec2) {
+    for handle in [
+      &self.image,
+      &self.accumulation,
+      &self.aovs.albedo,
+      &self.aovs.normal,
+      &self.aovs.depth,
+    ] {
+      if let Some(image) = images.get_mut(handle) {
+        image.resize(extent(size));
+      }
+    }
+  }
+}
+
+fn extent(size: UVec2) -> Extent3d {
+  Extent3d {
+    width: size.x,
+    height: size.y,
+    depth_or_array_layers: 1,
+  }
+}
+
+fn float_storage_image(size: UVec2) -> Image {
+  let mut image = Image::new_fill(extent(size), TextureDimension::D2, &[0; 16], TextureFormat::Rgba32Float);
+  image.texture_descriptor.usage =
+    TextureUsages::COPY_DST | TextureUsages::COPY_SRC | TextureUsages::STORAGE_BINDING | TextureUsages::TEXTURE_BINDING;
+  image
+}
+
+/// Everything needed to path trace a camera, added next to a `Camera3dBundle`.
+#[derive(Bundle)]
+pub struct RaytracedCameraBundle {
+  pub raytraced_camera: RaytracedCamera,
+  pub settings: RaytraceSettings,
+  pub samples: TextureIter,
+  pub motion: ViewMotion,
+}
+
+impl RaytracedCameraBundle {
+  pub fn new(images: &mut Assets<Image>, size: UVec2) -> Self {
+    Self {
+      raytraced_camera: RaytracedCamera::new(images, size),
+      settings: RaytraceSettings::default(),
+      samples: TextureIter(0),
+      motion: ViewMotion(false),
+    }
+  }
+}
 
 #[derive(Resource)]
 pub struct RTCameraEntity(pub Entity);
 
-#[derive(Resource, Clone, Deref, ExtractResource)]
+/// Ray traced camera shown in the window.
+#[derive(Resource, Clone, Deref)]
 pub struct PBRCameraEntity(pub Entity);
 
-#[derive(Resource, Clone, ExtractResource)]
+/// Sprite showing the ray traced image in the window.
+#[derive(Component)]
+pub struct RaytraceDisplay;
+
+/// Set by the render world once the ray tracing pipeline compiled and traced its first frame, sample counts stay at
+/// zero until then.
+#[derive(Resource, Clone, Default, Deref)]
+pub struct PipelineReady(pub Arc<AtomicBool>);
+
+/// Number of frames accumulated by a ray traced camera since its last reset.
+#[derive(Component, Clone, Copy, ExtractComponent)]
 pub struct TextureIter(pub u32);
 
+/// Whether the camera or the scene it sees moved this frame. Temporal accumulation only caps the weight of the history
+/// then, a still view keeps converging.
+#[derive(Component, Clone, Copy, ExtractComponent)]
+pub struct ViewMotion(pub bool);
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default, Serialize, Deserialize)]
+pub enum SamplingMode {
+  /// Independent samples from the per-pixel PCG generator.
+  Random,
+  /// Owen-scrambled Sobol points, shuffled and decorrelated per pixel and per sample dimension.
+  #[default]
+  Sobol,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default, Serialize, Deserialize)]
+pub enum ReconstructionFilter {
+  Box,
+  Tent,
+  #[default]
+  Gaussian,
+  BlackmanHarris,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default, Serialize, Deserialize)]
+pub enum RaytraceViewMode {
+  #[default]
+  PathTraced,
+  Albedo,
+  ShadingNormal,
+  GeometricNormal,
+  WorldPosition,
+  Depth,
+  MaterialId,
+  MeshId,
+  BounceCount,
+  TraversalSteps,
+}
+
+/// How primary rays leave a ray traced camera.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default, Serialize, Deserialize)]
+pub enum RaytraceProjection {
+  /// Projection of the Bevy camera, perspective or orthographic.
+  #[default]
+  Camera,
+  /// 360° latitude-longitude panorama around the camera.
+  Equirectangular,
+  /// Equidistant fisheye, the field of view spans the shorter image axis.
+  Fisheye,
+  /// One face of a cube map centered on the camera.
+  CubeFace,
+}
+
+/// Faces in the order and orientation of GPU cube textures, sampled with directions in the camera's local space.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default, Serialize, Deserialize)]
+pub enum CubeFace {
+  #[default]
+  PositiveX,
+  NegativeX,
+  PositiveY,
+  NegativeY,
+  PositiveZ,
+  NegativeZ,
+}
+
+impl SamplingMode {
+  pub const ALL: [Self; 2] = [Self::Random, Self::Sobol];
+}
+
+impl ReconstructionFilter {
+  pub const ALL: [Self; 4] = [Self::Box, Self::Tent, Self::Gaussian, Self::BlackmanHarris];
+}
+
+impl RaytraceProjection {
+  pub const ALL: [Self; 4] = [Self::Camera, Self::Equirectangular, Self::Fisheye, Self::CubeFace];
+}
+
+impl CubeFace {
+  pub const ALL: [Self; 6] = [
+    Self::PositiveX,
+    Self::NegativeX,
+    Self::PositiveY,
+    Self::NegativeY,
+    Self::PositiveZ,
+    Self::NegativeZ,
+  ];
+}
+
+impl RaytraceViewMode {
+  pub const ALL: [Self; 10] = [
+    Self::PathTraced,
+    Self::Albedo,
+    Self::ShadingNormal,
+    Self::GeometricNormal,
+    Self::WorldPosition,
+    Self::Depth,
+    Self::MaterialId,
+    Self::MeshId,
+    Self::BounceCount,
+    Self::TraversalSteps,
+  ];
+}
+
+/// Edge-aware à-trous wavelet filter applied to the accumulated image before display.
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct DenoiserSettings {
+  pub enabled: bool,
+  /// Number of wavelet passes, each doubling the filter footprint.
+  pub iterations: u32,
+  pub sigma_color: f32,
+  /// Exponent applied to the cosine between normals.
+  pub sigma_normal: f32,
+  /// Depth difference tolerance relative to the filter step.
+  pub sigma_depth: f32,
+}
+
+impl Default for DenoiserSettings {
+  fn default() -> Self {
+    Self {
+      enabled: false,
+      iterations: 4,
+      sigma_color: 1.0,
+      sigma_normal: 64.0,
+      sigma_depth: 0.1,
+    }
+  }
+}
+
+/// Reprojects the accumulated image into the moving camera instead of discarding it on every change.
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct TemporalSettings {
+  pub enabled: bool,
+  /// Lower bound of the blend factor of new samples while the view moves, the weight of the exponential moving
+  /// average.
+  pub alpha: f32,
+  /// Largest distance between the reprojected and the current first hit, relative to the view depth.
+  pub position_threshold: f32,
+  /// Smallest cosine between the reprojected and the current first hit normal.
+  pub normal_threshold: f32,
+}
+
+impl Default for TemporalSettings {
+  fn default() -> Self {
+    Self {
+      enabled: false,
+      alpha: 0.1,
+      position_threshold: 0.05,
+      normal_threshold: 0.9,
+    }
+  }
+}
+
+#[derive(Component, Clone, PartialEq, Debug, ExtractComponent, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RaytraceSettings {
+  /// Base seed of the per-pixel random number generator, equal seeds give bit-for-bit equal renders.
+  pub seed: u32,
+  /// Samples per pixel after which the image is left as is, temporal accumulation never stops.
+  pub max_samples: u32,
+  pub sampling: SamplingMode,
+  pub filter: ReconstructionFilter,
+  /// Filter support radius in pixels.
+  pub filter_radius: f32,
+  pub motion_blur: bool,
+  /// Shutter interval as fractions of the last frame, 0 being the previous and 1 the current frame.
+  pub shutter_open: f32,
+  pub shutter_close: f32,
+  pub view_mode: RaytraceViewMode,
+  pub projection: RaytraceProjection,
+  /// Field of view of [`RaytraceProjection::Fisheye`] in degrees.
+  pub fisheye_fov: f32,
+  pub cube_face: CubeFace,
+  pub denoiser: DenoiserSettings,
+  pub temporal: TemporalSettings,
+  /// Fixed resolution of the ray traced image, follows the primary window when unset.
+  pub resolution: Option<UVec2>,
+  /// Scale applied to the resolution, below 1 for quick previews.
+  pub render_scale: f32,
+}
+
+impl Default for RaytraceSettings {
+  fn default() -> Self {
+    Self {
+      seed: 0,
+      max_samples: 100,
+      sampling: SamplingMode::default(),
+      filter: ReconstructionFilter::default(),
+      filter_radius: 1.5,
+      motion_blur: false,
+      shutter_open: 0.5,
+      shutter_close: 1.0,
+      view_mode: RaytraceViewMode::default(),
+      projection: RaytraceProjection::default(),
+      fisheye_fov: 180.0,
+      cube_face: CubeFace::default(),
+      denoiser: DenoiserSettings::default(),
+      temporal: TemporalSettings::default(),
+      resolution: None,
+      render_scale: 1.0,
+    }
+  }
+}
+
+impl RaytraceSettings {
+  /// Reprojection relies on the camera projection, panoramic projections always restart the accumulation.
+  pub fn temporal_reprojection(&self) -> bool {
+    self.temporal.enabled && self.projection == RaytraceProjection::Camera
+  }
+
+  /// Whether a camera that accumulated `samples` so far gets another one this frame.
+  pub fn needs_samples(&self, samples: u32) -> bool {
+    self.temporal_reprojection() || samples < self.max_samples
+  }
+}
+
+/// World transform of the entity at the end of the previous frame, used to interpolate motion within the shutter.
+#[derive(Component, Clone, Copy, PartialEq, Deref)]
+pub struct PreviousGlobalTransform(pub GlobalTransform);
+
+impl ExtractComponent for PreviousGlobalTransform {
+  type Query = &'static Self;
+  type Filter = With<Camera>;
+  type Out = Self;
+
+  fn extract_component(item: QueryItem<'_, Self::Query>) -> Option<Self> {
+    Some(*item)
+  }
+}
+
+#[derive(Clone, Copy, PartialEq, Debug, Serialize, Deserialize)]
+pub enum Aperture {
+  /// Relative aperture, the lens radius follows from the focal length implied by the vertical field of view.
+  FStop(f32),
+  /// Lens radius in world units.
+  Radius(f32),
+}
+
+/// Which meshes are ray traced, those with [`NotInScene`](bevy_editor_pls::prelude::NotInScene) never are.
+#[derive(Resource, Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub enum RaytraceMarkerPolicy {
+  /// Every mesh unless it is marked [`NotRaytraced`].
+  #[default]
+  OptOut,
+  /// Only meshes marked [`Raytraced`].
+  OptIn,
+}
+
+impl RaytraceMarkerPolicy {
+  pub const ALL: [Self; 2] = [Self::OptOut, Self::OptIn];
+}
+
+/// Light arriving along rays that leave the scene.
+#[derive(Resource, Clone, Copy, PartialEq, Debug, Default, ExtractResource, Serialize, Deserialize)]
+pub enum RaytraceEnvironment {
+  /// Green gradient behind the scene and a dim grey ambient light for bounces.
+  #[default]
+  Debug,
+  /// The same radiance from every direction.
+  Uniform { color: Color, intensity: f32 },
+}
+
+impl RaytraceEnvironment {
+  /// [`ShaderFrame::environment`].
+  pub fn shader_environment(&self) -> [f32; 4] {
+    match self {
+      Self::Debug => [0.0; 4],
+      Self::Uniform { color, intensity } => {
+        let [r, g, b, _] = color.as_linear_rgba_f32();
+        [r * intensity, g * intensity, b * intensity, 1.0]
+      }
+    }
+  }
+}
+
+/// Marks a mesh to be ray traced under [`RaytraceMarkerPolicy::OptIn`].
+#[derive(Component, Clone, Copy, Default)]
+pub struct Raytraced;
+
+/// Keeps a mesh out of the ray traced scene under [`RaytraceMarkerPolicy::OptOut`].
+#[derive(Component, Clone, Copy, Default)]
+pub struct NotRaytraced;
+
+/// Per mesh control over the kinds of rays that can hit it, everything is on without the component.
+#[derive(Component, Clone, Copy, PartialEq, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RaytraceVisibility {
+  /// Seen directly by the camera.
+  pub camera_rays: bool,
+  /// Seen in reflections and lights the scene through bounces.
+  pub reflections: bool,
+  /// Blocks shadow rays towards lights.
+  pub cast_shadows: bool,
+}
+
+impl Default for RaytraceVisibility {
+  fn default() -> Self {
+    Self {
+      camera_rays: true,
+      reflections: true,
+      cast_shadows: true,
+    }
+  }
+}
+
+impl RaytraceVisibility {
+  pub const CAMERA_RAYS: u32 = 1;
+  pub const REFLECTIONS: u32 = 2;
+  pub const CAST_SHADOWS: u32 = 4;
+
+  /// Bit flags of [`ShaderMesh::flags`].
+  pub fn flags(&self) -> u32 {
+    (self.camera_rays as u32 * Self::CAMERA_RAYS)
+      | (self.reflections as u32 * Self::REFLECTIONS)
+      | (self.cast_shadows as u32 * Self::CAST_SHADOWS)
+  }
+}
+
+/// Bit mask of the layers of [`RenderLayers`], the default layer 0 without the component.
+pub fn render_layer_mask(layers: Option<&RenderLayers>) -> u32 {
+  layers
+    .copied()
+    .unwrap_or_default()
+    .iter()
+    .fold(0, |mask, layer| mask | 1 << layer)
+}
+
+/// Replaces the pinhole ray generation of the camera it is attached to with a thin lens model.
+#[derive(Component, Clone, Copy, PartialEq, Debug, ExtractComponent, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ThinLensCamera {
+  pub aperture: Aperture,
+  /// Distance from the lens to the plane in focus, measured along the view direction.
+  pub focus_distance: f32,
+  /// Number of aperture blades, anything below 3 gives a circular aperture.
+  pub blades: u32,
+  /// Rotation of the aperture polygon in radians.
+  pub blade_rotation: f32,
+  /// Height of the sensor used to derive the focal length for [`Aperture::FStop`].
+  pub sensor_height: f32,
+}
+
+impl ThinLensCamera {
+  pub fn lens_radius(&self, projection: &Mat4) -> f32 {
+    match self.aperture {
+      Aperture::Radius(radius) => radius,
+      Aperture::FStop(f_stop) => {
+        let focal_length = self.sensor_height * projection.y_axis.y * 0.5;
+        focal_length / (2.0 * f_stop)
+      }
+    }
+  }
+}
+
+impl Default for ThinLensCamera {
+  fn default() -> Self {
+    Self {
+      aperture: Aperture::FStop(2.8),
+      focus_distance: 4.0,
+      blades: 0,
+      blade_rotation: 0.0,
+      sensor_height: 0.024,
+    }
+  }
+}
+
+/// Copies of the previous frame's accumulation and first hit AOVs, read by the temporal reprojection.
+#[derive(Component, Clone)]
+pub struct HistoryTextures {
+  pub size: UVec2,
+  pub color: CachedTexture,
+  pub normal: CachedTexture,
+  pub depth: CachedTexture,
+}
+
+/// History textures of every ray traced camera by entity, kept until the camera is resized or gone.
+#[derive(Resource, Default)]
+pub struct HistoryTextureCache(pub HashMap<Entity, HistoryTextures>);
+
+/// Scene bind groups shared by every ray traced camera.
 #[derive(Resource)]
 pub struct RaytracingBindGroups {
-  pub image: BindGroup,
+  /// Buffers bound by the groups, they are rebuilt once one of them is reallocated.
+  pub buffers: [BufferId; 5],
   pub meshes: BindGroup,
   pub materials: BindGroup,
-  pub light_dir: BindGroup,
-  pub seed: BindGroup,
+  pub frame: BindGroup,
+  /// Settings of every camera, bound at [`CameraBindGroups::settings_offset`].
+  pub settings: BindGroup,
 }
 
+#[derive(Component)]
+pub struct CameraBindGroups {
+  /// Resolution of the ray traced image.
+  pub size: UVec2,
+  pub image: BindGroup,
+  /// Dynamic offset of the camera's [`ShaderSettings`] in the frame uniform buffer.
+  pub settings_offset: u32,
+}
+
+/// Image bind group of every ray traced camera by entity, along with the views it binds. It's rebuilt once one of
+/// them changes.
+#[derive(Resource, Default)]
+pub struct ImageBindGroupCache(pub HashMap<Entity, (Vec<TextureViewId>, BindGroup)>);
+
 #[derive(Component)]
 pub struct ColorComponent {
   pub color: Color,
@@ -45,21 +556,41 @@ pub struct ShaderVertex {
   pub p2: [u8; 4],
 }
 
-#[derive(Resource)]
+/// Geometry of the meshes in use. Meshes are triangulated once and kept in `geometry`, the packed `verticies` and
+/// `indicies` are rebuilt from it whenever a mesh is added, modified or dropped.
+#[derive(Resource, Default)]
 pub struct VertexStorage {
-  pub mesh_map: HashMap<HandleId, (usize, usize)>,
+  pub geometry: HashMap<HandleId, MeshGeometry>,
+  /// Meshes that failed to triangulate, not retried until they are modified.
+  pub skipped: HashSet<HandleId>,
+  /// Skinned instances and their mesh by entity, each gets a copy of the mesh deformed by the skinning pass.
+  pub skinned: Vec<(Entity, HandleId)>,
+  pub mesh_map: HashMap<HandleId, MeshRange>,
+  /// Deformed copies of the skinned instances, packed after the meshes.
+  pub skinned_map: HashMap<Entity, MeshRange>,
   pub verticies: Vec<ShaderVertex>,
   pub indicies: Vec<u32>,
+  /// Bind pose, joints and weights of the skinned meshes.
+  pub skin_verticies: Vec<ShaderSkinVertex>,
 }
 
-impl Default for VertexStorage {
-  fn default() -> Self {
-    Self {
-      mesh_map: Default::default(),
-      verticies: vec![],
-      indicies: vec![],
-    }
-  }
+/// Vertices and mesh-local indices of one triangulated mesh.
+pub struct MeshGeometry {
+  pub verticies: Vec<ShaderVertex>,
+  pub indicies: Vec<u32>,
+  /// One per vertex for skinned meshes, empty otherwise.
+  pub skin: Vec<ShaderSkinVertex>,
+}
+
+/// Location of a mesh in the packed buffers of [`VertexStorage`].
+#[derive(Clone, Copy)]
+pub struct MeshRange {
+  pub index_first: usize,
+  pub index_len: usize,
+  pub vertex_first: usize,
+  pub vertex_len: usize,
+  /// First entry in [`VertexStorage::skin_verticies`] of skinned meshes.
+  pub skin_first: Option<usize>,
 }
 
 #[repr(C)]
@@ -77,22 +608,65 @@ pub struct ShaderMaterial {
 pub struct MaterialStorage {
   pub material_vec: Vec<ShaderMaterial>,
   pub material_map: HashMap<HandleId, usize>,
+  /// Slots of `material_vec` released by dropped materials, reused so other materials keep their index.
+  pub free_slots: Vec<usize>,
 }
 
 pub struct ExtractedMesh {
-  pub transform: Transform,
+  pub entity: Entity,
+  /// Traced through the deformed copy in [`VertexStorage::skinned_map`] when there is one.
+  pub skinned: bool,
+  pub transform: GlobalTransform,
+  pub previous_transform: GlobalTransform,
   pub material: HandleId,
   pub mesh: HandleId,
+  pub flags: u32,
+  pub render_layers: u32,
 }
 
 #[repr(C)]
 #[derive(Zeroable, Pod, Copy, Clone)]
 pub struct ShaderMesh {
   pub transform: Mat4,
+  pub previous_transform: Mat4,
   pub start_index: u32,
   pub len_index: u32,
   pub material: u32,
-  pub pad: [u8; 4],
+  /// [`RaytraceVisibility::flags`].
+  pub flags: u32,
+  /// Matched against [`ShaderSettings::render_layers`] of the camera.
+  pub render_layers: u32,
+  pub pad: [u32; 3],
+}
+
+#[repr(C)]
+#[derive(Zeroable, Pod, Copy, Clone)]
+pub struct ShaderSettings {
+  pub seed: u32,
+  pub frame: u32,
+  pub sampling: u32,
+  pub filter: u32,
+  pub filter_radius: f32,
+  pub lens_radius: f32,
+  pub focus_distance: f32,
+  pub aperture_blades: u32,
+  pub aperture_rotation: f32,
+  pub shutter_open: f32,
+  pub shutter_close: f32,
+  pub view_mode: u32,
+  pub previous_inverse_view: Mat4,
+  pub previous_view_proj: Mat4,
+  pub temporal: u32,
+  pub temporal_alpha: f32,
+  pub position_threshold: f32,
+  pub normal_threshold: f32,
+  pub resolution: [u32; 2],
+  pub aspect_scale: f32,
+  pub projection: u32,
+  pub fisheye_fov: f32,
+  pub cube_face: u32,
+  pub render_layers: u32,
+  pub moved: u32,
 }
 
 #[derive(Resource, Default)]
@@ -100,18 +674,73 @@ pub struct MeshStorage {
   pub meshes: Vec<ExtractedMesh>,
 }
 
-#[derive(Resource)]
-pub struct VertexBuffer {
-  pub vertex_buffer: Buffer,
-  pub index_buffer: Buffer,
+/// Lights beyond this many are left out of the ray traced scene.
+pub const MAX_LIGHTS: usize = 16;
+
+/// Point, spot or directional light, sampled with a shadow ray at every diffuse hit.
+#[repr(C)]
+#[derive(Zeroable, Pod, Copy, Clone, Default)]
+pub struct ShaderLight {
+  pub position: [f32; 3],
+  pub kind: u32,
+  /// Direction the light shines in, for spot and directional lights.
+  pub direction: [f32; 3],
+  /// Zero for an unlimited range.
+  pub inverse_range_squared: f32,
+  /// Radiant intensity of point and spot lights, irradiance of directional lights.
+  pub color: [f32; 3],
+  /// Spot cone falloff, `saturate(cos * scale + offset)` squared as in Bevy's raster lights.
+  pub spot_scale: f32,
+  pub spot_offset: f32,
+  pub pad: [f32; 3],
 }
 
-#[derive(Resource)]
-pub struct MeshBuffer {
-  pub buffer: Buffer,
+impl ShaderLight {
+  pub const POINT: u32 = 0;
+  pub const SPOT: u32 = 1;
+  pub const DIRECTIONAL: u32 = 2;
+}
+
+/// Lights of the main world in the units of [`ShaderLight`].
+#[derive(Resource, Default)]
+pub struct ExtractedLights(pub Vec<ShaderLight>);
+
+/// Scene wide uniform at the start of the frame uniform buffer.
+#[repr(C)]
+#[derive(Zeroable, Pod, Copy, Clone)]
+pub struct ShaderFrame {
+  pub light_dir: [f32; 3],
+  pub mesh_count: u32,
+  /// [`RaytraceEnvironment::shader_environment`], radiance in rgb and 1 in w for a uniform environment.
+  pub environment: [f32; 4],
+  pub light_count: u32,
+  pub pad: [u32; 3],
+  pub lights: [ShaderLight; MAX_LIGHTS],
 }
 
+/// GPU buffers of the ray traced scene, kept across frames.
 #[derive(Resource)]
-pub struct MaterialBuffer {
-  pub buffer: Buffer,
+pub struct SceneBuffers {
+  pub vertices: GrowableBuffer,
+  pub indices: GrowableBuffer,
+  pub meshes: GrowableBuffer,
+  pub materials: GrowableBuffer,
+  /// [`ShaderFrame`] followed by the [`ShaderSettings`] of every camera, each at an offset aligned for dynamic
+  /// uniform bindings.
+  pub uniforms: GrowableBuffer,
+  /// Instances in `meshes`, those waiting for their mesh or material are left out.
+  pub mesh_count: u32,
+}
+
+impl Default for SceneBuffers {
+  fn default() -> Self {
+    Self {
+      vertices: GrowableBuffer::new("vertex_buffer", BufferUsages::STORAGE),
+      indices: GrowableBuffer::new("index_buffer", BufferUsages::STORAGE),
+      meshes: GrowableBuffer::new("mesh_buffer", BufferUsages::STORAGE),
+      materials: GrowableBuffer::new("material_buffer", BufferUsages::STORAGE),
+      uniforms: GrowableBuffer::new("frame_uniform_buffer", BufferUsages::UNIFORM),
+      mesh_count: 0,
+    }
+  }
 }
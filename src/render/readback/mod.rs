@@ -0,0 +1,32 @@
+use crate::render::readback::node::ReadbackNode;
+use crate::render::readback::systems::{map_readback_buffers, prepare_readback_buffers};
+use crate::render::readback::types::{ImageReadbacks, ReadbackBuffers};
+use bevy::prelude::*;
+use bevy::render::extract_resource::ExtractResourcePlugin;
+use bevy::render::render_graph::RenderGraph;
+use bevy::render::{RenderApp, RenderSet};
+
+pub mod node;
+pub mod systems;
+pub mod types;
+
+/// Copies the images listed in [`ImageReadbacks`] back to the CPU once ray tracing and denoising are done, expects
+/// the denoise node to be in the render graph already.
+pub struct ReadbackPlugin;
+
+impl Plugin for ReadbackPlugin {
+  fn build(&self, app: &mut App) {
+    app.init_resource::<ImageReadbacks>();
+    app.add_plugin(ExtractResourcePlugin::<ImageReadbacks>::default());
+    let render_app = app.sub_app_mut(RenderApp);
+    render_app
+      .init_resource::<ReadbackBuffers>()
+      .add_system(prepare_readback_buffers.in_set(RenderSet::Prepare))
+      .add_system(map_readback_buffers.in_set(RenderSet::Cleanup));
+
+    let mut render_graph = render_app.world.resource_mut::<RenderGraph>();
+    render_graph.add_node("readback", ReadbackNode);
+    render_graph.add_node_edge("denoise", "readback");
+    render_graph.add_node_edge("readback", bevy::render::main_graph::node::CAMERA_DRIVER);
+  }
+}
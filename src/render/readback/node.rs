@@ -0,0 +1,47 @@
+use crate::render::readback::types::ReadbackBuffers;
+use bevy::prelude::*;
+use bevy::render::render_asset::RenderAssets;
+use bevy::render::render_graph;
+use bevy::render::render_resource::{Extent3d, ImageCopyBuffer, ImageDataLayout};
+use bevy::render::renderer::RenderContext;
+use std::num::NonZeroU32;
+
+pub struct ReadbackNode;
+
+impl render_graph::Node for ReadbackNode {
+  fn run(
+    &self,
+    _graph: &mut render_graph::RenderGraphContext,
+    render_context: &mut RenderContext,
+    world: &World,
+  ) -> Result<(), render_graph::NodeRunError> {
+    let gpu_images = world.resource::<RenderAssets<Image>>();
+    for readback in world
+      .resource::<ReadbackBuffers>()
+      .0
+      .iter()
+      .filter(|readback| !readback.copied)
+    {
+      let Some(image) = gpu_images.get(&readback.readback.image) else {
+        continue;
+      };
+      render_context.command_encoder().copy_texture_to_buffer(
+        image.texture.as_image_copy(),
+        ImageCopyBuffer {
+          buffer: &readback.buffer,
+          layout: ImageDataLayout {
+            offset: 0,
+            bytes_per_row: NonZeroU32::new(readback.padded_bytes_per_row),
+            rows_per_image: None,
+          },
+        },
+        Extent3d {
+          width: readback.size.x,
+          height: readback.size.y,
+          depth_or_array_layers: 1,
+        },
+      );
+    }
+    Ok(())
+  }
+}
@@ -0,0 +1,84 @@
+use crate::render::readback::types::{ImageReadbacks, ReadbackBuffer, ReadbackBuffers};
+use bevy::prelude::*;
+use bevy::render::render_asset::RenderAssets;
+use bevy::render::render_resource::{BufferDescriptor, BufferUsages, MapMode};
+use bevy::render::renderer::RenderDevice;
+use wgpu::Maintain;
+
+/// Creates a buffer for every new request and drops the ones of requests that were served or withdrawn.
+pub fn prepare_readback_buffers(
+  render_device: Res<RenderDevice>,
+  gpu_images: Res<RenderAssets<Image>>,
+  readbacks: Res<ImageReadbacks>,
+  mut buffers: ResMut<ReadbackBuffers>,
+) {
+  buffers
+    .0
+    .retain(|buffer| !buffer.readback.is_done() && readbacks.0.iter().any(|readback| readback.is(&buffer.readback)));
+  let new_buffers = readbacks
+    .0
+    .iter()
+    .filter(|readback| !readback.is_done() && !buffers.0.iter().any(|buffer| buffer.readback.is(readback)))
+    .filter_map(|readback| {
+      let image = gpu_images.get(&readback.image)?;
+      let size = image.size.as_uvec2();
+      let bytes_per_row = size.x * image.texture_format.describe().block_size as u32;
+      let padded_bytes_per_row = RenderDevice::align_copy_bytes_per_row(bytes_per_row as usize) as u32;
+      let buffer = render_device.create_buffer(&BufferDescriptor {
+        label: Some("readback_buffer"),
+        size: (padded_bytes_per_row * size.y) as u64,
+        usage: BufferUsages::MAP_READ | BufferUsages::COPY_DST,
+        mapped_at_creation: false,
+      });
+      Some(ReadbackBuffer {
+        readback: readback.clone(),
+        buffer,
+        size,
+        bytes_per_row,
+        padded_bytes_per_row,
+        copied: false,
+      })
+    })
+    .collect::<Vec<_>>();
+  buffers.0.extend(new_buffers);
+}
+
+/// Starts mapping the copies submitted this frame without waiting for them, each request gets its unpadded rows
+/// from the map callback once the GPU is done.
+pub fn map_readback_buffers(
+  render_device: Res<RenderDevice>,
+  gpu_images: Res<RenderAssets<Image>>,
+  mut buffers: ResMut<ReadbackBuffers>,
+) {
+  if buffers.0.is_empty() {
+    return;
+  }
+  for readback in buffers.0.iter_mut().filter(|readback| !readback.copied) {
+    // The node skips images that went away since the buffer was made
+    if gpu_images.get(&readback.readback.image).is_none() {
+      continue;
+    }
+    readback.copied = true;
+    let request = readback.readback.clone();
+    let buffer = readback.buffer.clone();
+    let (bytes_per_row, padded_bytes_per_row) = (readback.bytes_per_row, readback.padded_bytes_per_row);
+    render_device.map_buffer(&readback.buffer.slice(..), MapMode::Read, move |result| {
+      if let Err(err) = result {
+        error!("Failed to read back image: {}", err);
+        return;
+      }
+      let data = {
+        let mapped = buffer.slice(..).get_mapped_range();
+        mapped
+          .chunks_exact(padded_bytes_per_row as usize)
+          .flat_map(|row| &row[..bytes_per_row as usize])
+          .copied()
+          .collect::<Vec<_>>()
+      };
+      buffer.unmap();
+      request.fill(data);
+    });
+  }
+  // Runs the callbacks of the mappings that finished, the others are checked again next frame
+  render_device.poll(Maintain::Poll);
+}
@@ -0,0 +1,54 @@
+use bevy::prelude::*;
+use bevy::render::extract_resource::ExtractResource;
+use bevy::render::render_resource::Buffer;
+use std::sync::{Arc, Mutex};
+
+/// Images to copy back to the CPU, served a few frames later once the GPU is done with the copy. Remove a request
+/// once its data has been taken, it is copied again otherwise.
+#[derive(Resource, Clone, Default, ExtractResource)]
+pub struct ImageReadbacks(pub Vec<ImageReadback>);
+
+/// Pending copy of an image, the texels arrive tightly packed row by row.
+#[derive(Clone)]
+pub struct ImageReadback {
+  pub image: Handle<Image>,
+  data: Arc<Mutex<Option<Vec<u8>>>>,
+}
+
+impl ImageReadback {
+  pub fn new(image: Handle<Image>) -> Self {
+    Self { image, data: default() }
+  }
+
+  pub fn is_done(&self) -> bool {
+    self.data.lock().unwrap().is_some()
+  }
+
+  pub fn take(&self) -> Option<Vec<u8>> {
+    self.data.lock().unwrap().take()
+  }
+
+  /// Whether both refer to the same request.
+  pub fn is(&self, other: &Self) -> bool {
+    Arc::ptr_eq(&self.data, &other.data)
+  }
+
+  pub(crate) fn fill(&self, data: Vec<u8>) {
+    *self.data.lock().unwrap() = Some(data);
+  }
+}
+
+pub struct ReadbackBuffer {
+  pub readback: ImageReadback,
+  pub buffer: Buffer,
+  pub size: UVec2,
+  pub bytes_per_row: u32,
+  /// Row stride of the buffer, a multiple of the copy alignment.
+  pub padded_bytes_per_row: u32,
+  /// Whether the copy was submitted and the buffer is being mapped.
+  pub copied: bool,
+}
+
+/// Buffers of the requests being served, kept across frames until their mapping finished.
+#[derive(Resource, Default)]
+pub struct ReadbackBuffers(pub Vec<ReadbackBuffer>);
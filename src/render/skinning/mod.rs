@@ -0,0 +1,33 @@
+use crate::render::skinning::node::SkinningNode;
+use crate::render::skinning::pipeline::SkinningPipeline;
+use crate::render::skinning::systems::{extract_skins, prepare_skinning, queue_skinning_bind_group};
+use crate::render::skinning::types::{ExtractedSkins, SkinningBuffers};
+use bevy::prelude::*;
+use bevy::render::render_graph::RenderGraph;
+use bevy::render::{RenderApp, RenderSet};
+
+pub mod node;
+pub mod pipeline;
+pub mod systems;
+pub mod types;
+
+/// Deforms skinned meshes on the GPU before the raytrace node, expects the raytrace node to be in the render graph
+/// already. There is no acceleration structure to refit, the tracer reads the deformed vertices directly.
+pub struct SkinningPlugin;
+
+impl Plugin for SkinningPlugin {
+  fn build(&self, app: &mut App) {
+    let render_app = app.sub_app_mut(RenderApp);
+    render_app
+      .init_resource::<SkinningPipeline>()
+      .init_resource::<ExtractedSkins>()
+      .init_resource::<SkinningBuffers>()
+      .add_system(extract_skins.in_schedule(ExtractSchedule))
+      .add_system(prepare_skinning.in_set(RenderSet::Prepare))
+      .add_system(queue_skinning_bind_group.in_set(RenderSet::Queue));
+
+    let mut render_graph = render_app.world.resource_mut::<RenderGraph>();
+    render_graph.add_node("skinning", SkinningNode);
+    render_graph.add_node_edge("skinning", "raytrace");
+  }
+}
@@ -0,0 +1,38 @@
+use crate::render::skinning::pipeline::SkinningPipeline;
+use crate::render::skinning::types::{SkinningBindGroup, SkinningBuffers};
+use bevy::prelude::*;
+use bevy::render::render_graph;
+use bevy::render::render_resource::{ComputePassDescriptor, PipelineCache};
+use bevy::render::renderer::RenderContext;
+
+/// Deforms the skinned instances before the scene is traced.
+pub struct SkinningNode;
+
+impl render_graph::Node for SkinningNode {
+  fn run(
+    &self,
+    _graph: &mut render_graph::RenderGraphContext,
+    render_context: &mut RenderContext,
+    world: &World,
+  ) -> Result<(), render_graph::NodeRunError> {
+    let buffers = world.resource::<SkinningBuffers>();
+    if buffers.instance_count == 0 {
+      return Ok(());
+    }
+    let Some(bind_group) = world.get_resource::<SkinningBindGroup>() else {
+      return Ok(());
+    };
+    let pipeline_cache = world.resource::<PipelineCache>();
+    let Some(pipeline) = pipeline_cache.get_compute_pipeline(world.resource::<SkinningPipeline>().pipeline) else {
+      return Ok(());
+    };
+
+    let mut pass = render_context
+      .command_encoder()
+      .begin_compute_pass(&ComputePassDescriptor::default());
+    pass.set_pipeline(pipeline);
+    pass.set_bind_group(0, &bind_group.bind_group, &[]);
+    pass.dispatch_workgroups((buffers.max_vertex_count + 63) / 64, buffers.instance_count, 1);
+    Ok(())
+  }
+}
@@ -0,0 +1,57 @@
+use bevy::prelude::*;
+use bevy::render::render_resource::{
+  BindGroupLayout, BindGroupLayoutDescriptor, BindGroupLayoutEntry, BindingType, BufferBindingType,
+  CachedComputePipelineId, ComputePipelineDescriptor, PipelineCache, ShaderStages,
+};
+use bevy::render::renderer::RenderDevice;
+use std::borrow::Cow;
+
+#[derive(Resource)]
+pub struct SkinningPipeline {
+  pub bind_group_layout: BindGroupLayout,
+  pub pipeline: CachedComputePipelineId,
+}
+
+impl FromWorld for SkinningPipeline {
+  fn from_world(world: &mut World) -> Self {
+    let storage_entry = |binding, read_only| BindGroupLayoutEntry {
+      binding,
+      visibility: ShaderStages::COMPUTE,
+      ty: BindingType::Buffer {
+        ty: BufferBindingType::Storage { read_only },
+        has_dynamic_offset: false,
+        min_binding_size: None,
+      },
+      count: None,
+    };
+    let bind_group_layout = world
+      .resource::<RenderDevice>()
+      .create_bind_group_layout(&BindGroupLayoutDescriptor {
+        label: None,
+        entries: &[
+          // Ray traced vertices, the deformed copies are written in place
+          storage_entry(0, false),
+          storage_entry(1, true),
+          storage_entry(2, true),
+          storage_entry(3, true),
+        ],
+      });
+
+    let shader = world.resource::<AssetServer>().load("shaders/skinning.wgsl");
+    let pipeline = world
+      .resource::<PipelineCache>()
+      .queue_compute_pipeline(ComputePipelineDescriptor {
+        label: None,
+        layout: vec![bind_group_layout.clone()],
+        push_constant_ranges: vec![],
+        shader,
+        shader_defs: vec![],
+        entry_point: Cow::from("main"),
+      });
+
+    SkinningPipeline {
+      bind_group_layout,
+      pipeline,
+    }
+  }
+}
@@ -0,0 +1,121 @@
+use crate::render::raytracer::types::{SceneBuffers, VertexStorage};
+use crate::render::skinning::pipeline::SkinningPipeline;
+use crate::render::skinning::types::{ExtractedSkins, ShaderSkinInstance, SkinningBindGroup, SkinningBuffers};
+use bevy::prelude::*;
+use bevy::render::mesh::skinning::{SkinnedMesh, SkinnedMeshInverseBindposes};
+use bevy::render::render_resource::{BindGroupDescriptor, BindGroupEntry};
+use bevy::render::renderer::{RenderDevice, RenderQueue};
+use bevy::render::Extract;
+use bevy_editor_pls::prelude::NotInScene;
+use itertools::Itertools;
+
+pub fn extract_skins(
+  mut skins: ResMut<ExtractedSkins>,
+  skinned: Extract<Query<(Entity, &SkinnedMesh, &GlobalTransform), Without<NotInScene>>>,
+  inverse_bindposes: Extract<Res<Assets<SkinnedMeshInverseBindposes>>>,
+  joints: Extract<Query<&GlobalTransform>>,
+) {
+  skins.0.clear();
+  for (entity, skin, transform) in skinned.iter() {
+    let matrices = inverse_bindposes.get(&skin.inverse_bindposes).and_then(|bindposes| {
+      skin
+        .joints
+        .iter()
+        .zip(bindposes.iter())
+        .map(|(joint, bindpose)| Some(joints.get(*joint).ok()?.compute_matrix() * *bindpose))
+        .collect::<Option<Vec<_>>>()
+    });
+    // Until the skin is loaded the mesh follows its entity like any other
+    let matrices = matrices.unwrap_or_else(|| vec![transform.compute_matrix()]);
+    skins.0.insert(entity, matrices);
+  }
+}
+
+pub fn prepare_skinning(
+  render_device: Res<RenderDevice>,
+  render_queue: Res<RenderQueue>,
+  vertex_storage: Res<VertexStorage>,
+  skins: Res<ExtractedSkins>,
+  mut buffers: ResMut<SkinningBuffers>,
+) {
+  if vertex_storage.is_changed() {
+    buffers
+      .skin_vertices
+      .write(&render_device, &render_queue, &vertex_storage.skin_verticies);
+  }
+  let mut instances = vec![];
+  let mut joints = vec![];
+  for (entity, range) in vertex_storage.skinned_map.iter().sorted_by_key(|(entity, _)| **entity) {
+    let (Some(skin_first), Some(matrices)) = (range.skin_first, skins.0.get(entity)) else {
+      continue;
+    };
+    instances.push(ShaderSkinInstance {
+      skin_vertex: skin_first as u32,
+      output_vertex: range.vertex_first as u32,
+      vertex_count: range.vertex_len as u32,
+      joint_offset: joints.len() as u32,
+      joint_count: matrices.len() as u32,
+      pad: [0; 3],
+    });
+    joints.extend_from_slice(matrices);
+  }
+  buffers.instance_count = instances.len() as u32;
+  buffers.max_vertex_count = instances
+    .iter()
+    .map(|instance| instance.vertex_count)
+    .max()
+    .unwrap_or(0);
+  if instances.is_empty() {
+    return;
+  }
+  buffers.instances.write(&render_device, &render_queue, &instances);
+  buffers.joints.write(&render_device, &render_queue, &joints);
+}
+
+pub fn queue_skinning_bind_group(
+  mut commands: Commands,
+  pipeline: Res<SkinningPipeline>,
+  render_device: Res<RenderDevice>,
+  scene_buffers: Res<SceneBuffers>,
+  buffers: Res<SkinningBuffers>,
+  bind_group: Option<Res<SkinningBindGroup>>,
+) {
+  let (Some(vertices), Some(skin_vertices), Some(instances), Some(joints)) = (
+    scene_buffers.vertices.buffer(),
+    buffers.skin_vertices.buffer(),
+    buffers.instances.buffer(),
+    buffers.joints.buffer(),
+  ) else {
+    return;
+  };
+  let ids = [vertices.id(), skin_vertices.id(), instances.id(), joints.id()];
+  if bind_group.map_or(false, |bind_group| bind_group.buffers == ids) {
+    return;
+  }
+  let bind_group = render_device.create_bind_group(&BindGroupDescriptor {
+    label: Some("skinning_bind_group"),
+    layout: &pipeline.bind_group_layout,
+    entries: &[
+      BindGroupEntry {
+        binding: 0,
+        resource: vertices.as_entire_binding(),
+      },
+      BindGroupEntry {
+        binding: 1,
+        resource: skin_vertices.as_entire_binding(),
+      },
+      BindGroupEntry {
+        binding: 2,
+        resource: instances.as_entire_binding(),
+      },
+      BindGroupEntry {
+        binding: 3,
+        resource: joints.as_entire_binding(),
+      },
+    ],
+  });
+  commands.insert_resource(SkinningBindGroup {
+    buffers: ids,
+    bind_group,
+  });
+}
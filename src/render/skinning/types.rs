@@ -0,0 +1,63 @@
+use crate::render::growable_buffer::GrowableBuffer;
+use bevy::prelude::*;
+use bevy::render::render_resource::{BindGroup, BufferId, BufferUsages};
+use bevy::utils::HashMap;
+use bytemuck::{Pod, Zeroable};
+
+/// Bind pose vertex of a skinned mesh with its joints and weights.
+#[repr(C)]
+#[derive(Zeroable, Pod, Copy, Clone)]
+pub struct ShaderSkinVertex {
+  pub position: [f32; 3],
+  pub p1: [u8; 4],
+  pub normal: [f32; 3],
+  pub p2: [u8; 4],
+  pub joints: [u32; 4],
+  pub weights: [f32; 4],
+}
+
+/// One skinned instance, deforms `vertex_count` vertices from `skin_vertex` into the ray traced vertices at
+/// `output_vertex`.
+#[repr(C)]
+#[derive(Zeroable, Pod, Copy, Clone)]
+pub struct ShaderSkinInstance {
+  pub skin_vertex: u32,
+  pub output_vertex: u32,
+  pub vertex_count: u32,
+  pub joint_offset: u32,
+  pub joint_count: u32,
+  pub pad: [u32; 3],
+}
+
+/// World space joint matrices of every skinned entity, times the inverse bind poses.
+#[derive(Resource, Default)]
+pub struct ExtractedSkins(pub HashMap<Entity, Vec<Mat4>>);
+
+#[derive(Resource)]
+pub struct SkinningBuffers {
+  pub skin_vertices: GrowableBuffer,
+  pub instances: GrowableBuffer,
+  pub joints: GrowableBuffer,
+  pub instance_count: u32,
+  /// Vertices of the largest instance, the width of the dispatch.
+  pub max_vertex_count: u32,
+}
+
+impl Default for SkinningBuffers {
+  fn default() -> Self {
+    Self {
+      skin_vertices: GrowableBuffer::new("skin_vertex_buffer", BufferUsages::STORAGE),
+      instances: GrowableBuffer::new("skin_instance_buffer", BufferUsages::STORAGE),
+      joints: GrowableBuffer::new("skin_joint_buffer", BufferUsages::STORAGE),
+      instance_count: 0,
+      max_vertex_count: 0,
+    }
+  }
+}
+
+#[derive(Resource)]
+pub struct SkinningBindGroup {
+  /// Ray traced vertices followed by the skinning buffers, the group is rebuilt once one of them is reallocated.
+  pub buffers: [BufferId; 4],
+  pub bind_group: BindGroup,
+}
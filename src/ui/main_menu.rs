@@ -1,4 +1,6 @@
+use crate::app::scenes::{OpenScenes, RecentScenes, SceneRequest};
 use crate::app::AppState;
+use crate::ui::scene_browser::{scene_browser, SceneBrowserState};
 use bevy::app::AppExit;
 use bevy::prelude::*;
 use bevy_egui::egui::Layout;
@@ -8,6 +10,11 @@ pub fn main_menu(
   mut egui: EguiContexts,
   mut next_state: ResMut<NextState<AppState>>,
   mut exit_event: EventWriter<AppExit>,
+  mut scene_requests: EventWriter<SceneRequest>,
+  mut browser: Local<SceneBrowserState>,
+  open_scenes: Res<OpenScenes>,
+  recent_scenes: Res<RecentScenes>,
+  asset_server: Res<AssetServer>,
   windows: Query<&Window>,
 ) {
   let x = (windows.single().width() - 300.0) / 2.0;
@@ -23,6 +30,11 @@ pub fn main_menu(
           exit_event.send(AppExit);
         }
       });
+      ui.separator();
+      egui::ScrollArea::vertical().show(ui, |ui| {
+        let requests = scene_browser(ui, &mut browser, &open_scenes, &recent_scenes, &asset_server);
+        scene_requests.send_batch(requests);
+      });
       ui.allocate_space(ui.available_size());
     });
 }
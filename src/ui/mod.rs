@@ -1,3 +1,5 @@
 pub mod debug_ui;
 pub mod main_menu;
 pub mod rt_viewport;
+pub mod scene_browser;
+pub mod settings_window;
@@ -1,18 +1,162 @@
-use crate::render::raytracer::types::RaytracingImage;
+use crate::app::export::{ExportJob, ExportJobs, ExportOptions, ExportTarget, Tonemapper};
+use crate::render::raytracer::types::{
+  RaytraceProjection, RaytraceSettings, RaytracedCamera, TextureIter, ThinLensCamera,
+};
+use crate::render::readback::types::ImageReadbacks;
+use crate::ui::settings_window::{camera_picker, combo_box};
+use crate::util::export::timestamped_file_name;
+use crate::util::pick::pick;
 use bevy::prelude::*;
 use bevy_editor_pls::editor_window::{EditorWindow, EditorWindowContext};
 use bevy_egui::{egui, EguiUserTextures};
+use std::path::Path;
 
 pub struct RTViewportWindow;
 
+pub struct RTViewportState {
+  click_to_focus: bool,
+  camera: Option<Entity>,
+  /// Directory saved renders are written to, created on demand.
+  output_dir: String,
+  export_options: ExportOptions,
+}
+
+impl Default for RTViewportState {
+  fn default() -> Self {
+    Self {
+      click_to_focus: false,
+      camera: None,
+      output_dir: "renders".to_string(),
+      export_options: ExportOptions::default(),
+    }
+  }
+}
+
 impl EditorWindow for RTViewportWindow {
-  type State = ();
+  type State = RTViewportState;
   const NAME: &'static str = "RTX Viewport";
 
-  fn ui(world: &mut World, _cx: EditorWindowContext, ui: &mut egui::Ui) {
-    let viewport_image = &world.resource::<RaytracingImage>().0;
-    let egui = world.resource::<EguiUserTextures>();
-    let id = egui.image_id(viewport_image);
-    ui.image(id.unwrap(), ui.available_size());
+  fn ui(world: &mut World, mut cx: EditorWindowContext, ui: &mut egui::Ui) {
+    let state = cx.state_mut::<RTViewportWindow>().unwrap();
+    ui.checkbox(&mut state.click_to_focus, "Click to focus");
+    let Some(camera) = camera_picker(world, ui, &mut state.camera) else {
+      return;
+    };
+    ui.horizontal(|ui| {
+      ui.label("Output directory");
+      ui.text_edit_singleline(&mut state.output_dir);
+    });
+    let options = &mut state.export_options;
+    ui.horizontal(|ui| {
+      ui.checkbox(&mut options.raw, "Raw");
+      combo_box(ui, "Tonemapper", &mut options.tonemapper, &Tonemapper::ALL);
+      if ui.button("Save PNG").clicked() {
+        save_file(world, camera, Path::new(&state.output_dir), "png", *options);
+      }
+      if ui.button("Copy to clipboard").clicked() {
+        save(world, camera, ExportTarget::Clipboard, *options);
+      }
+    });
+    ui.horizontal(|ui| {
+      ui.checkbox(&mut options.half, "Half");
+      ui.checkbox(&mut options.aovs, "AOVs");
+      if ui.button("Save HDR").clicked() {
+        save_file(world, camera, Path::new(&state.output_dir), "exr", *options);
+      }
+    });
+    let Some(viewport_image) = world.get::<RaytracedCamera>(camera).map(|camera| camera.image.clone()) else {
+      return;
+    };
+    let id = world.resource_mut::<EguiUserTextures>().add_image(viewport_image);
+    let response = ui.image(id, ui.available_size()).interact(egui::Sense::click());
+    if state.click_to_focus && response.clicked() {
+      if let Some(position) = response.interact_pointer_pos() {
+        let rect = response.rect;
+        let uv = Vec2::new(
+          (position.x - rect.min.x) / rect.width(),
+          (position.y - rect.min.y) / rect.height(),
+        );
+        focus_at(world, camera, uv);
+      }
+    }
+  }
+}
+
+/// Casts a pick ray through `uv` of the ray-traced image and moves the focus plane of the camera onto the hit.
+fn focus_at(world: &mut World, camera_entity: Entity, uv: Vec2) {
+  // The lens is only simulated for the projection of the camera
+  let projection = world
+    .get::<RaytraceSettings>(camera_entity)
+    .map(|settings| settings.projection);
+  if projection.map_or(false, |projection| projection != RaytraceProjection::Camera) {
+    return;
+  }
+  let Ok((camera, transform)) = world.query::<(&Camera, &GlobalTransform)>().get(world, camera_entity) else {
+    return;
+  };
+  let (camera, transform) = (camera.clone(), *transform);
+  let image_size = world
+    .get::<RaytracedCamera>(camera_entity)
+    .and_then(|camera| camera.size(world.resource::<Assets<Image>>()))
+    .map(|size| size.as_vec2());
+  // Same aspect correction as the ray generation in the shader
+  let aspect_scale = match (image_size, camera.logical_viewport_size()) {
+    (Some(image), Some(viewport)) => (image.x / image.y) / (viewport.x / viewport.y),
+    _ => 1.0,
+  };
+  let ndc = Vec2::new((uv.x * 2.0 - 1.0) * aspect_scale, 1.0 - uv.y * 2.0);
+  let (Some(near), Some(far)) = (
+    camera.ndc_to_world(&transform, ndc.extend(1.0)),
+    camera.ndc_to_world(&transform, ndc.extend(f32::EPSILON)),
+  ) else {
+    return;
+  };
+  let ray = Ray {
+    origin: near,
+    direction: (far - near).normalize(),
+  };
+  let Some(distance) = pick(world, &ray) else {
+    return;
+  };
+  let focus_distance = (ray.get_point(distance) - transform.translation()).dot(transform.forward());
+  if let Some(mut lens) = world.get_mut::<ThinLensCamera>(camera_entity) {
+    lens.focus_distance = focus_distance;
+  }
+}
+
+/// Saves a timestamped file to `directory`, so repeated saves never overwrite each other.
+fn save_file(world: &mut World, camera_entity: Entity, directory: &Path, extension: &str, options: ExportOptions) {
+  if let Err(err) = std::fs::create_dir_all(directory) {
+    error!("Failed to create {}: {}", directory.display(), err);
+    return;
+  }
+  let path = directory.join(timestamped_file_name("render", extension));
+  save(world, camera_entity, ExportTarget::File(path), options);
+}
+
+/// Queues an export of the camera's images, written by `write_exports` once they are read back.
+fn save(world: &mut World, camera_entity: Entity, target: ExportTarget, options: ExportOptions) {
+  let Ok((camera, settings, samples)) = world
+    .query::<(&RaytracedCamera, &RaytraceSettings, &TextureIter)>()
+    .get(world, camera_entity)
+  else {
+    return;
+  };
+  let (camera, settings, samples) = (camera.clone(), settings.clone(), samples.0);
+  let job = world.resource_scope(|world, mut readbacks: Mut<ImageReadbacks>| {
+    ExportJob::new(
+      target,
+      options,
+      &camera,
+      world.resource::<Assets<Image>>(),
+      &mut readbacks,
+    )
+  });
+  match job {
+    Ok(job) => world
+      .resource_mut::<ExportJobs>()
+      .0
+      .push(job.with_render_info(&settings, samples)),
+    Err(err) => error!("{}", err),
   }
 }
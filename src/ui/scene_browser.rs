@@ -0,0 +1,111 @@
+use crate::app::scenes::{asset_scenes, OpenScenes, RecentScenes, SceneRequest};
+use crate::loaders::rtscene::is_rtscene_file;
+use bevy::prelude::*;
+use bevy_editor_pls::editor_window::{EditorWindow, EditorWindowContext};
+use bevy_egui::egui;
+use std::path::{Path, PathBuf};
+
+#[derive(Default)]
+pub struct SceneBrowserState {
+  /// Typed in path, relative to the asset folder or absolute.
+  path: String,
+  /// Listed on first use and on refresh.
+  asset_scenes: Option<Vec<PathBuf>>,
+}
+
+/// Lists the open, recent and asset folder scenes. Returns what was clicked.
+pub fn scene_browser(
+  ui: &mut egui::Ui,
+  state: &mut SceneBrowserState,
+  open: &OpenScenes,
+  recent: &RecentScenes,
+  asset_server: &AssetServer,
+) -> Vec<SceneRequest> {
+  let mut requests = vec![];
+  ui.label("Open scenes");
+  for scene in &open.scenes {
+    ui.horizontal(|ui| {
+      ui.label(format!(
+        "{} ({:?})",
+        scene.path.display(),
+        asset_server.get_load_state(&scene.handle)
+      ));
+      if ui.button("Unload").clicked() {
+        requests.push(SceneRequest::Unload(scene.entity));
+      }
+    });
+  }
+
+  ui.separator();
+  ui.horizontal(|ui| {
+    ui.text_edit_singleline(&mut state.path);
+    if !state.path.is_empty() {
+      let path = Path::new(&state.path);
+      scene_buttons(ui, path, &mut requests);
+      let save = ui
+        .add_enabled(is_rtscene_file(path), egui::Button::new("Save"))
+        .on_hover_text("Write the open scenes and the camera to this .rtscene.ron file")
+        .on_disabled_hover_text("Scenes are saved as .rtscene.ron files");
+      if save.clicked() {
+        requests.push(SceneRequest::Save(path.to_path_buf()));
+      }
+    }
+  });
+
+  ui.separator();
+  ui.label("Recent");
+  for path in &recent.0 {
+    scene_row(ui, path, &mut requests);
+  }
+
+  ui.separator();
+  ui.horizontal(|ui| {
+    ui.label("Assets");
+    if ui.button("Refresh").clicked() {
+      state.asset_scenes = None;
+    }
+  });
+  for path in state.asset_scenes.get_or_insert_with(asset_scenes).iter() {
+    scene_row(ui, path, &mut requests);
+  }
+  requests
+}
+
+fn scene_row(ui: &mut egui::Ui, path: &Path, requests: &mut Vec<SceneRequest>) {
+  ui.horizontal(|ui| {
+    scene_buttons(ui, path, requests);
+    ui.label(path.display().to_string());
+  });
+}
+
+fn scene_buttons(ui: &mut egui::Ui, path: &Path, requests: &mut Vec<SceneRequest>) {
+  if ui.button("Open").on_hover_text("Replace the open scenes").clicked() {
+    requests.push(SceneRequest::Open(path.to_path_buf()));
+  }
+  if ui.button("Add").on_hover_text("Open next to the open scenes").clicked() {
+    requests.push(SceneRequest::Add(path.to_path_buf()));
+  }
+}
+
+pub struct SceneBrowserWindow;
+
+impl EditorWindow for SceneBrowserWindow {
+  type State = SceneBrowserState;
+  const NAME: &'static str = "Scenes";
+
+  fn ui(world: &mut World, mut cx: EditorWindowContext, ui: &mut egui::Ui) {
+    let state = cx.state_mut::<SceneBrowserWindow>().unwrap();
+    let requests = egui::ScrollArea::vertical()
+      .show(ui, |ui| {
+        scene_browser(
+          ui,
+          state,
+          world.resource::<OpenScenes>(),
+          world.resource::<RecentScenes>(),
+          world.resource::<AssetServer>(),
+        )
+      })
+      .inner;
+    world.resource_mut::<Events<SceneRequest>>().extend(requests);
+  }
+}
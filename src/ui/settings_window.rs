@@ -0,0 +1,214 @@
+use crate::render::raytracer::types::{
+  Aperture, CubeFace, PBRCameraEntity, RaytraceMarkerPolicy, RaytraceProjection, RaytraceSettings, RaytraceViewMode,
+  RaytracedCamera, ReconstructionFilter, SamplingMode, ThinLensCamera,
+};
+use bevy::prelude::*;
+use bevy_editor_pls::editor_window::{EditorWindow, EditorWindowContext};
+use bevy_egui::egui;
+use bevy_egui::egui::Widget;
+use std::f32::consts::TAU;
+
+pub struct RaytraceSettingsWindow;
+
+#[derive(Default)]
+pub struct RaytraceSettingsState {
+  camera: Option<Entity>,
+}
+
+impl EditorWindow for RaytraceSettingsWindow {
+  type State = RaytraceSettingsState;
+  const NAME: &'static str = "RTX Settings";
+
+  fn ui(world: &mut World, mut cx: EditorWindowContext, ui: &mut egui::Ui) {
+    let state = cx.state_mut::<RaytraceSettingsWindow>().unwrap();
+    let mut policy = *world.resource::<RaytraceMarkerPolicy>();
+    combo_box(ui, "Traced meshes", &mut policy, &RaytraceMarkerPolicy::ALL);
+    world.resource_mut::<RaytraceMarkerPolicy>().set_if_neq(policy);
+    ui.separator();
+    let Some(camera) = camera_picker(world, ui, &mut state.camera) else {
+      ui.label("No ray traced camera");
+      return;
+    };
+    // Edit a copy so that the accumulation only resets when something actually changed
+    let Some(mut settings) = world.get::<RaytraceSettings>(camera).cloned() else {
+      return;
+    };
+
+    combo_box(ui, "View mode", &mut settings.view_mode, &RaytraceViewMode::ALL);
+    combo_box(ui, "Projection", &mut settings.projection, &RaytraceProjection::ALL);
+    match settings.projection {
+      RaytraceProjection::Fisheye => {
+        egui::Slider::new(&mut settings.fisheye_fov, 10.0..=360.0)
+          .text("Field of view")
+          .suffix("°")
+          .ui(ui);
+      }
+      RaytraceProjection::CubeFace => combo_box(ui, "Cube face", &mut settings.cube_face, &CubeFace::ALL),
+      _ => {}
+    }
+    egui::Slider::new(&mut settings.render_scale, 0.1..=2.0)
+      .text("Render scale")
+      .ui(ui);
+    let mut fixed_resolution = settings.resolution.is_some();
+    ui.horizontal(|ui| {
+      ui.checkbox(&mut fixed_resolution, "Fixed resolution");
+      if fixed_resolution {
+        let resolution = settings.resolution.get_or_insert(UVec2::new(1920, 1080));
+        egui::DragValue::new(&mut resolution.x).clamp_range(1..=8192).ui(ui);
+        egui::DragValue::new(&mut resolution.y).clamp_range(1..=8192).ui(ui);
+      } else {
+        settings.resolution = None;
+      }
+    });
+    combo_box(ui, "Sampling", &mut settings.sampling, &SamplingMode::ALL);
+    ui.horizontal(|ui| {
+      ui.label("Seed");
+      egui::DragValue::new(&mut settings.seed).ui(ui);
+    });
+    ui.horizontal(|ui| {
+      ui.label("Max samples");
+      egui::DragValue::new(&mut settings.max_samples)
+        .clamp_range(1..=1 << 20)
+        .ui(ui);
+    });
+    combo_box(ui, "Filter", &mut settings.filter, &ReconstructionFilter::ALL);
+    egui::Slider::new(&mut settings.filter_radius, 0.5..=4.0)
+      .text("Filter radius")
+      .ui(ui);
+    ui.checkbox(&mut settings.motion_blur, "Motion blur");
+    ui.add_enabled_ui(settings.motion_blur, |ui| {
+      egui::Slider::new(&mut settings.shutter_open, 0.0..=1.0)
+        .text("Shutter open")
+        .ui(ui);
+      egui::Slider::new(&mut settings.shutter_close, settings.shutter_open..=1.0)
+        .text("Shutter close")
+        .ui(ui);
+    });
+
+    ui.separator();
+    // Cameras are pinholes until a lens is added here or by a scene file
+    let current_lens = world.get::<ThinLensCamera>(camera).copied();
+    let mut depth_of_field = current_lens.is_some();
+    ui.checkbox(&mut depth_of_field, "Depth of field");
+    let mut lens = current_lens.unwrap_or_default();
+    ui.add_enabled_ui(depth_of_field, |ui| {
+      match &mut lens.aperture {
+        Aperture::FStop(f_stop) => egui::Slider::new(f_stop, 0.5..=32.0)
+          .logarithmic(true)
+          .text("f-stop")
+          .ui(ui),
+        Aperture::Radius(radius) => egui::Slider::new(radius, 0.0..=1.0)
+          .logarithmic(true)
+          .text("Lens radius")
+          .ui(ui),
+      };
+      egui::Slider::new(&mut lens.focus_distance, 0.01..=1000.0)
+        .logarithmic(true)
+        .text("Focus distance")
+        .ui(ui);
+      egui::Slider::new(&mut lens.blades, 0..=12).text("Blades").ui(ui);
+      egui::Slider::new(&mut lens.blade_rotation, 0.0..=TAU)
+        .text("Blade rotation")
+        .ui(ui);
+    });
+    match (depth_of_field, current_lens) {
+      (true, current) if current != Some(lens) => {
+        world.entity_mut(camera).insert(lens);
+      }
+      (false, Some(_)) => {
+        world.entity_mut(camera).remove::<ThinLensCamera>();
+      }
+      _ => {}
+    }
+
+    ui.separator();
+    let denoiser = &mut settings.denoiser;
+    ui.checkbox(&mut denoiser.enabled, "Denoiser");
+    ui.add_enabled_ui(denoiser.enabled, |ui| {
+      egui::Slider::new(&mut denoiser.iterations, 1..=8)
+        .text("Iterations")
+        .ui(ui);
+      egui::Slider::new(&mut denoiser.sigma_color, 0.01..=10.0)
+        .logarithmic(true)
+        .text("Color sigma")
+        .ui(ui);
+      egui::Slider::new(&mut denoiser.sigma_normal, 1.0..=256.0)
+        .logarithmic(true)
+        .text("Normal sigma")
+        .ui(ui);
+      egui::Slider::new(&mut denoiser.sigma_depth, 0.001..=10.0)
+        .logarithmic(true)
+        .text("Depth sigma")
+        .ui(ui);
+    });
+
+    ui.separator();
+    let temporal = &mut settings.temporal;
+    ui.checkbox(&mut temporal.enabled, "Temporal accumulation");
+    ui.add_enabled_ui(temporal.enabled, |ui| {
+      egui::Slider::new(&mut temporal.alpha, 0.01..=1.0)
+        .logarithmic(true)
+        .text("Blend factor")
+        .ui(ui);
+      egui::Slider::new(&mut temporal.position_threshold, 0.001..=1.0)
+        .logarithmic(true)
+        .text("Position threshold")
+        .ui(ui);
+      egui::Slider::new(&mut temporal.normal_threshold, 0.0..=1.0)
+        .text("Normal threshold")
+        .ui(ui);
+    });
+
+    if let Some(mut current) = world.get_mut::<RaytraceSettings>(camera) {
+      current.set_if_neq(settings);
+    }
+  }
+}
+
+pub fn combo_box<T: PartialEq + Copy + std::fmt::Debug>(ui: &mut egui::Ui, label: &str, value: &mut T, options: &[T]) {
+  egui::ComboBox::from_label(label)
+    .selected_text(format!("{:?}", value))
+    .show_ui(ui, |ui| {
+      for option in options {
+        ui.selectable_value(value, *option, format!("{:?}", option));
+      }
+    });
+}
+
+/// Lets the user choose one of the ray traced cameras, defaulting to the one shown in the window.
+pub fn camera_picker(world: &mut World, ui: &mut egui::Ui, selected: &mut Option<Entity>) -> Option<Entity> {
+  let cameras = world
+    .query_filtered::<(Entity, Option<&Name>), With<RaytracedCamera>>()
+    .iter(world)
+    .map(|(entity, name)| {
+      (
+        entity,
+        name.map_or_else(|| format!("{:?}", entity), |name| name.to_string()),
+      )
+    })
+    .collect::<Vec<_>>();
+  if !selected.map_or(false, |entity| cameras.iter().any(|(camera, _)| *camera == entity)) {
+    *selected = world
+      .get_resource::<PBRCameraEntity>()
+      .map(|camera| camera.0)
+      .filter(|entity| cameras.iter().any(|(camera, _)| camera == entity))
+      .or_else(|| cameras.first().map(|(camera, _)| *camera));
+  }
+  let current = *selected;
+  if cameras.len() > 1 {
+    let label = |entity: Entity| {
+      cameras
+        .iter()
+        .find(|(camera, _)| *camera == entity)
+        .map_or_else(String::new, |(_, name)| name.clone())
+    };
+    egui::ComboBox::from_label("Camera")
+      .selected_text(current.map_or_else(String::new, label))
+      .show_ui(ui, |ui| {
+        for (camera, name) in &cameras {
+          ui.selectable_value(selected, Some(*camera), name.as_str());
+        }
+      });
+  }
+  current
+}
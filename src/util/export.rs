@@ -0,0 +1,210 @@
+use bevy::prelude::*;
+use exr::prelude::{AnyChannel, AnyChannels, FlatSamples, Image as ExrImage, WritableImage};
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Writes tightly packed 8 bit sRGB rgba texels as a png file with keyword and text pairs as `tEXt` chunks.
+pub fn write_png(path: &Path, size: UVec2, rgba: &[u8], text: &[(String, String)]) -> std::io::Result<()> {
+  let mut encoder = png::Encoder::new(BufWriter::new(File::create(path)?), size.x, size.y);
+  encoder.set_color(png::ColorType::Rgba);
+  encoder.set_depth(png::BitDepth::Eight);
+  encoder.set_srgb(png::SrgbRenderingIntent::Perceptual);
+  for (keyword, text) in text {
+    encoder.add_text_chunk(keyword.clone(), text.clone())?;
+  }
+  let mut writer = encoder.write_header()?;
+  writer.write_image_data(rgba)?;
+  writer.finish()?;
+  Ok(())
+}
+
+/// Writes linear rgb rows, top row first, as a little endian portable float map.
+pub fn write_pfm(path: &Path, size: UVec2, rgb: &[Vec3]) -> std::io::Result<()> {
+  let mut file = BufWriter::new(File::create(path)?);
+  // A negative scale marks little endian data
+  write!(file, "PF\n{} {}\n-1.0\n", size.x, size.y)?;
+  // Rows are stored bottom to top
+  for row in rgb.chunks_exact(size.x as usize).rev() {
+    for texel in row {
+      for value in texel.to_array() {
+        file.write_all(&value.to_le_bytes())?;
+      }
+    }
+  }
+  file.flush()
+}
+
+/// Reads a color portable float map as linear rgb rows, top row first.
+pub fn read_pfm(path: &Path) -> std::io::Result<(UVec2, Vec<Vec3>)> {
+  let bytes = std::fs::read(path)?;
+  parse_pfm(&bytes).map_err(|err| std::io::Error::new(std::io::ErrorKind::InvalidData, err))
+}
+
+fn parse_pfm(bytes: &[u8]) -> Result<(UVec2, Vec<Vec3>), String> {
+  // Magic, width, height and scale, the data starts after the whitespace ending the scale
+  let mut header = vec![];
+  let mut start = 0;
+  let mut data = bytes.len();
+  for (i, byte) in bytes.iter().enumerate() {
+    if !byte.is_ascii_whitespace() {
+      continue;
+    }
+    if i > start {
+      header.push(std::str::from_utf8(&bytes[start..i]).map_err(|_| "invalid header")?);
+    }
+    start = i + 1;
+    if header.len() == 4 {
+      data = start;
+      break;
+    }
+  }
+  let [magic, width, height, scale] = header[..] else {
+    return Err("truncated header".into());
+  };
+  if magic != "PF" {
+    return Err(format!("{} is not a color float map", magic));
+  }
+  let parse = |value: &str| value.parse::<u32>().ok().filter(|value| *value > 0);
+  let size = UVec2::new(
+    parse(width).ok_or_else(|| format!("invalid width {}", width))?,
+    parse(height).ok_or_else(|| format!("invalid height {}", height))?,
+  );
+  // A negative scale marks little endian data
+  let scale = scale.parse::<f32>().map_err(|_| format!("invalid scale {}", scale))?;
+  let values = bytes[data..]
+    .chunks_exact(4)
+    .map(|value| {
+      let value = value.try_into().unwrap();
+      if scale < 0.0 {
+        f32::from_le_bytes(value)
+      } else {
+        f32::from_be_bytes(value)
+      }
+    })
+    .collect::<Vec<_>>();
+  let texels = (size.x * size.y) as usize;
+  if values.len() < texels * 3 {
+    return Err("truncated data".into());
+  }
+  let rgb = values[..texels * 3]
+    .chunks_exact(3)
+    .map(|texel| Vec3::new(texel[0], texel[1], texel[2]))
+    .collect::<Vec<_>>();
+  // Rows are stored bottom to top
+  Ok((
+    size,
+    rgb.chunks_exact(size.x as usize).rev().flatten().copied().collect(),
+  ))
+}
+
+/// Root mean square difference of the channels of two images of the same size.
+pub fn rmse(a: &[Vec3], b: &[Vec3]) -> f32 {
+  let sum = a
+    .iter()
+    .zip(b)
+    .map(|(a, b)| (*a - *b).length_squared() as f64)
+    .sum::<f64>();
+  (sum / (a.len().max(1) * 3) as f64).sqrt() as f32
+}
+
+/// Writes named channels of linear values, top row first, as a single part OpenEXR file. Channels of other layers
+/// are named `layer.channel`.
+pub fn write_exr(path: &Path, size: UVec2, channels: Vec<(String, Vec<f32>)>, half: bool) -> std::io::Result<()> {
+  let channels = channels
+    .into_iter()
+    .map(|(name, values)| {
+      let samples = if half {
+        FlatSamples::F16(values.into_iter().map(exr::prelude::f16::from_f32).collect())
+      } else {
+        FlatSamples::F32(values)
+      };
+      AnyChannel::new(name.as_str(), samples)
+    })
+    .collect::<Vec<_>>();
+  ExrImage::from_channels((size.x as usize, size.y as usize), AnyChannels::sort(channels.into()))
+    .write()
+    .to_file(path)
+    .map_err(|err| std::io::Error::new(std::io::ErrorKind::Other, err))
+}
+
+/// Reinterprets read back rgba32float texels.
+pub fn rgba32_texels(data: &[u8]) -> Vec<Vec4> {
+  data
+    .chunks_exact(16)
+    .map(|texel| {
+      let value = |i: usize| f32::from_le_bytes(texel[i * 4..i * 4 + 4].try_into().unwrap());
+      Vec4::new(value(0), value(1), value(2), value(3))
+    })
+    .collect()
+}
+
+/// `<prefix>-YYYYMMDD-HHMMSS-mmm.<extension>` with the current UTC time down to the millisecond, for files that should
+/// never overwrite each other.
+pub fn timestamped_file_name(prefix: &str, extension: &str) -> String {
+  let millis = SystemTime::now()
+    .duration_since(UNIX_EPOCH)
+    .map_or(0, |duration| duration.as_millis() as u64);
+  let seconds = millis / 1000;
+  let (days, time) = (seconds / 86400, seconds % 86400);
+  // Civil date from days since 1970-01-01, see http://howardhinnant.github.io/date_algorithms.html#civil_from_days
+  let z = days as i64 + 719468;
+  let era = z.div_euclid(146097);
+  let day_of_era = z.rem_euclid(146097);
+  let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+  let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+  let mp = (5 * day_of_year + 2) / 153;
+  let day = day_of_year - (153 * mp + 2) / 5 + 1;
+  let month = if mp < 10 { mp + 3 } else { mp - 9 };
+  let year = year_of_era + era * 400 + (month <= 2) as i64;
+  format!(
+    "{}-{:04}{:02}{:02}-{:02}{:02}{:02}-{:03}.{}",
+    prefix,
+    year,
+    month,
+    day,
+    time / 3600,
+    time / 60 % 60,
+    time % 60,
+    millis % 1000,
+    extension
+  )
+}
+
+#[cfg(test)]
+mod tests {
+  use super::{parse_pfm, read_pfm, rmse, write_pfm};
+  use bevy::prelude::*;
+
+  #[test]
+  fn pfm_round_trip() {
+    let size = UVec2::new(2, 3);
+    let rgb = (0..6).map(|i| Vec3::new(i as f32, 0.5, -1.0)).collect::<Vec<_>>();
+    let path = std::env::temp_dir().join(format!("pfm_round_trip-{}.pfm", std::process::id()));
+    write_pfm(&path, size, &rgb).unwrap();
+    let read = read_pfm(&path);
+    std::fs::remove_file(&path).unwrap();
+    assert_eq!(read.unwrap(), (size, rgb));
+  }
+
+  #[test]
+  fn big_endian_pfm() {
+    let mut bytes = b"PF\n1 2\n1.0\n".to_vec();
+    for value in [1.0f32, 2.0, 3.0, 4.0, 5.0, 6.0] {
+      bytes.extend(value.to_be_bytes());
+    }
+    let (size, rgb) = parse_pfm(&bytes).unwrap();
+    assert_eq!(size, UVec2::new(1, 2));
+    assert_eq!(rgb, [Vec3::new(4.0, 5.0, 6.0), Vec3::new(1.0, 2.0, 3.0)]);
+    assert!(parse_pfm(&bytes[..bytes.len() - 1]).is_err());
+    assert!(parse_pfm(b"Pf\n1 1\n-1.0\n").is_err());
+  }
+
+  #[test]
+  fn root_mean_square_error() {
+    let a = [Vec3::ZERO, Vec3::ONE];
+    assert_eq!(rmse(&a, &a), 0.0);
+    assert!((rmse(&a, &[Vec3::ONE, Vec3::ONE]) - 0.5f32.sqrt()).abs() < 1e-6);
+  }
+}
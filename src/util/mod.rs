@@ -1 +1,4 @@
 pub mod array;
+pub mod export;
+pub mod pick;
+pub mod triangulate;
@@ -0,0 +1,57 @@
+use bevy::prelude::*;
+use bevy::render::mesh::VertexAttributeValues;
+use bevy_editor_pls::prelude::NotInScene;
+
+/// Möller–Trumbore intersection, returns the distance along the ray for hits on either side of the triangle.
+pub fn intersect_triangle(ray: &Ray, v0: Vec3, v1: Vec3, v2: Vec3) -> Option<f32> {
+  let e1 = v1 - v0;
+  let e2 = v2 - v0;
+  let p = ray.direction.cross(e2);
+  let det = e1.dot(p);
+  if det.abs() < f32::EPSILON {
+    return None;
+  }
+  let inv_det = 1.0 / det;
+  let s = ray.origin - v0;
+  let u = s.dot(p) * inv_det;
+  if !(0.0..=1.0).contains(&u) {
+    return None;
+  }
+  let q = s.cross(e1);
+  let v = ray.direction.dot(q) * inv_det;
+  if v < 0.0 || u + v > 1.0 {
+    return None;
+  }
+  let t = e2.dot(q) * inv_det;
+  (t > 0.0).then_some(t)
+}
+
+pub fn intersect_mesh(ray: &Ray, mesh: &Mesh, transform: &GlobalTransform) -> Option<f32> {
+  let Some(VertexAttributeValues::Float32x3(positions)) = mesh.attribute(Mesh::ATTRIBUTE_POSITION) else {
+    return None;
+  };
+  let matrix = transform.compute_matrix();
+  let positions = positions
+    .iter()
+    .map(|p| matrix.transform_point3(Vec3::from(*p)))
+    .collect::<Vec<_>>();
+  let indices = match mesh.indices() {
+    Some(indices) => indices.iter().collect::<Vec<_>>(),
+    None => (0..positions.len()).collect::<Vec<_>>(),
+  };
+  indices
+    .chunks_exact(3)
+    .filter_map(|tri| intersect_triangle(ray, positions[tri[0]], positions[tri[1]], positions[tri[2]]))
+    .min_by(f32::total_cmp)
+}
+
+/// Closest hit distance of `ray` against every mesh in the scene.
+pub fn pick(world: &mut World, ray: &Ray) -> Option<f32> {
+  let mut query = world.query_filtered::<(&Handle<Mesh>, &GlobalTransform), Without<NotInScene>>();
+  let world: &World = world;
+  let meshes = world.resource::<Assets<Mesh>>();
+  query
+    .iter(world)
+    .filter_map(|(handle, transform)| intersect_mesh(ray, meshes.get(handle)?, transform))
+    .min_by(f32::total_cmp)
+}
@@ -0,0 +1,217 @@
+use bevy::prelude::*;
+use bevy::render::mesh::{Indices, PrimitiveTopology, VertexAttributeValues};
+
+/// Indexed triangle list with a normal per vertex, what the ray tracer consumes.
+pub struct Triangles {
+  pub positions: Vec<Vec3>,
+  pub normals: Vec<Vec3>,
+  /// Skinning joints and weights per vertex, empty for meshes that aren't skinned.
+  pub joints: Vec<Skin>,
+  pub indices: Vec<u32>,
+}
+
+/// Four joint indices and their weights.
+#[derive(Clone, Copy)]
+pub struct Skin {
+  pub indices: [u32; 4],
+  pub weights: [f32; 4],
+}
+
+/// Turns a mesh into a triangle list. Non-indexed meshes get sequential indices, strips are unrolled and meshes
+/// without normals are split into faces with flat normals. Fails for meshes without positions and for lines and points.
+pub fn triangulate(mesh: &Mesh) -> Result<Triangles, String> {
+  let Some(VertexAttributeValues::Float32x3(positions)) = mesh.attribute(Mesh::ATTRIBUTE_POSITION) else {
+    return Err("no Float32x3 positions".to_string());
+  };
+  let positions = positions.iter().copied().map(Vec3::from).collect::<Vec<_>>();
+  let (indices, restart) = match mesh.indices() {
+    Some(Indices::U16(indices)) => (indices.iter().map(|&i| i as u32).collect(), u16::MAX as u32),
+    Some(Indices::U32(indices)) => (indices.clone(), u32::MAX),
+    None => ((0..positions.len() as u32).collect::<Vec<_>>(), u32::MAX),
+  };
+  let triangles = match mesh.primitive_topology() {
+    PrimitiveTopology::TriangleList => indices.chunks_exact(3).map(|tri| [tri[0], tri[1], tri[2]]).collect(),
+    PrimitiveTopology::TriangleStrip => unroll_strip(&indices, restart),
+    topology => return Err(format!("{:?} topology is not ray traced", topology)),
+  };
+  // Indices past the vertices would read other meshes on the GPU
+  let triangles = triangles
+    .into_iter()
+    .filter(|tri| tri.iter().all(|&i| (i as usize) < positions.len()))
+    .collect::<Vec<_>>();
+
+  let joints = skin(mesh, positions.len());
+
+  match mesh.attribute(Mesh::ATTRIBUTE_NORMAL) {
+    Some(VertexAttributeValues::Float32x3(normals)) if normals.len() == positions.len() => Ok(Triangles {
+      normals: normals.iter().copied().map(Vec3::from).collect(),
+      positions,
+      joints,
+      indices: triangles.into_iter().flatten().collect(),
+    }),
+    _ => Ok(flat_shaded(&positions, &joints, &triangles)),
+  }
+}
+
+/// Joints and weights of a skinned mesh, empty when either attribute is missing or doesn't match the vertices.
+fn skin(mesh: &Mesh, len: usize) -> Vec<Skin> {
+  let (Some(VertexAttributeValues::Uint16x4(indices)), Some(VertexAttributeValues::Float32x4(weights))) = (
+    mesh.attribute(Mesh::ATTRIBUTE_JOINT_INDEX),
+    mesh.attribute(Mesh::ATTRIBUTE_JOINT_WEIGHT),
+  ) else {
+    return vec![];
+  };
+  if indices.len() != len || weights.len() != len {
+    return vec![];
+  }
+  indices
+    .iter()
+    .zip(weights)
+    .map(|(indices, weights)| Skin {
+      indices: indices.map(|i| i as u32),
+      weights: *weights,
+    })
+    .collect()
+}
+
+/// Every other triangle of a strip is flipped to keep the winding, a restart index begins a new strip.
+fn unroll_strip(indices: &[u32], restart: u32) -> Vec<[u32; 3]> {
+  indices
+    .split(|&i| i == restart)
+    .flat_map(|strip| {
+      strip.windows(3).enumerate().filter_map(|(n, tri)| {
+        let tri = if n % 2 == 0 {
+          [tri[0], tri[1], tri[2]]
+        } else {
+          [tri[1], tri[0], tri[2]]
+        };
+        // Strips are stitched together with degenerate triangles
+        (tri[0] != tri[1] && tri[1] != tri[2] && tri[0] != tri[2]).then_some(tri)
+      })
+    })
+    .collect()
+}
+
+fn flat_shaded(positions: &[Vec3], joints: &[Skin], triangles: &[[u32; 3]]) -> Triangles {
+  let mut flat = Triangles {
+    positions: Vec::with_capacity(triangles.len() * 3),
+    normals: Vec::with_capacity(triangles.len() * 3),
+    joints: Vec::with_capacity(if joints.is_empty() { 0 } else { triangles.len() * 3 }),
+    indices: (0..triangles.len() as u32 * 3).collect(),
+  };
+  for tri in triangles {
+    let [a, b, c] = tri.map(|i| positions[i as usize]);
+    let normal = (b - a).cross(c - a).normalize_or_zero();
+    flat.positions.extend([a, b, c]);
+    flat.normals.extend([normal; 3]);
+    if !joints.is_empty() {
+      flat.joints.extend(tri.map(|i| joints[i as usize]));
+    }
+  }
+  flat
+}
+
+#[cfg(test)]
+mod tests {
+  use super::{triangulate, Triangles};
+  use bevy::prelude::*;
+  use bevy::render::mesh::{Indices, PrimitiveTopology};
+
+  /// Corners of a unit square in the XY plane, counterclockwise seen from +Z.
+  const SQUARE: [[f32; 3]; 4] = [[0.0, 0.0, 0.0], [1.0, 0.0, 0.0], [0.0, 1.0, 0.0], [1.0, 1.0, 0.0]];
+
+  fn mesh(topology: PrimitiveTopology, positions: &[[f32; 3]], indices: Option<Indices>) -> Mesh {
+    let mut mesh = Mesh::new(topology);
+    mesh.insert_attribute(Mesh::ATTRIBUTE_POSITION, positions.to_vec());
+    mesh.set_indices(indices);
+    mesh
+  }
+
+  fn with_normals(mut mesh: Mesh) -> Mesh {
+    let len = mesh.count_vertices();
+    mesh.insert_attribute(Mesh::ATTRIBUTE_NORMAL, vec![[0.0, 0.0, 1.0]; len]);
+    mesh
+  }
+
+  fn face_normals(triangles: &Triangles) -> Vec<Vec3> {
+    triangles
+      .indices
+      .chunks_exact(3)
+      .map(|tri| {
+        let [a, b, c] = [tri[0], tri[1], tri[2]].map(|i| triangles.positions[i as usize]);
+        (b - a).cross(c - a).normalize()
+      })
+      .collect()
+  }
+
+  #[test]
+  fn synthesizes_indices() {
+    let triangles = triangulate(&with_normals(mesh(PrimitiveTopology::TriangleList, &SQUARE[..3], None))).unwrap();
+    assert_eq!(triangles.indices, [0, 1, 2]);
+    assert_eq!(triangles.positions.len(), 3);
+  }
+
+  #[test]
+  fn flat_normals_without_normals() {
+    let indices = Indices::U32(vec![0, 1, 2, 2, 1, 3]);
+    let triangles = triangulate(&mesh(PrimitiveTopology::TriangleList, &SQUARE, Some(indices))).unwrap();
+    // Every face gets its own vertices
+    assert_eq!(triangles.positions.len(), 6);
+    assert_eq!(triangles.indices, [0, 1, 2, 3, 4, 5]);
+    assert!(triangles.normals.iter().all(|normal| *normal == Vec3::Z));
+  }
+
+  #[test]
+  fn strips_keep_their_winding() {
+    let indices = Indices::U32(vec![0, 1, 2, 3]);
+    let triangles = triangulate(&with_normals(mesh(
+      PrimitiveTopology::TriangleStrip,
+      &SQUARE,
+      Some(indices),
+    )))
+    .unwrap();
+    assert_eq!(triangles.indices, [0, 1, 2, 2, 1, 3]);
+    assert!(face_normals(&triangles).iter().all(|normal| *normal == Vec3::Z));
+  }
+
+  #[test]
+  fn strips_restart() {
+    let positions = [SQUARE, SQUARE.map(|[x, y, _]| [x, y, 1.0])].concat();
+    let indices = Indices::U16(vec![0, 1, 2, u16::MAX, 4, 5, 6]);
+    let triangles = triangulate(&with_normals(mesh(
+      PrimitiveTopology::TriangleStrip,
+      &positions,
+      Some(indices),
+    )))
+    .unwrap();
+    assert_eq!(triangles.indices, [0, 1, 2, 4, 5, 6]);
+  }
+
+  #[test]
+  fn drops_indices_out_of_range() {
+    let indices = Indices::U32(vec![0, 1, 2, 2, 1, 4]);
+    let triangles = triangulate(&with_normals(mesh(
+      PrimitiveTopology::TriangleList,
+      &SQUARE,
+      Some(indices),
+    )))
+    .unwrap();
+    assert_eq!(triangles.indices, [0, 1, 2]);
+  }
+
+  #[test]
+  fn rejects_lines_and_points() {
+    for topology in [
+      PrimitiveTopology::LineList,
+      PrimitiveTopology::LineStrip,
+      PrimitiveTopology::PointList,
+    ] {
+      assert!(triangulate(&mesh(topology, &SQUARE, None)).is_err(), "{:?}", topology);
+    }
+  }
+
+  #[test]
+  fn rejects_meshes_without_positions() {
+    assert!(triangulate(&Mesh::new(PrimitiveTopology::TriangleList)).is_err());
+  }
+}